@@ -1,6 +1,5 @@
 use mini_search_engine::{search::SearchEngine, storage::JsonStorage};
 use std::sync::Arc;
-use tokio;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
@@ -1,6 +1,15 @@
-use crate::search::SearchEngine;
-use anyhow::Result;
+use crate::core::{Collapse, Decay, FieldFilter, Fusion, IndexSettings, SearchOptions, SortSpec};
+use crate::search::{
+    EvaluationReport, Evaluator, IndexCatalog, IngestOptions, Judgment, Metrics, SearchEngine, bulk,
+};
+use crate::storage::{FORMAT_VERSION, JsonStorage, Storage};
+use anyhow::{Context, Result, anyhow};
 use clap::{Parser, Subcommand};
+use futures::stream;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::sync::Arc;
+use uuid::Uuid;
 
 #[derive(Parser)]
 #[command(name = "search-engine")]
@@ -8,6 +17,12 @@ use clap::{Parser, Subcommand};
 pub struct CliApp {
     #[command(subcommand)]
     pub command: Commands,
+    /// Documents indexed between two commits and saves while crawling
+    #[arg(long, global = true, default_value = "1000")]
+    pub batch_size: usize,
+    /// Index or alias to work on; searches accept several, comma-separated
+    #[arg(long, global = true, value_delimiter = ',', default_value = "default")]
+    pub index: Vec<String>,
 }
 
 #[derive(Subcommand)]
@@ -20,12 +35,42 @@ pub enum Commands {
     },
     /// Search for documents
     Search {
-        /// Search query
-        #[arg(short, long)]
+        /// Search query, may be empty when searching by --vector alone
+        #[arg(short, long, default_value = "")]
         query: String,
         /// Maximum number of results
         #[arg(short, long, default_value = "10")]
         limit: usize,
+        /// Fields to count across all matches (tag, domain, extension, year, ...)
+        #[arg(long, value_delimiter = ',')]
+        facets: Vec<String>,
+        /// Only keep results matching field:value (repeatable, comma-separated)
+        #[arg(long)]
+        filter: Vec<String>,
+        /// Sort order instead of relevance, e.g. "date:desc,score:desc" or "title:asc"
+        #[arg(long)]
+        sort: Option<String>,
+        /// Show how each result's score was computed
+        #[arg(long)]
+        explain: bool,
+        /// Favor recent documents, e.g. "exp:30d" or "gauss:1y:30d" (function:scale[:offset[:decay]])
+        #[arg(long)]
+        decay: Option<String>,
+        /// Query embedding for vector or hybrid search, comma-separated
+        #[arg(long, value_delimiter = ',', allow_negative_numbers = true)]
+        vector: Vec<f32>,
+        /// How text and vector hits are combined: rrf, rrf:<k> or weighted:<alpha>
+        #[arg(long)]
+        fusion: Option<String>,
+        /// Show only the best copy of each group of near-duplicates
+        #[arg(long)]
+        collapse_duplicates: bool,
+        /// Keep only the best hits per group: host, dir, dir/<depth> or a field, e.g. "host:2"
+        #[arg(long)]
+        collapse: Option<String>,
+        /// Diversify the top results (MMR), 1.0 = pure relevance, 0.0 = maximal novelty
+        #[arg(long)]
+        diversify: Option<f64>,
     },
     /// Clear the search index
     Clear,
@@ -54,26 +99,245 @@ pub enum Commands {
 
     /// List all indexed documents
     List,
+
+    /// Write every document as newline-delimited JSON
+    Export {
+        /// File to write, standard output if omitted
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+
+    /// Index documents from newline-delimited JSON, replacing those with the same id
+    Import {
+        /// File to read, standard input if omitted
+        #[arg(short, long)]
+        input: Option<String>,
+    },
+
+    /// Find documents similar to an indexed one
+    Similar {
+        /// Id, path or URL of the source document
+        #[arg(short, long)]
+        document: String,
+        /// Maximum number of results
+        #[arg(short, long, default_value = "5")]
+        limit: usize,
+    },
+
+    /// Show the near-duplicate group of an indexed document
+    Duplicates {
+        /// Id, path or URL of the document
+        #[arg(short, long)]
+        document: String,
+    },
+
+    /// Give a document a static score boost, e.g. to pin an official page (1.0 removes it)
+    Pin {
+        /// Path or URL of the indexed document
+        #[arg(short, long)]
+        path: String,
+        /// Score multiplier
+        #[arg(short, long, default_value = "2.0")]
+        boost: f64,
+    },
+
+    /// List all indexes
+    Indexes,
+
+    /// Create an empty named index
+    CreateIndex {
+        /// Name of the index: lowercase letters, digits, '-' and '_'
+        #[arg(short, long)]
+        name: String,
+        /// Settings file with the index's analyzer and scoring configuration
+        #[arg(long)]
+        config: Option<String>,
+        /// Number of shards the documents are split into, fixed once the index is created
+        #[arg(long, default_value_t = 1)]
+        shards: usize,
+    },
+
+    /// Delete a named index and its documents
+    DropIndex {
+        /// Name of the index
+        #[arg(short, long)]
+        name: String,
+    },
+
+    /// Point an alias at one or more indexes, atomically replacing its previous indexes
+    Alias {
+        /// Name of the alias
+        #[arg(short, long)]
+        name: String,
+        /// Indexes the alias stands for, comma-separated
+        #[arg(short, long, value_delimiter = ',', required = true)]
+        indexes: Vec<String>,
+    },
+
+    /// List all aliases
+    Aliases,
+
+    /// Delete an alias, leaving its indexes untouched
+    RemoveAlias {
+        /// Name of the alias
+        #[arg(short, long)]
+        name: String,
+    },
+
+    /// Upgrade every index stored in an older format version
+    Migrate {
+        /// JSON index file to upgrade instead of the indexes of the database
+        #[arg(long)]
+        file: Option<String>,
+    },
+
+    /// Create, list, restore or delete snapshots of indexes
+    Snapshot {
+        #[command(subcommand)]
+        action: SnapshotAction,
+    },
+
+    /// Write the index as a memory-mapped file, served with `server <port> --mapped`
+    Compact {
+        /// Path of the mapped index file
+        #[arg(short, long, default_value = "data/index/search_index.mmap")]
+        output: String,
+    },
+
+    /// Measure ranking quality against graded relevance judgments
+    Evaluate {
+        /// JSON file of queries with graded relevant paths/URLs
+        #[arg(short, long)]
+        judgments: String,
+        /// Rank cut-off for all metrics
+        #[arg(short, long, default_value = "10")]
+        k: usize,
+        /// Settings file to evaluate instead of the current index settings
+        #[arg(long)]
+        config: Option<String>,
+        /// Second settings file to compare against the first configuration
+        #[arg(long)]
+        compare: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum SnapshotAction {
+    /// Snapshot the index named by --index; it keeps serving meanwhile
+    Create {
+        /// Name of the snapshot, `<index>-<time>` by default
+        #[arg(short, long)]
+        name: Option<String>,
+    },
+
+    /// List all snapshots
+    List,
+
+    /// Restore a snapshot into the index it was taken of
+    Restore {
+        /// Name of the snapshot
+        #[arg(short, long)]
+        name: String,
+        /// Index to restore into instead, created if it does not exist
+        #[arg(long)]
+        into: Option<String>,
+        /// JSON index file to restore into instead of the database
+        #[arg(long, conflicts_with = "into")]
+        file: Option<String>,
+    },
+
+    /// Delete a snapshot
+    Delete {
+        /// Name of the snapshot
+        #[arg(short, long)]
+        name: String,
+    },
 }
 
 impl CliApp {
-    pub async fn run(self, engine: &SearchEngine) -> Result<()> {
-        match self.command {
-            Commands::Index { directory } => {
-                println!("Indexing documents from: {}", directory);
-                let count = engine.index_directory(&directory).await?;
-                engine.save_index().await?;
-                println!("Successfully indexed {} documents", count);
-            }
+    /// Batching of the index commands, printing a line every second while pages arrive and
+    /// after every committed batch.
+    pub fn ingest_options(&self) -> IngestOptions {
+        IngestOptions::new()
+            .with_batch_size(self.batch_size)
+            .with_progress(|progress| {
+                if progress.crawled > 0 {
+                    println!(
+                        "  {} documents crawled, {} indexed ({} batches, {:.1}s, {:.0} docs/sec)",
+                        progress.crawled,
+                        progress.indexed,
+                        progress.batches,
+                        progress.elapsed_secs,
+                        progress.docs_per_sec
+                    );
+                }
+            })
+    }
 
-            Commands::Search { query, limit } => {
+    pub async fn run(self, catalog: &IndexCatalog) -> Result<()> {
+        let indexes = self.index;
+        match self.command {
+            Commands::Search {
+                query,
+                limit,
+                facets,
+                filter,
+                sort,
+                explain,
+                decay,
+                vector,
+                fusion,
+                collapse_duplicates,
+                collapse,
+                diversify,
+            } => {
                 println!("Searching for: '{}'", query);
-                let results = engine.search(&query, Some(limit)).await?;
+                let filters = filter
+                    .iter()
+                    .map(|spec| FieldFilter::parse_list(spec))
+                    .collect::<Result<Vec<_>>>()?
+                    .concat();
+                let mut options = SearchOptions::new()
+                    .with_limit(limit)
+                    .with_facets(facets)
+                    .with_filters(filters)
+                    .with_explain(explain)
+                    .with_collapse_duplicates(collapse_duplicates);
+                if let Some(sort) = sort {
+                    options = options.with_sort(SortSpec::parse(&sort)?);
+                }
+                if let Some(decay) = decay {
+                    options = options.with_decay(Decay::parse(&decay)?);
+                }
+                if !vector.is_empty() {
+                    options = options.with_vector(vector);
+                }
+                if let Some(fusion) = fusion {
+                    options = options.with_fusion(Fusion::parse(&fusion)?);
+                }
+                if let Some(collapse) = collapse {
+                    options = options.with_collapse(Collapse::parse(&collapse)?);
+                }
+                if let Some(lambda) = diversify {
+                    if !(0.0..=1.0).contains(&lambda) {
+                        return Err(anyhow!(
+                            "--diversify must be between 0 and 1, got {}",
+                            lambda
+                        ));
+                    }
+                    options = options.with_diversify(lambda);
+                }
+                let search_results = catalog.search(&indexes, &query, &options).await?;
+                let results = &search_results.results;
 
                 if results.is_empty() {
                     println!("No results found");
                 } else {
-                    println!("Found {} results:\n", results.len());
+                    println!(
+                        "Found {} results (showing {}):\n",
+                        search_results.total_hits,
+                        results.len()
+                    );
 
                     for (i, result) in results.iter().enumerate() {
                         println!(
@@ -83,10 +347,206 @@ impl CliApp {
                             result.score
                         );
                         println!("   Path: {}", result.document.path);
+                        if let Some(index) = &result.index {
+                            println!("   Index: {}", index);
+                        }
+                        if let Some(heading) =
+                            result.passage.as_ref().and_then(|p| p.heading.as_ref())
+                        {
+                            println!("   Section: {} ({})", heading, result.url());
+                        }
                         println!("   Snippet: {}", result.snippet);
+                        if result.duplicates > 0 {
+                            println!("   Near-duplicates: {}", result.duplicates);
+                        }
+                        if let Some(group) = &result.group
+                            && result.more_in_group > 0
+                        {
+                            println!("   More from {}: {}", group, result.more_in_group);
+                        }
+                        if let Some(explanation) = &result.explanation {
+                            println!("   Explanation:");
+                            for line in explanation.to_string().lines() {
+                                println!("     {}", line);
+                            }
+                        }
                         println!();
                     }
                 }
+
+                for facet in &search_results.facets {
+                    println!("Facet '{}':", facet.field);
+                    for value in &facet.values {
+                        println!("   {} ({})", value.value, value.count);
+                    }
+                }
+            }
+
+            Commands::Indexes => {
+                let summaries = catalog.list().await?;
+                println!("Indexes ({} total):", summaries.len());
+                for summary in summaries {
+                    print!(
+                        "  {:<20} {:>8} documents {:>8} terms",
+                        summary.name, summary.documents, summary.terms
+                    );
+                    if summary.shards > 1 {
+                        print!("  ({} shards)", summary.shards);
+                    }
+                    if !summary.aliases.is_empty() {
+                        print!("  (aliases: {})", summary.aliases.join(", "));
+                    }
+                    println!();
+                }
+            }
+
+            Commands::CreateIndex {
+                name,
+                config,
+                shards,
+            } => {
+                let settings = match &config {
+                    Some(path) => IndexSettings::from_file(path)?,
+                    None => IndexSettings::default(),
+                };
+                catalog.create(&name, settings, shards).await?;
+                println!("Created index '{}' with {} shards", name, shards);
+            }
+
+            Commands::DropIndex { name } => {
+                catalog.drop_index(&name).await?;
+                println!("Dropped index '{}'", name);
+            }
+
+            Commands::Alias { name, indexes } => {
+                catalog.set_alias(&name, indexes.clone()).await?;
+                println!("Alias '{}' now points at {}", name, indexes.join(", "));
+            }
+
+            Commands::Aliases => {
+                let aliases = catalog.aliases().await;
+                println!("Aliases ({} total):", aliases.len());
+                for (alias, indexes) in aliases {
+                    println!("  {:<20} -> {}", alias, indexes.join(", "));
+                }
+            }
+
+            Commands::RemoveAlias { name } => {
+                catalog.remove_alias(&name).await?;
+                println!("Removed alias '{}'", name);
+            }
+
+            Commands::Migrate { file } => {
+                let migrated = match file {
+                    Some(path) => vec![(path.clone(), JsonStorage::new(path).migrate()?)],
+                    None => {
+                        let mut migrated = Vec::new();
+                        for name in catalog.names() {
+                            let version = catalog.migrate(&name).await?;
+                            migrated.push((name, version));
+                        }
+                        migrated
+                    }
+                };
+                for (name, version) in migrated {
+                    match version {
+                        None => println!("  {:<20} empty", name),
+                        Some(version) if version < FORMAT_VERSION => println!(
+                            "  {:<20} migrated from format {} to {}",
+                            name, version, FORMAT_VERSION
+                        ),
+                        Some(_) => {
+                            println!("  {:<20} up to date (format {})", name, FORMAT_VERSION)
+                        }
+                    }
+                }
+            }
+
+            Commands::Snapshot { action } => Self::run_snapshot(action, &indexes, catalog).await?,
+
+            command => {
+                let [name] = &indexes[..] else {
+                    return Err(anyhow!("This command works on a single --index"));
+                };
+                Self::run_on_index(command, &*catalog.get(name).await?).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn run_snapshot(
+        action: SnapshotAction,
+        indexes: &[String],
+        catalog: &IndexCatalog,
+    ) -> Result<()> {
+        match action {
+            SnapshotAction::Create { name } => {
+                let [index] = indexes else {
+                    return Err(anyhow!("Snapshots are taken of a single --index"));
+                };
+                let info = catalog.create_snapshot(index, name.as_deref()).await?;
+                println!(
+                    "Created snapshot '{}' of index '{}': {} documents, {} bytes",
+                    info.name,
+                    info.index,
+                    info.documents,
+                    info.size.unwrap_or(0)
+                );
+            }
+
+            SnapshotAction::List => {
+                let snapshots = catalog.snapshots().list()?;
+                println!("Snapshots ({} total):", snapshots.len());
+                for info in snapshots {
+                    let created = chrono::DateTime::from_timestamp(info.created, 0)
+                        .map(|time| time.format("%Y-%m-%d %H:%M:%S").to_string())
+                        .unwrap_or_default();
+                    println!(
+                        "  {:<32} {:<20} {}  {:>8} documents {:>10} bytes",
+                        info.name,
+                        info.index,
+                        created,
+                        info.documents,
+                        info.size.unwrap_or(0)
+                    );
+                }
+            }
+
+            SnapshotAction::Restore { name, into, file } => {
+                let (info, target) = match file {
+                    Some(path) => {
+                        let storage: Arc<dyn Storage> = Arc::new(JsonStorage::new(path.clone()));
+                        (catalog.snapshots().restore_into(&name, &[storage])?, path)
+                    }
+                    None => {
+                        let info = catalog.restore_snapshot(&name, into.as_deref()).await?;
+                        let target = into.unwrap_or_else(|| info.index.clone());
+                        (info, format!("index '{}'", target))
+                    }
+                };
+                println!(
+                    "Restored snapshot '{}' with {} documents into {}",
+                    info.name, info.documents, target
+                );
+            }
+
+            SnapshotAction::Delete { name } => {
+                catalog.snapshots().delete(&name)?;
+                println!("Deleted snapshot '{}'", name);
+            }
+        }
+        Ok(())
+    }
+
+    /// Commands working on a single index, the one named by `--index`.
+    async fn run_on_index(command: Commands, engine: &SearchEngine) -> Result<()> {
+        match command {
+            Commands::Index { directory } => {
+                println!("Indexing documents from: {}", directory);
+                let count = engine.index_directory(&directory).await?;
+                engine.save_index().await?;
+                println!("Successfully indexed {} documents", count);
             }
 
             Commands::Clear => {
@@ -119,12 +579,64 @@ impl CliApp {
                 println!("Successfully indexed {} documents from website", count);
             }
 
+            Commands::Similar { document, limit } => {
+                let Some(doc_id) = Self::resolve_document(engine, &document).await else {
+                    println!("No indexed document with path {}", document);
+                    return Ok(());
+                };
+
+                let results = engine.similar(&doc_id, limit).await?;
+                if results.is_empty() {
+                    println!("No similar documents found");
+                } else {
+                    println!("Documents similar to {}:\n", document);
+                    for (i, result) in results.iter().enumerate() {
+                        println!(
+                            "{}. {} (Score: {:.4})",
+                            i + 1,
+                            result.document.title,
+                            result.score
+                        );
+                        println!("   Path: {}", result.document.path);
+                        println!();
+                    }
+                }
+            }
+
+            Commands::Duplicates { document } => {
+                let Some(doc_id) = Self::resolve_document(engine, &document).await else {
+                    println!("No indexed document with path {}", document);
+                    return Ok(());
+                };
+
+                if let Some((canonical, duplicates)) = engine.duplicates(&doc_id).await {
+                    println!("Canonical: {} ({})", canonical.title, canonical.path);
+                    if duplicates.is_empty() {
+                        println!("No near-duplicates");
+                    }
+                    for duplicate in duplicates {
+                        println!("   Duplicate: {} ({})", duplicate.title, duplicate.path);
+                    }
+                }
+            }
+
+            Commands::Pin { path, boost } => {
+                if engine.set_boost(&path, boost).await? {
+                    engine.commit().await;
+                    engine.save_index().await?;
+                    println!("Boost of {} set to {}", path, boost);
+                } else {
+                    println!("No indexed document with path {}", path);
+                }
+            }
+
             Commands::List => {
                 let documents = engine.list_all_documents().await;
                 println!("Indexed Documents ({} total):\n", documents.len());
 
                 for (i, doc) in documents.iter().enumerate() {
                     println!("{}. Title: {}", i + 1, doc.title);
+                    println!("   ID: {}", doc.id);
                     println!("   URL: {}", doc.path);
                     println!("   Word count: {}", doc.word_count);
                     println!(
@@ -133,8 +645,137 @@ impl CliApp {
                     );
                 }
             }
+
+            Commands::Export { output } => {
+                let documents = engine.committed_documents().await?;
+                let count = match &output {
+                    Some(path) => {
+                        let file = File::create(path)
+                            .with_context(|| format!("Failed to create {}", path))?;
+                        bulk::write_documents(documents, BufWriter::new(file))?
+                    }
+                    None => bulk::write_documents(documents, std::io::stdout().lock())?,
+                };
+                if let Some(path) = output {
+                    println!("Exported {} documents to {}", count, path);
+                }
+            }
+
+            Commands::Import { input } => {
+                let documents: Box<dyn Iterator<Item = Result<_>>> = match &input {
+                    Some(path) => {
+                        let file =
+                            File::open(path).with_context(|| format!("Failed to open {}", path))?;
+                        Box::new(bulk::read_documents(BufReader::new(file)))
+                    }
+                    None => Box::new(bulk::read_documents(std::io::stdin().lock())),
+                };
+                // Documents before a malformed line are kept
+                let imported = engine.import(stream::iter(documents)).await;
+                engine.save_index().await?;
+                let progress = imported?;
+                println!(
+                    "Imported {} documents ({} skipped as near-duplicates, {} replacing one with the same id)",
+                    progress.indexed, progress.skipped, progress.replaced
+                );
+            }
+
+            Commands::Compact { output } => {
+                engine.write_mapped(&output).await?;
+                let (doc_count, term_count) = engine.get_stats().await;
+                println!(
+                    "Wrote mapped index with {} documents and {} terms to {}",
+                    doc_count, term_count, output
+                );
+            }
+
+            Commands::Search { .. }
+            | Commands::Indexes
+            | Commands::CreateIndex { .. }
+            | Commands::DropIndex { .. }
+            | Commands::Alias { .. }
+            | Commands::Aliases
+            | Commands::RemoveAlias { .. }
+            | Commands::Migrate { .. }
+            | Commands::Snapshot { .. } => unreachable!("handled by run"),
+
+            Commands::Evaluate {
+                judgments,
+                k,
+                config,
+                compare,
+            } => {
+                let judgments = Judgment::load(&judgments)?;
+                println!("Evaluating {} queries at k={}", judgments.len(), k);
+
+                let baseline = match &config {
+                    Some(path) => {
+                        let settings = IndexSettings::from_file(path)?;
+                        let engine = engine.reindexed(settings).await?;
+                        Evaluator::evaluate(&engine, &judgments, k).await?
+                    }
+                    None => Evaluator::evaluate(engine, &judgments, k).await?,
+                };
+
+                println!(
+                    "\nBaseline ({}):",
+                    config.as_deref().unwrap_or("current index")
+                );
+                Self::print_report(&baseline);
+
+                if let Some(path) = compare {
+                    let settings = IndexSettings::from_file(&path)?;
+                    let candidate_engine = engine.reindexed(settings).await?;
+                    let candidate = Evaluator::evaluate(&candidate_engine, &judgments, k).await?;
+
+                    println!("\nCandidate ({}):", path);
+                    Self::print_report(&candidate);
+
+                    println!("\nDifference (candidate - baseline):");
+                    Self::print_header();
+                    for (base, cand) in baseline.queries.iter().zip(&candidate.queries) {
+                        Self::print_row(&base.query, &cand.metrics.delta(&base.metrics));
+                    }
+                    Self::print_row("MEAN", &candidate.mean.delta(&baseline.mean));
+                }
+            }
         }
 
         Ok(())
     }
+
+    /// Accepts a document id or the path/URL it was indexed under.
+    async fn resolve_document(engine: &SearchEngine, document: &str) -> Option<Uuid> {
+        match Uuid::parse_str(document) {
+            Ok(doc_id) => Some(doc_id),
+            Err(_) => engine.document_id(document).await,
+        }
+    }
+
+    fn print_report(report: &EvaluationReport) {
+        Self::print_header();
+        for query in &report.queries {
+            Self::print_row(&query.query, &query.metrics);
+        }
+        Self::print_row("MEAN", &report.mean);
+    }
+
+    fn print_header() {
+        println!(
+            "  {:<30} {:>8} {:>8} {:>8} {:>8} {:>8}",
+            "query", "P@k", "R@k", "MRR", "MAP", "nDCG"
+        );
+    }
+
+    fn print_row(label: &str, metrics: &Metrics) {
+        println!(
+            "  {:<30} {:>8.4} {:>8.4} {:>8.4} {:>8.4} {:>8.4}",
+            label.chars().take(30).collect::<String>(),
+            metrics.precision,
+            metrics.recall,
+            metrics.reciprocal_rank,
+            metrics.average_precision,
+            metrics.ndcg
+        );
+    }
 }
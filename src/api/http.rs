@@ -1,13 +1,103 @@
-use crate::search::SearchEngine;
+use crate::core::ranking::SearchResult;
+use crate::core::{
+    Collapse, Decay, Document, Explanation, Facet, FieldFilter, Fusion, IndexSettings,
+    SearchOptions, SortSpec,
+};
+use crate::search::{IndexCatalog, IngestProgress, SearchEngine, bulk};
 use crate::web::SEARCH_PAGE_HTML;
+use futures::stream::{self, Stream, StreamExt};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::sync::Arc;
+use uuid::Uuid;
 use warp::Filter;
+use warp::hyper::body::{Body, Buf};
 
 #[derive(Deserialize)]
 struct SearchQuery {
+    /// May be empty for vector-only queries
+    #[serde(default)]
     q: String,
     limit: Option<usize>,
+    /// Comma-separated fields to aggregate, e.g. `tag,domain`
+    facets: Option<String>,
+    /// Comma-separated `field:value` pairs, e.g. `tag:rust,year:2024`
+    filter: Option<String>,
+    /// Comma-separated `field:order` pairs, e.g. `date:desc,score:desc`
+    sort: Option<String>,
+    /// Include a per-result score breakdown
+    explain: Option<bool>,
+    /// Freshness decay as `function:scale[:offset[:decay]]`, e.g. `exp:30d`
+    decay: Option<String>,
+    /// Comma-separated query embedding for vector or hybrid search
+    vector: Option<String>,
+    /// `rrf`, `rrf:<k>` or `weighted:<alpha>`
+    fusion: Option<String>,
+    /// Set to `true` to show only the best copy of each group of near-duplicates
+    collapse_duplicates: Option<bool>,
+    /// Best hits kept per group as `key[:size]`, e.g. `host:2` or `dir/1`
+    collapse: Option<String>,
+    /// MMR trade-off between relevance (1.0) and novelty (0.0)
+    diversify: Option<f64>,
+}
+
+impl SearchQuery {
+    fn to_options(&self) -> Result<SearchOptions, warp::Rejection> {
+        let mut options = SearchOptions::new()
+            .with_limit(self.limit.unwrap_or(10))
+            .with_explain(self.explain.unwrap_or(false))
+            .with_collapse_duplicates(self.collapse_duplicates.unwrap_or(false));
+
+        if let Some(facets) = &self.facets {
+            options = options.with_facets(facets.split(',').map(String::from).collect());
+        }
+
+        if let Some(filter) = &self.filter {
+            let filters = FieldFilter::parse_list(filter).map_err(invalid)?;
+            options = options.with_filters(filters);
+        }
+
+        if let Some(sort) = &self.sort {
+            let sort = SortSpec::parse(sort).map_err(invalid)?;
+            options = options.with_sort(sort);
+        }
+
+        if let Some(decay) = &self.decay {
+            let decay = Decay::parse(decay).map_err(invalid)?;
+            options = options.with_decay(decay);
+        }
+
+        if let Some(vector) = &self.vector {
+            let vector = vector
+                .split(',')
+                .map(|v| v.trim().parse::<f32>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| invalid(anyhow::anyhow!("Invalid vector '{}'", vector)))?;
+            options = options.with_vector(vector);
+        }
+
+        if let Some(fusion) = &self.fusion {
+            let fusion = Fusion::parse(fusion).map_err(invalid)?;
+            options = options.with_fusion(fusion);
+        }
+
+        if let Some(collapse) = &self.collapse {
+            let collapse = Collapse::parse(collapse).map_err(invalid)?;
+            options = options.with_collapse(collapse);
+        }
+
+        if let Some(lambda) = self.diversify {
+            if !(0.0..=1.0).contains(&lambda) {
+                return Err(invalid(anyhow::anyhow!(
+                    "Diversify must be between 0 and 1, got {}",
+                    lambda
+                )));
+            }
+            options = options.with_diversify(lambda);
+        }
+
+        Ok(options)
+    }
 }
 
 #[derive(Serialize)]
@@ -15,27 +105,112 @@ struct SearchResponse {
     query: String,
     results: Vec<SearchResultJson>,
     total: usize,
+    total_hits: usize,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    facets: Vec<Facet>,
+}
+
+#[derive(Deserialize)]
+struct SimilarQuery {
+    limit: Option<usize>,
+}
+
+#[derive(Serialize)]
+struct SimilarResponse {
+    id: Uuid,
+    results: Vec<SearchResultJson>,
 }
 
 #[derive(Serialize)]
 struct SearchResultJson {
+    id: Uuid,
     title: String,
     path: String,
+    /// Path with the anchor of the best passage, e.g. `guide.html#installation`
+    url: String,
+    /// Heading of the best passage of a document ranked by passages
+    #[serde(skip_serializing_if = "Option::is_none")]
+    section: Option<String>,
     score: f64,
     snippet: String,
+    /// Near-duplicates of this document, see `/documents/{id}/duplicates`
+    duplicates: usize,
+    /// Collapse group, e.g. the host for `collapse=host`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    group: Option<String>,
+    /// Further hits from the same group left out by collapsing
+    #[serde(skip_serializing_if = "Option::is_none")]
+    more_in_group: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    explanation: Option<Explanation>,
+    /// Index of the hit when several indexes were searched
+    #[serde(skip_serializing_if = "Option::is_none")]
+    index: Option<String>,
+}
+
+impl From<SearchResult> for SearchResultJson {
+    fn from(result: SearchResult) -> Self {
+        Self {
+            url: result.url(),
+            section: result
+                .passage
+                .as_ref()
+                .and_then(|passage| passage.heading.clone()),
+            id: result.document.id,
+            title: result.document.title,
+            path: result.document.path,
+            score: result.score,
+            snippet: result.snippet,
+            duplicates: result.duplicates,
+            more_in_group: result.group.is_some().then_some(result.more_in_group),
+            group: result.group,
+            explanation: result.explanation,
+            index: result.index,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct DocumentSummary {
+    id: Uuid,
+    title: String,
+    path: String,
+}
+
+impl From<Document> for DocumentSummary {
+    fn from(document: Document) -> Self {
+        Self {
+            id: document.id,
+            title: document.title,
+            path: document.path,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct DuplicatesResponse {
+    id: Uuid,
+    canonical: DocumentSummary,
+    duplicates: Vec<DocumentSummary>,
 }
 
 #[derive(Serialize)]
 struct StatsResponse {
     documents: usize,
     terms: usize,
+    /// Generations committed since the server started
+    generation: u64,
+    /// Progress of a crawl being indexed, absent when none is running
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ingest: Option<IngestProgress>,
 }
 
 pub struct HttpServer;
 
 impl HttpServer {
-    pub async fn run(engine: Arc<SearchEngine>, port: u16) {
-        let search_engine = Arc::clone(&engine);
+    /// Serves the default index on the top-level routes and every index under `/indexes`.
+    pub async fn run(catalog: Arc<IndexCatalog>, port: u16) -> anyhow::Result<()> {
+        let search_engine = catalog.get(IndexCatalog::DEFAULT_INDEX).await?;
 
         // Serve the main search page at root
         let homepage = warp::path::end().map(|| warp::reply::html(SEARCH_PAGE_HTML));
@@ -47,6 +222,35 @@ impl HttpServer {
             .and(with_engine(Arc::clone(&search_engine)))
             .and_then(handle_search);
 
+        let similar = warp::path!("documents" / Uuid / "similar")
+            .and(warp::get())
+            .and(warp::query::<SimilarQuery>())
+            .and(with_engine(Arc::clone(&search_engine)))
+            .and_then(handle_similar);
+
+        let duplicates = warp::path!("documents" / Uuid / "duplicates")
+            .and(warp::get())
+            .and(with_engine(Arc::clone(&search_engine)))
+            .and_then(handle_duplicates);
+
+        let add_document = warp::path("documents")
+            .and(warp::path::end())
+            .and(warp::post())
+            .and(warp::body::json())
+            .and(with_engine(Arc::clone(&search_engine)))
+            .and_then(handle_add_document);
+
+        let export_documents = warp::path!("documents" / "_bulk")
+            .and(warp::get())
+            .and(with_engine(Arc::clone(&search_engine)))
+            .and_then(handle_export_documents);
+
+        let import_documents = warp::path!("documents" / "_bulk")
+            .and(warp::post())
+            .and(warp::body::stream())
+            .and(with_engine(Arc::clone(&search_engine)))
+            .and_then(handle_import_documents);
+
         let status = warp::path("status")
             .and(warp::get())
             .and(with_engine(Arc::clone(&search_engine)))
@@ -75,19 +279,168 @@ impl HttpServer {
             .and(with_engine(Arc::clone(&search_engine)))
             .and_then(handle_index_site);
 
+        let list_indexes = warp::path!("indexes")
+            .and(warp::get())
+            .and(with_catalog(Arc::clone(&catalog)))
+            .and_then(handle_list_indexes);
+
+        let create_index = warp::path!("indexes" / String)
+            .and(warp::post())
+            .and(warp::query::<CreateIndexQuery>())
+            .and(warp::body::json())
+            .and(with_catalog(Arc::clone(&catalog)))
+            .and_then(handle_create_index);
+
+        let drop_index = warp::path!("indexes" / String)
+            .and(warp::delete())
+            .and(with_catalog(Arc::clone(&catalog)))
+            .and_then(handle_drop_index);
+
+        let index_search = warp::path!("indexes" / String / "search")
+            .and(warp::get())
+            .and(warp::query::<SearchQuery>())
+            .and(with_catalog(Arc::clone(&catalog)))
+            .and_then(handle_index_search);
+
+        let index_add_document = warp::path!("indexes" / String / "documents")
+            .and(warp::post())
+            .and(warp::body::json())
+            .and(with_catalog(Arc::clone(&catalog)))
+            .and_then(
+                |name: String, request, catalog: Arc<IndexCatalog>| async move {
+                    handle_add_document(request, resolve_index(&catalog, &name).await?).await
+                },
+            );
+
+        let index_export_documents = warp::path!("indexes" / String / "documents" / "_bulk")
+            .and(warp::get())
+            .and(with_catalog(Arc::clone(&catalog)))
+            .and_then(|name: String, catalog: Arc<IndexCatalog>| async move {
+                handle_export_documents(resolve_index(&catalog, &name).await?).await
+            });
+
+        let index_import_documents = warp::path!("indexes" / String / "documents" / "_bulk")
+            .and(warp::post())
+            .and(warp::body::stream())
+            .and(with_catalog(Arc::clone(&catalog)))
+            .and_then(
+                |name: String, body, catalog: Arc<IndexCatalog>| async move {
+                    handle_import_documents(body, resolve_index(&catalog, &name).await?).await
+                },
+            );
+
+        let index_stats = warp::path!("indexes" / String / "stats")
+            .and(warp::get())
+            .and(with_catalog(Arc::clone(&catalog)))
+            .and_then(|name: String, catalog: Arc<IndexCatalog>| async move {
+                handle_stats(resolve_index(&catalog, &name).await?).await
+            });
+
+        let index_directory = warp::path!("indexes" / String / "index")
+            .and(warp::post())
+            .and(warp::body::json())
+            .and(with_catalog(Arc::clone(&catalog)))
+            .and_then(
+                |name: String, request, catalog: Arc<IndexCatalog>| async move {
+                    handle_index(request, resolve_index(&catalog, &name).await?).await
+                },
+            );
+
+        let index_web_into = warp::path!("indexes" / String / "index-web")
+            .and(warp::post())
+            .and(warp::body::json())
+            .and(with_catalog(Arc::clone(&catalog)))
+            .and_then(
+                |name: String, request, catalog: Arc<IndexCatalog>| async move {
+                    handle_index_web(request, resolve_index(&catalog, &name).await?).await
+                },
+            );
+
+        let index_site_into = warp::path!("indexes" / String / "index-site")
+            .and(warp::post())
+            .and(warp::body::json())
+            .and(with_catalog(Arc::clone(&catalog)))
+            .and_then(
+                |name: String, request, catalog: Arc<IndexCatalog>| async move {
+                    handle_index_site(request, resolve_index(&catalog, &name).await?).await
+                },
+            );
+
+        let list_aliases = warp::path!("aliases")
+            .and(warp::get())
+            .and(with_catalog(Arc::clone(&catalog)))
+            .and_then(handle_list_aliases);
+
+        let set_alias = warp::path!("aliases" / String)
+            .and(warp::put())
+            .and(warp::body::json())
+            .and(with_catalog(Arc::clone(&catalog)))
+            .and_then(handle_set_alias);
+
+        let remove_alias = warp::path!("aliases" / String)
+            .and(warp::delete())
+            .and(with_catalog(Arc::clone(&catalog)))
+            .and_then(handle_remove_alias);
+
+        let list_snapshots = warp::path!("snapshots")
+            .and(warp::get())
+            .and(with_catalog(Arc::clone(&catalog)))
+            .and_then(handle_list_snapshots);
+
+        let create_snapshot = warp::path!("snapshots")
+            .and(warp::post())
+            .and(warp::query::<SnapshotQuery>())
+            .and(with_catalog(Arc::clone(&catalog)))
+            .and_then(handle_create_snapshot);
+
+        let restore_snapshot = warp::path!("snapshots" / String / "restore")
+            .and(warp::post())
+            .and(warp::query::<SnapshotQuery>())
+            .and(with_catalog(Arc::clone(&catalog)))
+            .and_then(handle_restore_snapshot);
+
+        let delete_snapshot = warp::path!("snapshots" / String)
+            .and(warp::delete())
+            .and(with_catalog(Arc::clone(&catalog)))
+            .and_then(handle_delete_snapshot);
+
         let cors = warp::cors()
             .allow_any_origin()
             .allow_headers(vec!["content-type"])
-            .allow_methods(vec!["GET", "POST"]);
+            .allow_methods(vec!["GET", "POST", "PUT", "DELETE"]);
 
         // Combine all routes - homepage first, then API routes
         let routes = homepage
             .or(status)
             .or(search)
+            .or(similar)
+            .or(duplicates)
+            .or(add_document)
+            .or(export_documents)
+            .or(import_documents)
             .or(stats)
             .or(index)
             .or(index_web)
             .or(index_site)
+            .or(list_indexes)
+            .or(create_index)
+            .or(drop_index)
+            .or(index_search)
+            .or(index_add_document)
+            .or(index_export_documents)
+            .or(index_import_documents)
+            .or(index_stats)
+            .or(index_directory)
+            .or(index_web_into)
+            .or(index_site_into)
+            .or(list_aliases)
+            .or(set_alias)
+            .or(remove_alias)
+            .or(list_snapshots)
+            .or(create_snapshot)
+            .or(restore_snapshot)
+            .or(delete_snapshot)
+            .recover(handle_rejection)
             .with(cors)
             .with(warp::log("search_engine"));
 
@@ -95,14 +448,38 @@ impl HttpServer {
         println!("📍 Address: http://localhost:{}", port);
         println!("🌐 Web Interface: http://localhost:{}/", port);
         println!("🔧 API Endpoints:");
-        println!("   GET  /search?q=<query>&limit=<limit>");
+        println!(
+            "   GET  /search?q=<query>&limit=<limit>&facets=<fields>&filter=<field:value>&sort=<field:order>&decay=<function:scale>&vector=<floats>&fusion=<rrf|weighted:alpha>"
+        );
+        println!("   GET  /documents/<id>/similar?limit=<limit>");
+        println!("   GET  /documents/<id>/duplicates");
+        println!(
+            "   POST /documents {{\"title\": \"...\", \"content\": \"...\", \"path\": \"...\", \"embedding\": [0.1, 0.2]}}"
+        );
+        println!("   GET  /documents/_bulk, POST /documents/_bulk (newline-delimited JSON)");
         println!("   GET  /stats");
         println!("   POST /index {{\"directory\": \"/path/to/docs\"}}");
         println!("   POST /index-web {{\"urls\": [\"url1\", \"url2\"], \"max_pages\": 50}}");
         println!("   POST /index-site {{\"url\": \"https://example.com\", \"max_pages\": 100}}");
+        println!("   GET  /indexes");
+        println!("   POST /indexes/<name> {{\"scoring\": \"tfidf\"}}");
+        println!("   DELETE /indexes/<name>");
+        println!("   GET  /indexes/<name[,name...]>/search?q=<query>");
+        println!(
+            "   POST /indexes/<name>/documents, /indexes/<name>/documents/_bulk, /indexes/<name>/index, /indexes/<name>/index-web, /indexes/<name>/index-site"
+        );
+        println!("   GET  /indexes/<name>/stats");
+        println!("   GET  /aliases");
+        println!("   PUT  /aliases/<name> {{\"indexes\": [\"docs_v2\"]}}");
+        println!("   DELETE /aliases/<name>");
+        println!("   GET  /snapshots");
+        println!("   POST /snapshots?index=<name>&name=<snapshot>");
+        println!("   POST /snapshots/<snapshot>/restore?index=<name>");
+        println!("   DELETE /snapshots/<snapshot>");
         println!();
 
         warp::serve(routes).run(([127, 0, 0, 1], port)).await;
+        Ok(())
     }
 }
 
@@ -112,6 +489,22 @@ fn with_engine(
     warp::any().map(move || Arc::clone(&engine))
 }
 
+fn with_catalog(
+    catalog: Arc<IndexCatalog>,
+) -> impl Filter<Extract = (Arc<IndexCatalog>,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || Arc::clone(&catalog))
+}
+
+async fn resolve_index(
+    catalog: &IndexCatalog,
+    name: &str,
+) -> Result<Arc<SearchEngine>, warp::Rejection> {
+    catalog
+        .get(name)
+        .await
+        .map_err(|_| warp::reject::not_found())
+}
+
 async fn handle_status(engine: Arc<SearchEngine>) -> Result<impl warp::Reply, warp::Rejection> {
     let (documents, terms) = engine.get_stats().await;
 
@@ -141,6 +534,9 @@ async fn handle_search(
         query.q, doc_count, term_count
     );
 
+    let options = query.to_options()?;
+    engine.check_options(&options).map_err(invalid)?;
+
     // Check if index is empty
     if doc_count == 0 {
         println!("❌ No documents in index!");
@@ -148,45 +544,321 @@ async fn handle_search(
             query: query.q,
             results: vec![],
             total: 0,
+            total_hits: 0,
+            facets: vec![],
         }));
     }
 
-    let results = engine.search(&query.q, query.limit).await.map_err(|e| {
-        println!("❌ Search error: {:?}", e);
-        warp::reject::custom(SearchError)
-    })?;
+    let search_results = engine
+        .search_with_options(&query.q, &options)
+        .await
+        .map_err(|e| {
+            println!("❌ Search error: {:?}", e);
+            warp::reject::custom(SearchError)
+        })?;
 
-    println!("📊 Found {} results for '{}'", results.len(), query.q);
+    println!(
+        "📊 Found {} results for '{}'",
+        search_results.total_hits, query.q
+    );
 
-    let json_results: Vec<SearchResultJson> = results
+    let json_results: Vec<SearchResultJson> = search_results
+        .results
         .into_iter()
         .map(|r| {
             println!("  - {} (score: {:.4})", r.document.title, r.score);
-            SearchResultJson {
-                title: r.document.title,
-                path: r.document.path,
-                score: r.score,
-                snippet: r.snippet,
-            }
+            SearchResultJson::from(r)
         })
         .collect();
 
     let response = SearchResponse {
         query: query.q,
         total: json_results.len(),
+        total_hits: search_results.total_hits,
         results: json_results,
+        facets: search_results.facets,
     };
 
     Ok(warp::reply::json(&response))
 }
 
+async fn handle_index_search(
+    names: String,
+    query: SearchQuery,
+    catalog: Arc<IndexCatalog>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let names: Vec<String> = names.split(',').map(str::to_string).collect();
+    for name in &names {
+        catalog
+            .resolve(name)
+            .await
+            .map_err(|_| warp::reject::not_found())?;
+    }
+
+    let options = query.to_options()?;
+    catalog
+        .check_options(&names, &options)
+        .await
+        .map_err(invalid)?;
+    let search_results = catalog
+        .search(&names, &query.q, &options)
+        .await
+        .map_err(|e| {
+            println!("❌ Search error: {:?}", e);
+            warp::reject::custom(SearchError)
+        })?;
+
+    let results: Vec<SearchResultJson> = search_results
+        .results
+        .into_iter()
+        .map(SearchResultJson::from)
+        .collect();
+    Ok(warp::reply::json(&SearchResponse {
+        query: query.q,
+        total: results.len(),
+        total_hits: search_results.total_hits,
+        results,
+        facets: search_results.facets,
+    }))
+}
+
+async fn handle_list_indexes(
+    catalog: Arc<IndexCatalog>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let indexes = catalog
+        .list()
+        .await
+        .map_err(|_| warp::reject::custom(SearchError))?;
+    Ok(warp::reply::json(
+        &serde_json::json!({ "indexes": indexes }),
+    ))
+}
+
+#[derive(Deserialize)]
+struct CreateIndexQuery {
+    shards: Option<usize>,
+}
+
+async fn handle_create_index(
+    name: String,
+    query: CreateIndexQuery,
+    settings: IndexSettings,
+    catalog: Arc<IndexCatalog>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let shards = query.shards.unwrap_or(1);
+    catalog.create(&name, settings, shards).await.map_err(|e| {
+        println!("❌ Could not create index: {}", e);
+        warp::reject::custom(SearchError)
+    })?;
+
+    Ok(warp::reply::json(&serde_json::json!({
+        "index": name,
+        "shards": shards,
+        "message": "Index created successfully"
+    })))
+}
+
+async fn handle_drop_index(
+    name: String,
+    catalog: Arc<IndexCatalog>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    resolve_index(&catalog, &name).await?;
+    catalog.drop_index(&name).await.map_err(|e| {
+        println!("❌ Could not drop index: {}", e);
+        warp::reject::custom(SearchError)
+    })?;
+
+    Ok(warp::reply::json(&serde_json::json!({
+        "index": name,
+        "message": "Index dropped successfully"
+    })))
+}
+
+#[derive(Deserialize)]
+struct SetAliasRequest {
+    indexes: Vec<String>,
+}
+
+async fn handle_list_aliases(
+    catalog: Arc<IndexCatalog>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    Ok(warp::reply::json(
+        &serde_json::json!({ "aliases": catalog.aliases().await }),
+    ))
+}
+
+async fn handle_set_alias(
+    name: String,
+    request: SetAliasRequest,
+    catalog: Arc<IndexCatalog>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    catalog
+        .set_alias(&name, request.indexes.clone())
+        .await
+        .map_err(|e| {
+            println!("❌ Could not set alias: {}", e);
+            warp::reject::custom(SearchError)
+        })?;
+
+    Ok(warp::reply::json(&serde_json::json!({
+        "alias": name,
+        "indexes": request.indexes,
+        "message": "Alias updated successfully"
+    })))
+}
+
+async fn handle_remove_alias(
+    name: String,
+    catalog: Arc<IndexCatalog>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    catalog
+        .remove_alias(&name)
+        .await
+        .map_err(|_| warp::reject::not_found())?;
+
+    Ok(warp::reply::json(&serde_json::json!({
+        "alias": name,
+        "message": "Alias removed successfully"
+    })))
+}
+
+#[derive(Deserialize)]
+struct SnapshotQuery {
+    /// Index to snapshot, or to restore into instead of the snapshot's own
+    index: Option<String>,
+    /// Name of a new snapshot
+    name: Option<String>,
+}
+
+async fn handle_list_snapshots(
+    catalog: Arc<IndexCatalog>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let snapshots = catalog
+        .snapshots()
+        .list()
+        .map_err(|_| warp::reject::custom(SearchError))?;
+    Ok(warp::reply::json(
+        &serde_json::json!({ "snapshots": snapshots }),
+    ))
+}
+
+async fn handle_create_snapshot(
+    query: SnapshotQuery,
+    catalog: Arc<IndexCatalog>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let index = query
+        .index
+        .as_deref()
+        .unwrap_or(IndexCatalog::DEFAULT_INDEX);
+    resolve_index(&catalog, index).await?;
+    let snapshot = catalog
+        .create_snapshot(index, query.name.as_deref())
+        .await
+        .map_err(|e| {
+            println!("❌ Could not create snapshot: {:#}", e);
+            warp::reject::custom(SearchError)
+        })?;
+
+    Ok(warp::reply::json(&serde_json::json!({
+        "snapshot": snapshot,
+        "message": "Snapshot created successfully"
+    })))
+}
+
+async fn handle_restore_snapshot(
+    name: String,
+    query: SnapshotQuery,
+    catalog: Arc<IndexCatalog>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let snapshot = catalog
+        .restore_snapshot(&name, query.index.as_deref())
+        .await
+        .map_err(|e| {
+            println!("❌ Could not restore snapshot: {:#}", e);
+            warp::reject::custom(SearchError)
+        })?;
+
+    Ok(warp::reply::json(&serde_json::json!({
+        "snapshot": snapshot,
+        "index": query.index.unwrap_or_else(|| snapshot.index.clone()),
+        "message": "Snapshot restored successfully"
+    })))
+}
+
+async fn handle_delete_snapshot(
+    name: String,
+    catalog: Arc<IndexCatalog>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    catalog
+        .snapshots()
+        .delete(&name)
+        .map_err(|_| warp::reject::not_found())?;
+
+    Ok(warp::reply::json(&serde_json::json!({
+        "snapshot": name,
+        "message": "Snapshot deleted successfully"
+    })))
+}
+
+async fn handle_similar(
+    id: Uuid,
+    query: SimilarQuery,
+    engine: Arc<SearchEngine>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let results = engine
+        .similar(&id, query.limit.unwrap_or(5))
+        .await
+        .map_err(|_| warp::reject::not_found())?;
+
+    let response = SimilarResponse {
+        id,
+        results: results.into_iter().map(SearchResultJson::from).collect(),
+    };
+
+    Ok(warp::reply::json(&response))
+}
+
+async fn handle_duplicates(
+    id: Uuid,
+    engine: Arc<SearchEngine>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let (canonical, duplicates) = engine
+        .duplicates(&id)
+        .await
+        .ok_or_else(warp::reject::not_found)?;
+
+    Ok(warp::reply::json(&DuplicatesResponse {
+        id,
+        canonical: canonical.into(),
+        duplicates: duplicates.into_iter().map(DocumentSummary::from).collect(),
+    }))
+}
+
 async fn handle_stats(engine: Arc<SearchEngine>) -> Result<impl warp::Reply, warp::Rejection> {
     let (documents, terms) = engine.get_stats().await;
 
-    let response = StatsResponse { documents, terms };
+    let response = StatsResponse {
+        documents,
+        terms,
+        generation: engine.generation(),
+        ingest: engine.progress(),
+    };
     Ok(warp::reply::json(&response))
 }
 
+#[derive(Deserialize)]
+struct AddDocumentRequest {
+    title: String,
+    content: String,
+    path: String,
+    #[serde(default)]
+    metadata: HashMap<String, String>,
+    /// Unix seconds
+    timestamp: Option<i64>,
+    boost: Option<f64>,
+    embedding: Option<Vec<f32>>,
+}
+
 #[derive(Deserialize)]
 struct IndexRequest {
     directory: String,
@@ -204,6 +876,96 @@ struct IndexSiteRequest {
     max_pages: Option<usize>,
 }
 
+async fn handle_add_document(
+    request: AddDocumentRequest,
+    engine: Arc<SearchEngine>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let boost = request.boost.unwrap_or(1.0);
+    if !Document::is_valid_boost(boost) {
+        return Err(invalid(anyhow::anyhow!(
+            "Boost must be a finite number above 0, got {}",
+            boost
+        )));
+    }
+    let mut document = Document::new(request.title, request.content, request.path)
+        .with_metadata(request.metadata)
+        .with_timestamp(request.timestamp)
+        .with_boost(boost);
+    if let Some(embedding) = request.embedding {
+        document = document.with_embedding(embedding);
+    }
+
+    let Some(id) = engine
+        .add_document(document)
+        .await
+        .map_err(|_| warp::reject::custom(SearchError))?
+    else {
+        return Ok(warp::reply::json(&serde_json::json!({
+            "id": null,
+            "message": "Document skipped as a near-duplicate"
+        })));
+    };
+
+    engine.commit().await;
+    engine
+        .save_index()
+        .await
+        .map_err(|_| warp::reject::custom(SearchError))?;
+
+    Ok(warp::reply::json(&serde_json::json!({
+        "id": id,
+        "message": "Document indexed successfully"
+    })))
+}
+
+/// Streams every committed document as newline-delimited JSON, loading each one as its line
+/// is sent.
+async fn handle_export_documents(
+    engine: Arc<SearchEngine>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let documents = engine
+        .committed_documents()
+        .await
+        .map_err(|_| warp::reject::custom(SearchError))?;
+
+    let lines = stream::iter(documents).map(|document| bulk::to_line(&document));
+    warp::http::Response::builder()
+        .header("content-type", "application/x-ndjson")
+        .body(Body::wrap_stream(lines))
+        .map_err(|_| warp::reject::custom(SearchError))
+}
+
+/// Indexes newline-delimited JSON documents in batches while the body is received. Documents
+/// before a malformed line are kept.
+async fn handle_import_documents(
+    body: impl Stream<Item = Result<impl Buf, warp::Error>>,
+    engine: Arc<SearchEngine>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let chunks = body.map(|chunk| {
+        chunk
+            .map(|mut chunk| chunk.copy_to_bytes(chunk.remaining()))
+            .map_err(anyhow::Error::from)
+    });
+    let imported = engine.import(bulk::document_stream(chunks)).await;
+
+    engine
+        .save_index()
+        .await
+        .map_err(|_| warp::reject::custom(SearchError))?;
+    let progress = imported.map_err(|e| {
+        println!("❌ Could not import documents: {:#}", e);
+        warp::reject::custom(SearchError)
+    })?;
+
+    Ok(warp::reply::json(&serde_json::json!({
+        "received_documents": progress.crawled,
+        "indexed_documents": progress.indexed,
+        "skipped_documents": progress.skipped,
+        "replaced_documents": progress.replaced,
+        "message": "Documents imported successfully"
+    })))
+}
+
 async fn handle_index(
     request: IndexRequest,
     engine: Arc<SearchEngine>,
@@ -268,3 +1030,23 @@ async fn handle_index_site(
 struct SearchError;
 
 impl warp::reject::Reject for SearchError {}
+
+/// Request parameters that cannot be parsed or applied, answered with 400 Bad Request.
+#[derive(Debug)]
+struct InvalidRequest(String);
+
+impl warp::reject::Reject for InvalidRequest {}
+
+fn invalid(error: anyhow::Error) -> warp::Rejection {
+    warp::reject::custom(InvalidRequest(format!("{:#}", error)))
+}
+
+async fn handle_rejection(rejection: warp::Rejection) -> Result<impl warp::Reply, warp::Rejection> {
+    match rejection.find::<InvalidRequest>() {
+        Some(InvalidRequest(message)) => Ok(warp::reply::with_status(
+            warp::reply::json(&serde_json::json!({ "error": message })),
+            warp::http::StatusCode::BAD_REQUEST,
+        )),
+        None => Err(rejection),
+    }
+}
@@ -0,0 +1,181 @@
+use crate::core::{FieldStats, Link, LinkGraph, Tokenizer};
+use imbl::HashMap;
+use serde::{Deserialize, Serialize};
+
+/// Postings of the text of links pointing at a page, keyed by the target URL.
+///
+/// Targets do not need to be crawled yet: their anchor text is indexed as soon as a
+/// crawled page links to them and applies once the page itself is indexed.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(from = "AnchorPostings")]
+pub struct AnchorIndex {
+    postings: HashMap<String, HashMap<String, usize>>, // term -> target url -> frequency
+    lengths: HashMap<String, usize>,
+    total_tokens: usize,
+    /// The postings by target url, derived from them when deserialized
+    #[serde(skip)]
+    targets: HashMap<String, HashMap<String, usize>>, // target url -> term -> frequency
+}
+
+/// The stored fields of an `AnchorIndex`.
+#[derive(Deserialize)]
+struct AnchorPostings {
+    postings: HashMap<String, HashMap<String, usize>>,
+    lengths: HashMap<String, usize>,
+    total_tokens: usize,
+}
+
+impl From<AnchorPostings> for AnchorIndex {
+    fn from(stored: AnchorPostings) -> Self {
+        let mut targets: HashMap<String, HashMap<String, usize>> = HashMap::new();
+        for (term, frequencies) in &stored.postings {
+            for (target, frequency) in frequencies {
+                targets
+                    .entry(target.clone())
+                    .or_default()
+                    .insert(term.clone(), *frequency);
+            }
+        }
+        Self {
+            postings: stored.postings,
+            lengths: stored.lengths,
+            total_tokens: stored.total_tokens,
+            targets,
+        }
+    }
+}
+
+impl AnchorIndex {
+    pub const FIELD: &'static str = "anchor";
+
+    /// Aggregates the anchor text of every link in `graph` onto its target.
+    /// Links from a page to itself are ignored.
+    pub fn build(graph: &LinkGraph, tokenizer: &Tokenizer) -> Self {
+        let mut anchors = Self::default();
+        for (source, links) in graph.iter() {
+            anchors.add_links(source, links, tokenizer);
+        }
+        anchors
+    }
+
+    /// Indexes the anchor text of the links found on `source`.
+    pub fn add_links(&mut self, source: &str, links: &[Link], tokenizer: &Tokenizer) {
+        for link in links {
+            if link.target == source {
+                continue;
+            }
+
+            let tokens = tokenizer.tokenize(&link.anchor_text);
+            if tokens.is_empty() {
+                continue;
+            }
+
+            *self.lengths.entry(link.target.clone()).or_insert(0) += tokens.len();
+            self.total_tokens += tokens.len();
+            for token in tokens {
+                *self
+                    .targets
+                    .entry(link.target.clone())
+                    .or_default()
+                    .entry(token.clone())
+                    .or_insert(0) += 1;
+                *self
+                    .postings
+                    .entry(token)
+                    .or_default()
+                    .entry(link.target.clone())
+                    .or_insert(0) += 1;
+            }
+        }
+    }
+
+    /// Takes back the anchor text of links indexed with `add_links`, e.g. when `source` is
+    /// crawled again.
+    pub fn remove_links(&mut self, source: &str, links: &[Link], tokenizer: &Tokenizer) {
+        for link in links {
+            if link.target == source {
+                continue;
+            }
+
+            let tokens = tokenizer.tokenize(&link.anchor_text);
+            if tokens.is_empty() {
+                continue;
+            }
+
+            if let Some(length) = self.lengths.get_mut(&link.target) {
+                *length = length.saturating_sub(tokens.len());
+                if *length == 0 {
+                    self.lengths.remove(&link.target);
+                }
+            }
+            self.total_tokens = self.total_tokens.saturating_sub(tokens.len());
+            if let Some(terms) = self.targets.get_mut(&link.target) {
+                for token in &tokens {
+                    if let Some(frequency) = terms.get_mut(token) {
+                        *frequency -= 1;
+                        if *frequency == 0 {
+                            terms.remove(token);
+                        }
+                    }
+                }
+                if terms.is_empty() {
+                    self.targets.remove(&link.target);
+                }
+            }
+            for token in tokens {
+                let Some(targets) = self.postings.get_mut(&token) else {
+                    continue;
+                };
+                if let Some(frequency) = targets.get_mut(&link.target) {
+                    *frequency -= 1;
+                    if *frequency == 0 {
+                        targets.remove(&link.target);
+                    }
+                }
+                if targets.is_empty() {
+                    self.postings.remove(&token);
+                }
+            }
+        }
+    }
+
+    /// How often each term occurs in the anchor text of links to `target`.
+    pub fn target(&self, target: &str) -> Option<&HashMap<String, usize>> {
+        self.targets.get(target)
+    }
+
+    /// Every page with anchor text.
+    pub fn targets(&self) -> impl Iterator<Item = &String> {
+        self.targets.keys()
+    }
+
+    /// Restores the anchor text of a target listed by `target`.
+    pub fn insert_target(&mut self, target: String, terms: HashMap<String, usize>) {
+        for (term, frequency) in &terms {
+            *self.lengths.entry(target.clone()).or_insert(0) += frequency;
+            self.total_tokens += frequency;
+            self.postings
+                .entry(term.clone())
+                .or_default()
+                .insert(target.clone(), *frequency);
+        }
+        self.targets.insert(target, terms);
+    }
+
+    pub fn postings(&self, term: &str) -> Option<&HashMap<String, usize>> {
+        self.postings.get(term)
+    }
+
+    pub fn length(&self, target: &str) -> usize {
+        self.lengths.get(target).copied().unwrap_or(0)
+    }
+
+    pub fn stats(&self, boost: f64) -> FieldStats {
+        FieldStats {
+            name: Self::FIELD,
+            doc_count: self.lengths.len(),
+            total_tokens: self.total_tokens,
+            boost,
+        }
+    }
+}
@@ -0,0 +1,123 @@
+use imbl::HashMap;
+use uuid::Uuid;
+
+/// Terms, documents and pages of an index changed since it was created or loaded, each with
+/// the revision of its last change. Every change to the index is a new revision, so a storage
+/// that saved revision `r` only has to write what changed after `r`.
+#[derive(Debug, Clone, Default)]
+pub struct ChangeLog {
+    revision: u64,
+    terms: HashMap<String, u64>,
+    documents: HashMap<Uuid, u64>,
+    /// Documents whose vector links, duplicate group or authority changed along with another
+    /// document or the link graph, while the document itself did not
+    links: HashMap<Uuid, u64>,
+    /// Pages whose outlinks or anchor text changed
+    pages: HashMap<String, u64>,
+}
+
+/// Terms and documents a storage holds as of the revision given with each, see
+/// `ChangeLog::forget`.
+#[derive(Debug, Default)]
+pub struct SavedChanges {
+    pub terms: Vec<(String, u64)>,
+    pub documents: Vec<(Uuid, u64)>,
+}
+
+impl ChangeLog {
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    /// Starts a new revision; changes recorded from now on belong to it.
+    pub(crate) fn begin(&mut self) {
+        self.revision += 1;
+    }
+
+    pub(crate) fn term(&mut self, term: &str) {
+        match self.terms.get_mut(term) {
+            Some(revision) => *revision = self.revision,
+            None => {
+                self.terms.insert(term.to_string(), self.revision);
+            }
+        }
+    }
+
+    pub(crate) fn document(&mut self, doc_id: Uuid) {
+        self.documents.insert(doc_id, self.revision);
+    }
+
+    pub(crate) fn links(&mut self, doc_id: Uuid) {
+        self.links.insert(doc_id, self.revision);
+    }
+
+    pub(crate) fn page(&mut self, page: &str) {
+        match self.pages.get_mut(page) {
+            Some(revision) => *revision = self.revision,
+            None => {
+                self.pages.insert(page.to_string(), self.revision);
+            }
+        }
+    }
+
+    /// Terms whose postings changed after `revision`.
+    pub fn terms_since(&self, revision: u64) -> impl Iterator<Item = &str> {
+        self.terms
+            .iter()
+            .filter(move |(_, changed)| **changed > revision)
+            .map(|(term, _)| term.as_str())
+    }
+
+    /// Documents added, changed or removed after `revision`.
+    pub fn documents_since(&self, revision: u64) -> impl Iterator<Item = &Uuid> {
+        self.documents
+            .iter()
+            .filter(move |(_, changed)| **changed > revision)
+            .map(|(doc_id, _)| doc_id)
+    }
+
+    /// Documents whose vector links, duplicate group or authority changed after `revision`
+    /// without the document changing, see `documents_since` for the others.
+    pub fn links_since(&self, revision: u64) -> impl Iterator<Item = &Uuid> {
+        self.links
+            .iter()
+            .filter(move |(_, changed)| **changed > revision)
+            .map(|(doc_id, _)| doc_id)
+    }
+
+    /// Pages whose outlinks or anchor text changed after `revision`.
+    pub fn pages_since(&self, revision: u64) -> impl Iterator<Item = &str> {
+        self.pages
+            .iter()
+            .filter(move |(_, changed)| **changed > revision)
+            .map(|(page, _)| page.as_str())
+    }
+
+    /// Drops the changes up to `revision`, once they are saved, and returns the terms and
+    /// documents among them.
+    pub fn forget(&mut self, revision: u64) -> SavedChanges {
+        let saved = SavedChanges {
+            terms: self
+                .terms
+                .iter()
+                .filter(|(_, changed)| **changed <= revision)
+                .map(|(term, changed)| (term.clone(), *changed))
+                .collect(),
+            documents: self
+                .documents
+                .iter()
+                .filter(|(_, changed)| **changed <= revision)
+                .map(|(doc_id, changed)| (*doc_id, *changed))
+                .collect(),
+        };
+        for (term, _) in &saved.terms {
+            self.terms.remove(term);
+        }
+        for (doc_id, _) in &saved.documents {
+            self.documents.remove(doc_id);
+        }
+        self.links.retain(|_, changed| *changed > revision);
+        self.pages.retain(|_, changed| *changed > revision);
+        saved
+    }
+}
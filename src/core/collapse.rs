@@ -0,0 +1,160 @@
+use crate::core::InvertedIndex;
+use anyhow::{Result, anyhow};
+use std::collections::HashMap;
+use url::Url;
+use uuid::Uuid;
+
+/// What search hits are grouped by when collapsing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CollapseKey {
+    /// Host name of web pages
+    Host,
+    /// Leading `depth` directories of the path, the whole parent directory for depth 0
+    Directory { depth: usize },
+    /// A doc-value column such as `tag` or a metadata key
+    Field(String),
+}
+
+/// Keeps only the best `size` hits of each group.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Collapse {
+    pub key: CollapseKey,
+    pub size: usize,
+}
+
+impl Collapse {
+    /// Parses `key[:size]` where key is `host`, `dir`, `dir/<depth>` or a field name,
+    /// e.g. `host:2` or `dir/1`. The size defaults to 1.
+    pub fn parse(spec: &str) -> Result<Self> {
+        let (key, size) = match spec.trim().rsplit_once(':') {
+            Some((key, size)) => {
+                let size = size
+                    .trim()
+                    .parse::<usize>()
+                    .map_err(|_| anyhow!("Invalid collapse size '{}'", size))?;
+                (key.trim().to_lowercase(), size)
+            }
+            None => (spec.trim().to_lowercase(), 1),
+        };
+
+        if size == 0 {
+            return Err(anyhow!("Collapse size must be at least 1"));
+        }
+
+        let key = match key.as_str() {
+            "" => return Err(anyhow!("Empty collapse key in '{}'", spec)),
+            "host" => CollapseKey::Host,
+            "dir" => CollapseKey::Directory { depth: 0 },
+            _ => match key.strip_prefix("dir/") {
+                Some(depth) => CollapseKey::Directory {
+                    depth: depth
+                        .parse()
+                        .map_err(|_| anyhow!("Invalid directory depth '{}'", depth))?,
+                },
+                None => CollapseKey::Field(key),
+            },
+        };
+
+        Ok(Self { key, size })
+    }
+
+    /// The group a document belongs to. Documents without one are never collapsed.
+    pub fn group(&self, index: &InvertedIndex, doc_id: &Uuid) -> Option<String> {
+        let value = |field: &str| {
+            index
+                .doc_values
+                .get(field, doc_id)
+                .and_then(|values| values.first())
+                .cloned()
+        };
+
+        match &self.key {
+            CollapseKey::Host => value("domain"),
+            CollapseKey::Directory { depth } => {
+                value("path").map(|path| Self::directory(&path, *depth))
+            }
+            CollapseKey::Field(field) => value(field),
+        }
+    }
+
+    /// Drops every hit beyond the best `size` of its group, keeping the order. `group_of`
+    /// looks up the group of a hit, see `group`. Returns how many hits each group lost.
+    pub fn apply<T>(
+        &self,
+        hits: &mut Vec<T>,
+        group_of: impl Fn(&T) -> Option<String>,
+    ) -> HashMap<String, usize> {
+        let mut counts: HashMap<String, usize> = HashMap::new();
+        hits.retain(|hit| match group_of(hit) {
+            Some(group) => {
+                let count = counts.entry(group).or_insert(0);
+                *count += 1;
+                *count <= self.size
+            }
+            None => true,
+        });
+
+        counts
+            .into_iter()
+            .filter(|(_, count)| *count > self.size)
+            .map(|(group, count)| (group, count - self.size))
+            .collect()
+    }
+
+    /// `example.com/docs/guide` for web pages, `docs/guide` for files.
+    fn directory(path: &str, depth: usize) -> String {
+        let (host, path) = match Url::parse(path) {
+            Ok(url) if url.has_host() => (
+                url.host_str().map(str::to_lowercase),
+                url.path().to_string(),
+            ),
+            _ => (None, path.to_string()),
+        };
+
+        let mut segments: Vec<&str> = path.split(['/', '\\']).collect();
+        // The last segment is the file name, empty for paths ending in a slash
+        segments.pop();
+        segments.retain(|segment| !segment.is_empty() && *segment != ".");
+        if depth > 0 {
+            segments.truncate(depth);
+        }
+
+        let directory: Vec<&str> = host.as_deref().into_iter().chain(segments).collect();
+        if directory.is_empty() {
+            ".".to_string()
+        } else {
+            directory.join("/")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_collapse_specs() {
+        let parse = |spec| Collapse::parse(spec).unwrap();
+        assert_eq!(
+            parse("host"),
+            Collapse {
+                key: CollapseKey::Host,
+                size: 1
+            }
+        );
+        assert_eq!(
+            parse(" Host:3 "),
+            Collapse {
+                key: CollapseKey::Host,
+                size: 3
+            }
+        );
+        assert_eq!(parse("dir").key, CollapseKey::Directory { depth: 0 });
+        assert_eq!(parse("dir/2:2").key, CollapseKey::Directory { depth: 2 });
+        assert_eq!(parse("Tag").key, CollapseKey::Field("tag".to_string()));
+
+        for spec in ["", ":2", "host:0", "host:many", "dir/deep"] {
+            assert!(Collapse::parse(spec).is_err(), "{:?}", spec);
+        }
+    }
+}
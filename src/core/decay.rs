@@ -0,0 +1,237 @@
+use crate::core::Explanation;
+use anyhow::{Result, anyhow};
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
+
+/// Shape of the curve a score decays along as a document gets older.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecayFunction {
+    Exp,
+    Linear,
+    Gauss,
+}
+
+/// Multiplies scores by a factor in `(0, 1]` depending on the age of a document's timestamp.
+///
+/// Documents up to `offset` seconds old keep their full score; `scale` seconds later the
+/// factor has dropped to `decay`. Documents without a timestamp are left untouched.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Decay {
+    pub function: DecayFunction,
+    pub scale: i64,
+    pub offset: i64,
+    pub decay: f64,
+}
+
+impl Decay {
+    pub const DEFAULT_DECAY: f64 = 0.5;
+
+    pub fn new(function: DecayFunction, scale: i64) -> Self {
+        Self {
+            function,
+            scale,
+            offset: 0,
+            decay: Self::DEFAULT_DECAY,
+        }
+    }
+
+    pub fn with_offset(mut self, offset: i64) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    pub fn with_decay(mut self, decay: f64) -> Self {
+        self.decay = decay;
+        self
+    }
+
+    /// Parses `function:scale[:offset[:decay]]`, e.g. `exp:30d`, `gauss:1y:30d` or
+    /// `linear:90d:0d:0.2`. Durations take an `s`, `m`, `h`, `d`, `w` or `y` suffix
+    /// and default to days.
+    pub fn parse(spec: &str) -> Result<Self> {
+        let mut parts = spec.split(':').map(str::trim);
+
+        let function = match parts.next().map(str::to_lowercase).as_deref() {
+            Some("exp") => DecayFunction::Exp,
+            Some("linear") => DecayFunction::Linear,
+            Some("gauss") => DecayFunction::Gauss,
+            _ => {
+                return Err(anyhow!(
+                    "Invalid decay '{}', expected exp, linear or gauss",
+                    spec
+                ));
+            }
+        };
+
+        let scale = parts
+            .next()
+            .ok_or_else(|| anyhow!("Missing scale in decay '{}'", spec))
+            .and_then(Self::parse_duration)?;
+        if scale <= 0 {
+            return Err(anyhow!("Decay scale must be positive in '{}'", spec));
+        }
+
+        let mut decay = Self::new(function, scale);
+        if let Some(offset) = parts.next() {
+            decay = decay.with_offset(Self::parse_duration(offset)?);
+        }
+        if let Some(value) = parts.next() {
+            let value: f64 = value
+                .parse()
+                .map_err(|_| anyhow!("Invalid decay value '{}'", value))?;
+            if !(value > 0.0 && value < 1.0) {
+                return Err(anyhow!(
+                    "Decay value must be between 0 and 1, got {}",
+                    value
+                ));
+            }
+            decay = decay.with_decay(value);
+        }
+        if parts.next().is_some() {
+            return Err(anyhow!("Too many parts in decay '{}'", spec));
+        }
+
+        Ok(decay)
+    }
+
+    /// Parses `90`, `12h`, `30d`, `2w` or `1y` into seconds.
+    pub fn parse_duration(value: &str) -> Result<i64> {
+        let value = value.trim();
+        let (number, unit) = match value.find(|c: char| !c.is_ascii_digit()) {
+            Some(i) => value.split_at(i),
+            None => (value, "d"),
+        };
+
+        let number: i64 = number
+            .parse()
+            .map_err(|_| anyhow!("Invalid duration '{}'", value))?;
+        let seconds = match unit {
+            "s" => 1,
+            "m" => 60,
+            "h" => 3_600,
+            "d" => 86_400,
+            "w" => 7 * 86_400,
+            "y" => 365 * 86_400,
+            _ => return Err(anyhow!("Invalid duration unit in '{}'", value)),
+        };
+
+        number
+            .checked_mul(seconds)
+            .ok_or_else(|| anyhow!("Duration '{}' is too long", value))
+    }
+
+    /// Factor for a document last changed at `timestamp`, both in Unix seconds.
+    pub fn factor(&self, timestamp: i64, now: i64) -> f64 {
+        let x = self.distance(timestamp, now) as f64 / self.scale as f64;
+
+        match self.function {
+            DecayFunction::Exp => self.decay.powf(x),
+            DecayFunction::Linear => (1.0 - (1.0 - self.decay) * x).max(0.0),
+            DecayFunction::Gauss => self.decay.powf(x * x),
+        }
+    }
+
+    /// Age beyond the offset. Timestamps at the ends of the `i64` range would overflow
+    /// a signed difference.
+    fn distance(&self, timestamp: i64, now: i64) -> u64 {
+        now.abs_diff(timestamp)
+            .saturating_sub(self.offset.max(0) as u64)
+    }
+
+    pub fn explain(&self, timestamp: i64, now: i64) -> Explanation {
+        let description = match self.function {
+            DecayFunction::Exp => "freshness, exp decay as decay ^ (distance / scale)",
+            DecayFunction::Linear => {
+                "freshness, linear decay as max(0, 1 - (1 - decay) * distance / scale)"
+            }
+            DecayFunction::Gauss => "freshness, gauss decay as decay ^ (distance / scale)^2",
+        };
+
+        let age = now.abs_diff(timestamp);
+        Explanation::new(self.factor(timestamp, now), description).with_details(vec![
+            Explanation::new(
+                self.distance(timestamp, now) as f64 / 86_400.0,
+                "distance, age in days minus offset",
+            )
+            .with_details(vec![
+                Explanation::new(age as f64 / 86_400.0, "age in days"),
+                Explanation::new(self.offset as f64 / 86_400.0, "offset in days"),
+            ]),
+            Explanation::new(self.scale as f64 / 86_400.0, "scale in days"),
+            Explanation::new(self.decay, "decay, factor at offset + scale"),
+        ])
+    }
+}
+
+/// Parses RFC 3339, RFC 2822 (as used by HTTP `Last-Modified`), `YYYY-MM-DD HH:MM:SS`
+/// and `YYYY-MM-DD` dates into Unix seconds. Dates without a zone are taken as UTC.
+pub fn parse_timestamp(value: &str) -> Option<i64> {
+    let value = value.trim();
+
+    DateTime::parse_from_rfc3339(value)
+        .or_else(|_| DateTime::parse_from_rfc2822(value))
+        .map(|date| date.timestamp())
+        .ok()
+        .or_else(|| {
+            NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S")
+                .or_else(|_| NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S"))
+                .ok()
+                .or_else(|| {
+                    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                        .ok()
+                        .and_then(|date| date.and_hms_opt(0, 0, 0))
+                })
+                .map(|date| date.and_utc().timestamp())
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_duration_rejects_overflow() {
+        assert_eq!(Decay::parse_duration("2w").unwrap(), 14 * 86_400);
+        assert!(Decay::parse_duration("99999999999999y").is_err());
+        assert!(Decay::parse("exp:99999999999999y").is_err());
+    }
+
+    #[test]
+    fn parses_decay_specs() {
+        assert_eq!(
+            Decay::parse("exp:30d").unwrap(),
+            Decay::new(DecayFunction::Exp, 30 * 86_400)
+        );
+        assert_eq!(
+            Decay::parse(" Gauss : 1y : 2w ").unwrap(),
+            Decay::new(DecayFunction::Gauss, 365 * 86_400).with_offset(14 * 86_400)
+        );
+        assert_eq!(
+            Decay::parse("linear:90:0h:0.2").unwrap(),
+            Decay::new(DecayFunction::Linear, 90 * 86_400).with_decay(0.2)
+        );
+
+        for spec in [
+            "",
+            "cubic:30d",
+            "exp",
+            "exp:0d",
+            "exp:30x",
+            "exp:30d:1d:1.5",
+            "exp:30d:1d:0",
+            "exp:30d:1d:half",
+            "exp:30d:1d:0.5:extra",
+        ] {
+            assert!(Decay::parse(spec).is_err(), "{:?}", spec);
+        }
+    }
+
+    #[test]
+    fn distant_timestamps_do_not_overflow() {
+        let decay = Decay::new(DecayFunction::Gauss, 30 * 86_400).with_offset(86_400);
+        for (timestamp, now) in [(i64::MIN, i64::MAX), (i64::MAX, i64::MIN), (0, i64::MIN)] {
+            assert_eq!(decay.factor(timestamp, now), 0.0);
+            assert_eq!(decay.explain(timestamp, now).value, 0.0);
+        }
+        assert_eq!(decay.factor(1_000, 1_000 + 86_400), 1.0);
+    }
+}
@@ -0,0 +1,289 @@
+use crate::core::Document;
+use chrono::{DateTime, Datelike};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use url::Url;
+use uuid::Uuid;
+
+/// Fields every document gets a column for, in addition to its metadata keys.
+pub const FACET_FIELDS: [&str; 4] = ["tag", "domain", "extension", "year"];
+
+/// Document attributes mirrored into columns so results can be sorted without loading documents.
+pub const SORT_FIELDS: [&str; 4] = ["title", "path", "word_count", "timestamp"];
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FacetCount {
+    pub value: String,
+    pub count: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Facet {
+    pub field: String,
+    pub values: Vec<FacetCount>,
+}
+
+impl Facet {
+    /// Sums the counts of facets over the same field, e.g. from several indexes, keeping the
+    /// `size` most frequent values.
+    pub fn merge(field: &str, facets: Vec<Facet>, size: usize) -> Facet {
+        let mut counts: HashMap<String, usize> = HashMap::new();
+        for facet in facets {
+            for value in facet.values {
+                *counts.entry(value.value).or_insert(0) += value.count;
+            }
+        }
+
+        let mut values: Vec<FacetCount> = counts
+            .into_iter()
+            .map(|(value, count)| FacetCount { value, count })
+            .collect();
+        values.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.value.cmp(&b.value)));
+        values.truncate(size);
+
+        Facet {
+            field: field.to_string(),
+            values,
+        }
+    }
+}
+
+/// Column-oriented per-field values kept next to the inverted index.
+///
+/// Aggregations and filters only touch the columns they need instead of
+/// loading full documents. Columns are persistent maps, so copies share them until changed.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DocValues {
+    columns: imbl::HashMap<String, imbl::HashMap<Uuid, Vec<String>>>,
+}
+
+impl DocValues {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, document: &Document) {
+        for (field, values) in Self::extract(document) {
+            if values.is_empty() {
+                continue;
+            }
+            self.columns
+                .entry(field)
+                .or_default()
+                .insert(document.id, values);
+        }
+    }
+
+    pub fn remove(&mut self, doc_id: &Uuid) {
+        let fields: Vec<String> = self
+            .columns
+            .iter()
+            .filter(|(_, column)| column.contains_key(doc_id))
+            .map(|(field, _)| field.clone())
+            .collect();
+        for field in fields {
+            let column = &mut self.columns[&field];
+            column.remove(doc_id);
+            if column.is_empty() {
+                self.columns.remove(&field);
+            }
+        }
+    }
+
+    /// Every field of a document with its values, by field name.
+    pub fn document(&self, doc_id: &Uuid) -> Vec<(String, Vec<String>)> {
+        let mut fields: Vec<(String, Vec<String>)> = self
+            .columns
+            .iter()
+            .filter_map(|(field, column)| Some((field.clone(), column.get(doc_id)?.clone())))
+            .collect();
+        fields.sort();
+        fields
+    }
+
+    /// Restores the values of a document listed by `document`.
+    pub fn insert_values(&mut self, doc_id: Uuid, fields: Vec<(String, Vec<String>)>) {
+        for (field, values) in fields {
+            self.columns
+                .entry(field)
+                .or_default()
+                .insert(doc_id, values);
+        }
+    }
+
+    pub fn get(&self, field: &str, doc_id: &Uuid) -> Option<&[String]> {
+        self.columns
+            .get(field)
+            .and_then(|column| column.get(doc_id))
+            .map(Vec::as_slice)
+    }
+
+    pub fn fields(&self) -> Vec<&str> {
+        self.columns.keys().map(String::as_str).collect()
+    }
+
+    /// Counts values of `field` over the given documents, most frequent first.
+    pub fn facet_counts<'a>(
+        &self,
+        field: &str,
+        doc_ids: impl IntoIterator<Item = &'a Uuid>,
+        size: usize,
+    ) -> Facet {
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+
+        if let Some(column) = self.columns.get(field) {
+            for doc_id in doc_ids {
+                if let Some(values) = column.get(doc_id) {
+                    for value in values {
+                        *counts.entry(value.as_str()).or_insert(0) += 1;
+                    }
+                }
+            }
+        }
+
+        let mut values: Vec<FacetCount> = counts
+            .into_iter()
+            .map(|(value, count)| FacetCount {
+                value: value.to_string(),
+                count,
+            })
+            .collect();
+        values.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.value.cmp(&b.value)));
+        values.truncate(size);
+
+        Facet {
+            field: field.to_string(),
+            values,
+        }
+    }
+
+    fn extract(document: &Document) -> Vec<(String, Vec<String>)> {
+        let mut fields = vec![
+            ("title".to_string(), vec![document.title.clone()]),
+            ("path".to_string(), vec![document.path.clone()]),
+            (
+                "word_count".to_string(),
+                vec![document.word_count.to_string()],
+            ),
+            (
+                "timestamp".to_string(),
+                document
+                    .timestamp
+                    .map(|t| t.to_string())
+                    .into_iter()
+                    .collect(),
+            ),
+            ("tag".to_string(), document.tags()),
+            (
+                "domain".to_string(),
+                Url::parse(&document.path)
+                    .ok()
+                    .and_then(|url| url.host_str().map(str::to_lowercase))
+                    .into_iter()
+                    .collect(),
+            ),
+            (
+                "extension".to_string(),
+                Self::extension(&document.path).into_iter().collect(),
+            ),
+            (
+                "year".to_string(),
+                document
+                    .metadata
+                    .get("date")
+                    .and_then(|date| date.get(..4))
+                    .filter(|year| year.chars().all(|c| c.is_ascii_digit()))
+                    .map(str::to_string)
+                    // Crawled pages have no date metadata, only a Last-Modified timestamp
+                    .or_else(|| {
+                        let date = DateTime::from_timestamp(document.timestamp?, 0)?;
+                        Some(date.year().to_string())
+                    })
+                    .into_iter()
+                    .collect(),
+            ),
+        ];
+
+        for (key, value) in &document.metadata {
+            // `Title` must not overwrite the title column any more than `title` does
+            let key = key.to_lowercase();
+            if key != "tags"
+                && !FACET_FIELDS.contains(&key.as_str())
+                && !SORT_FIELDS.contains(&key.as_str())
+            {
+                fields.push((key, vec![value.trim().to_string()]));
+            }
+        }
+
+        fields
+    }
+
+    fn extension(path: &str) -> Option<String> {
+        let file_path = match Url::parse(path) {
+            Ok(url) if url.scheme() == "http" || url.scheme() == "https" => url.path().to_string(),
+            _ => path.to_string(),
+        };
+
+        Path::new(&file_path)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(str::to_lowercase)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn metadata_cannot_overwrite_reserved_columns() {
+        let metadata = HashMap::from([
+            ("Title".to_string(), "Spoofed".to_string()),
+            ("PATH".to_string(), "elsewhere.md".to_string()),
+            ("Author".to_string(), " Ann ".to_string()),
+        ]);
+        let document = Document::new(
+            "Real title".to_string(),
+            "Content".to_string(),
+            "notes/real.md".to_string(),
+        )
+        .with_metadata(metadata);
+        let mut doc_values = DocValues::new();
+        doc_values.insert(&document);
+
+        let get = |field| doc_values.get(field, &document.id).map(<[String]>::to_vec);
+        assert_eq!(get("title"), Some(vec!["Real title".to_string()]));
+        assert_eq!(get("path"), Some(vec!["notes/real.md".to_string()]));
+        assert_eq!(get("author"), Some(vec!["Ann".to_string()]));
+    }
+
+    #[test]
+    fn year_comes_from_date_metadata_or_timestamp() {
+        // 2021-06-01T00:00:00Z
+        let crawled = Document::new(
+            "Page".to_string(),
+            "Content".to_string(),
+            "https://example.com/page".to_string(),
+        )
+        .with_timestamp(Some(1_622_505_600));
+        let dated = crawled.clone().with_metadata(HashMap::from([(
+            "date".to_string(),
+            "2019-03-04".to_string(),
+        )]));
+        let mut dated = dated;
+        dated.id = Uuid::new_v4();
+
+        let mut doc_values = DocValues::new();
+        doc_values.insert(&crawled);
+        doc_values.insert(&dated);
+        assert_eq!(
+            doc_values.get("year", &crawled.id),
+            Some(&["2021".to_string()][..])
+        );
+        assert_eq!(
+            doc_values.get("year", &dated.id),
+            Some(&["2019".to_string()][..])
+        );
+    }
+}
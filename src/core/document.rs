@@ -1,6 +1,17 @@
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use uuid::Uuid;
 
+/// A heading within a document's content.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Section {
+    pub heading: String,
+    /// Fragment linking to the heading, e.g. `installation` for `page.html#installation`
+    pub anchor: Option<String>,
+    /// Word offset in `content` at which the section starts
+    pub start: usize,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Document {
     pub id: Uuid,
@@ -8,6 +19,21 @@ pub struct Document {
     pub content: String,
     pub path: String,
     pub word_count: usize,
+    /// Free-form key/value pairs such as `tags` or `date` (e.g. from front matter)
+    #[serde(default)]
+    pub metadata: HashMap<String, String>,
+    /// When the content last changed, in Unix seconds
+    #[serde(default)]
+    pub timestamp: Option<i64>,
+    /// Static score multiplier, e.g. for pinned official pages
+    #[serde(default = "Document::default_boost")]
+    pub boost: f64,
+    /// Precomputed embedding for vector and hybrid search
+    #[serde(default)]
+    pub embedding: Option<Vec<f32>>,
+    /// Headings of the content in order, used to split long documents into passages
+    #[serde(default)]
+    pub sections: Vec<Section>,
 }
 
 impl Document {
@@ -20,10 +46,72 @@ impl Document {
             content,
             path,
             word_count,
+            metadata: HashMap::new(),
+            timestamp: None,
+            boost: Self::default_boost(),
+            embedding: None,
+            sections: Vec::new(),
         }
     }
 
+    pub fn with_metadata(mut self, metadata: HashMap<String, String>) -> Self {
+        self.metadata = metadata;
+        self
+    }
+
+    pub fn with_timestamp(mut self, timestamp: Option<i64>) -> Self {
+        self.timestamp = timestamp;
+        self
+    }
+
+    /// Invalid boosts (see `is_valid_boost`) are logged and replaced by 1.0.
+    pub fn with_boost(mut self, boost: f64) -> Self {
+        self.boost = if Self::is_valid_boost(boost) {
+            boost
+        } else {
+            log::warn!(
+                "Ignoring invalid boost {} of {}, using 1.0",
+                boost,
+                self.path
+            );
+            Self::default_boost()
+        };
+        self
+    }
+
+    /// Boosts multiply scores, so only finite values above zero keep rankings meaningful.
+    pub fn is_valid_boost(boost: f64) -> bool {
+        boost.is_finite() && boost > 0.0
+    }
+
+    pub fn with_embedding(mut self, embedding: Vec<f32>) -> Self {
+        self.embedding = Some(embedding);
+        self
+    }
+
+    pub fn with_sections(mut self, sections: Vec<Section>) -> Self {
+        self.sections = sections;
+        self
+    }
+
+    fn default_boost() -> f64 {
+        1.0
+    }
+
     pub fn get_text(&self) -> String {
         format!("{} {}", self.title, self.content)
     }
+
+    /// Tags listed in the `tags` metadata entry, split on commas.
+    pub fn tags(&self) -> Vec<String> {
+        self.metadata
+            .get("tags")
+            .map(|tags| {
+                tags.split(',')
+                    .map(|tag| tag.trim().to_lowercase())
+                    .filter(|tag| !tag.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
 }
@@ -0,0 +1,242 @@
+use crate::core::Document;
+use anyhow::{Result, anyhow};
+use lru::LruCache;
+use serde::ser::{Error, SerializeMap};
+use serde::{Serialize, Serializer};
+use std::collections::HashMap;
+use std::fmt;
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex, MutexGuard};
+use uuid::Uuid;
+
+/// Where a `DocumentStore` reads documents it does not hold, e.g. a storage tree.
+pub trait DocumentSource: Send + Sync {
+    fn load_document(&self, doc_id: &Uuid) -> Result<Option<Document>>;
+}
+
+/// The stored documents of an index. Documents added or changed in memory are held until
+/// they are saved and released; the others are read from the source when needed and kept
+/// in a cache of the most recently read ones, which every copy of the store shares. Copies
+/// share the map of documents as well until one of them changes.
+#[derive(Clone)]
+pub struct DocumentStore {
+    documents: imbl::HashMap<Uuid, Slot>,
+    source: Option<Arc<dyn DocumentSource>>,
+    cache: Arc<Mutex<DocumentCache>>,
+}
+
+/// Loaded values by key and the change log revision they are as of.
+type DocumentCache = LruCache<(Uuid, u64), Arc<Document>>;
+
+/// A document in memory, or the change log revision the source holds it as of. Cached
+/// copies are looked up by revision, so a copy of the store still reading an earlier
+/// generation is never handed a later one from the cache.
+#[derive(Clone)]
+enum Slot {
+    Held(Arc<Document>),
+    Stored(u64),
+}
+
+impl DocumentStore {
+    /// Documents read from the source that are kept in memory.
+    pub const CACHE_SIZE: usize = 1024;
+
+    pub fn new() -> Self {
+        Self {
+            documents: imbl::HashMap::new(),
+            source: None,
+            cache: Arc::new(Mutex::new(LruCache::new(
+                NonZeroUsize::new(Self::CACHE_SIZE).expect("cache size is not zero"),
+            ))),
+        }
+    }
+
+    /// A store of the documents `doc_ids`, loaded from `source` on demand.
+    pub fn with_source(
+        doc_ids: impl IntoIterator<Item = Uuid>,
+        source: Arc<dyn DocumentSource>,
+    ) -> Self {
+        Self {
+            documents: doc_ids
+                .into_iter()
+                .map(|doc_id| (doc_id, Slot::Stored(0)))
+                .collect(),
+            source: Some(source),
+            ..Self::new()
+        }
+    }
+
+    /// The document, loading it if needed. Documents that cannot be loaded are reported and
+    /// treated as missing, see `try_get`.
+    pub fn get(&self, doc_id: &Uuid) -> Option<Arc<Document>> {
+        self.try_get(doc_id).unwrap_or_else(|e| {
+            log::warn!("{:#}", e);
+            None
+        })
+    }
+
+    /// The document, loading it if needed. Fails for a document that cannot be loaded.
+    pub fn try_get(&self, doc_id: &Uuid) -> Result<Option<Arc<Document>>> {
+        let revision = match self.documents.get(doc_id) {
+            None => return Ok(None),
+            Some(Slot::Held(document)) => return Ok(Some(Arc::clone(document))),
+            Some(Slot::Stored(revision)) => *revision,
+        };
+        let key = (*doc_id, revision);
+        if let Some(document) = self.cache().get(&key) {
+            return Ok(Some(Arc::clone(document)));
+        }
+
+        let source = self
+            .source
+            .as_ref()
+            .ok_or_else(|| anyhow!("Document {} has no storage to load it from", doc_id))?;
+        let document = source
+            .load_document(doc_id)
+            .and_then(|document| document.ok_or_else(|| anyhow!("Document is missing")))
+            .map_err(|e| e.context(format!("Failed to load document {}", doc_id)))?;
+        let document = Arc::new(document);
+        self.cache().put(key, Arc::clone(&document));
+        Ok(Some(document))
+    }
+
+    /// The document to change, held in memory from now on. Fails for a document that cannot
+    /// be loaded.
+    pub fn get_mut(&mut self, doc_id: &Uuid) -> Result<Option<&mut Document>> {
+        let Some(document) = self.try_get(doc_id)? else {
+            return Ok(None);
+        };
+        self.documents.insert(*doc_id, Slot::Held(document));
+        match &mut self.documents[doc_id] {
+            Slot::Held(document) => Ok(Some(Arc::make_mut(document))),
+            Slot::Stored(_) => unreachable!("document was just stored in memory"),
+        }
+    }
+
+    /// Loads the documents `doc_ids` that are not held and holds them, so they can be read
+    /// and removed without loading anything. Fails, holding what was loaded before, for a
+    /// document that cannot be loaded.
+    pub fn hold<'a>(&mut self, doc_ids: impl IntoIterator<Item = &'a Uuid>) -> Result<()> {
+        for doc_id in doc_ids {
+            if let Some(Slot::Stored(_)) = self.documents.get(doc_id) {
+                let document = self
+                    .try_get(doc_id)?
+                    .ok_or_else(|| anyhow!("Document {} is missing", doc_id))?;
+                self.documents.insert(*doc_id, Slot::Held(document));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn insert(&mut self, document: Document) {
+        self.documents
+            .insert(document.id, Slot::Held(Arc::new(document)));
+    }
+
+    /// Removes the document and returns it, loading it if needed. Fails, keeping it, for a
+    /// document that cannot be loaded.
+    pub fn remove(&mut self, doc_id: &Uuid) -> Result<Option<Document>> {
+        let Some(document) = self.try_get(doc_id)? else {
+            return Ok(None);
+        };
+        self.documents.remove(doc_id);
+        Ok(Some(Arc::unwrap_or_clone(document)))
+    }
+
+    /// Drops held documents from memory once `source` holds them, each as of the change log
+    /// revision given with it, and reads them from there from now on.
+    pub fn release(
+        &mut self,
+        doc_ids: impl IntoIterator<Item = (Uuid, u64)>,
+        source: &Arc<dyn DocumentSource>,
+    ) {
+        self.source = Some(Arc::clone(source));
+        let mut cache = self.cache.lock().expect("document cache lock poisoned");
+        for (doc_id, revision) in doc_ids {
+            if let Some(slot) = self.documents.get_mut(&doc_id)
+                && let Slot::Held(document) = std::mem::replace(slot, Slot::Stored(revision))
+            {
+                cache.put((doc_id, revision), document);
+            }
+        }
+    }
+
+    pub fn contains(&self, doc_id: &Uuid) -> bool {
+        self.documents.contains_key(doc_id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.documents.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.documents.is_empty()
+    }
+
+    pub fn ids(&self) -> impl Iterator<Item = &Uuid> {
+        self.documents.keys()
+    }
+
+    /// Every document, loading those not held.
+    pub fn values(&self) -> impl Iterator<Item = Arc<Document>> {
+        self.documents.keys().filter_map(|doc_id| self.get(doc_id))
+    }
+
+    /// Every document, loading those not held. Fails at the first that cannot be loaded.
+    pub fn try_values(&self) -> impl Iterator<Item = Result<Arc<Document>>> {
+        self.documents.keys().map(|doc_id| {
+            self.try_get(doc_id)?
+                .ok_or_else(|| anyhow!("Document {} is missing", doc_id))
+        })
+    }
+
+    fn cache(&self) -> MutexGuard<'_, DocumentCache> {
+        self.cache.lock().expect("document cache lock poisoned")
+    }
+}
+
+impl Default for DocumentStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl From<HashMap<Uuid, Document>> for DocumentStore {
+    fn from(documents: HashMap<Uuid, Document>) -> Self {
+        Self {
+            documents: documents
+                .into_iter()
+                .map(|(doc_id, document)| (doc_id, Slot::Held(Arc::new(document))))
+                .collect(),
+            ..Self::new()
+        }
+    }
+}
+
+impl fmt::Debug for DocumentStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let held = self
+            .documents
+            .values()
+            .filter(|slot| matches!(slot, Slot::Held(_)));
+        f.debug_struct("DocumentStore")
+            .field("documents", &self.documents.len())
+            .field("held", &held.count())
+            .finish()
+    }
+}
+
+/// Serializes as a map from id to document, like the `HashMap` it replaces.
+impl Serialize for DocumentStore {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.documents.len()))?;
+        for doc_id in self.documents.keys() {
+            let document = self
+                .try_get(doc_id)
+                .map_err(|e| S::Error::custom(format!("{:#}", e)))?
+                .ok_or_else(|| S::Error::custom(format!("document {} is missing", doc_id)))?;
+            map.serialize_entry(doc_id, &*document)?;
+        }
+        map.end()
+    }
+}
@@ -0,0 +1,246 @@
+use imbl::HashMap;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// What happens to a document that is a near-duplicate of one already indexed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DuplicateMode {
+    /// Index every document as is
+    Off,
+    /// Do not index near-duplicates at all
+    Skip,
+    /// Index near-duplicates but group them under the first copy, which collapses them
+    /// in search results
+    #[default]
+    Group,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DuplicateSettings {
+    pub mode: DuplicateMode,
+    /// Estimated share of word pairs two documents must have in common to count as
+    /// near-duplicates, between 0 and 1
+    pub threshold: f64,
+}
+
+impl Default for DuplicateSettings {
+    fn default() -> Self {
+        Self {
+            mode: DuplicateMode::default(),
+            threshold: 0.7,
+        }
+    }
+}
+
+/// What the index keeps about one fingerprinted document: its signature and, for a
+/// canonical document, the near-duplicates grouped under it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DuplicateEntry {
+    pub signature: Vec<u32>,
+    pub duplicates: Vec<Uuid>,
+}
+
+/// MinHash signatures of indexed documents and the near-duplicate groups they form.
+///
+/// Every group has one canonical document, the first copy that was indexed.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DuplicateIndex {
+    signatures: HashMap<Uuid, Vec<u32>>,
+    /// Per band of the signature, documents by the hash of that band (locality sensitive
+    /// hashing), to find candidates without comparing against every document
+    bands: Vec<HashMap<u64, Vec<Uuid>>>,
+    /// Duplicate -> canonical document
+    canonical: HashMap<Uuid, Uuid>,
+    /// Canonical document -> duplicates, in indexing order
+    members: HashMap<Uuid, Vec<Uuid>>,
+}
+
+impl DuplicateIndex {
+    /// Documents with fewer terms are not fingerprinted, short texts collide too easily.
+    const MIN_TOKENS: usize = 8;
+    const NUM_HASHES: usize = 64;
+    /// Signature slots per band; 16 bands of 4 find about 99% of pairs with 0.7 similarity
+    const BAND_ROWS: usize = 4;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// MinHash signature over the document's overlapping word pairs, or `None` for very
+    /// short texts.
+    pub fn signature(tokens: &[String]) -> Option<Vec<u32>> {
+        if tokens.len() < Self::MIN_TOKENS {
+            return None;
+        }
+
+        let shingles: Vec<u64> = tokens
+            .windows(2)
+            .map(|pair| Self::fnv1a(&pair.join(" ")))
+            .collect();
+
+        Some(
+            (0..Self::NUM_HASHES as u64)
+                .map(|seed| {
+                    shingles
+                        .iter()
+                        .map(|shingle| Self::mix(shingle ^ seed.wrapping_mul(0x9e3779b97f4a7c15)))
+                        .min()
+                        .unwrap_or(u64::MAX) as u32
+                })
+                .collect(),
+        )
+    }
+
+    /// Share of equal slots, an estimate of the Jaccard similarity of the two shingle sets.
+    pub fn similarity(a: &[u32], b: &[u32]) -> f64 {
+        let equal = a.iter().zip(b).filter(|(a, b)| a == b).count();
+        equal as f64 / a.len().max(1) as f64
+    }
+
+    /// The most similar canonical document with at least `threshold` similarity.
+    pub fn find(&self, signature: &[u32], threshold: f64) -> Option<Uuid> {
+        let mut best: Option<(f64, Uuid)> = None;
+
+        for (band, hash) in Self::band_hashes(signature) {
+            let Some(candidates) = self.bands.get(band).and_then(|ids| ids.get(&hash)) else {
+                continue;
+            };
+
+            for candidate in candidates {
+                if self.canonical.contains_key(candidate) {
+                    continue;
+                }
+                let similarity = Self::similarity(&self.signatures[candidate], signature);
+                let better = best.is_none_or(|(best_similarity, best_id)| {
+                    similarity > best_similarity
+                        || (similarity == best_similarity && *candidate < best_id)
+                });
+                if similarity >= threshold && better {
+                    best = Some((similarity, *candidate));
+                }
+            }
+        }
+
+        best.map(|(_, doc_id)| doc_id)
+    }
+
+    pub fn insert(&mut self, doc_id: Uuid, signature: Vec<u32>) {
+        for (band, hash) in Self::band_hashes(&signature) {
+            if self.bands.len() <= band {
+                self.bands.resize_with(band + 1, HashMap::new);
+            }
+            self.bands[band].entry(hash).or_default().push(doc_id);
+        }
+        self.signatures.insert(doc_id, signature);
+    }
+
+    /// The signature and group of a fingerprinted document, `None` for others.
+    pub fn entry(&self, doc_id: &Uuid) -> Option<DuplicateEntry> {
+        Some(DuplicateEntry {
+            signature: self.signatures.get(doc_id)?.clone(),
+            duplicates: self.duplicates_of(doc_id).to_vec(),
+        })
+    }
+
+    /// Restores a document from its `entry`.
+    pub fn insert_entry(&mut self, doc_id: Uuid, entry: DuplicateEntry) {
+        self.insert(doc_id, entry.signature);
+        for duplicate in &entry.duplicates {
+            self.canonical.insert(*duplicate, doc_id);
+        }
+        if !entry.duplicates.is_empty() {
+            self.members.insert(doc_id, entry.duplicates);
+        }
+    }
+
+    /// Records `duplicate` as a near-duplicate of the canonical document `canonical`.
+    pub fn group(&mut self, duplicate: Uuid, canonical: Uuid) {
+        self.canonical.insert(duplicate, canonical);
+        self.members.entry(canonical).or_default().push(duplicate);
+    }
+
+    /// Forgets a document. When it was canonical, the next copy in its group takes over.
+    /// Returns the canonical document whose group changed, if any.
+    pub fn remove(&mut self, doc_id: &Uuid) -> Option<Uuid> {
+        if let Some(signature) = self.signatures.remove(doc_id) {
+            for (band, hash) in Self::band_hashes(&signature) {
+                if let Some(ids) = self.bands.get_mut(band).and_then(|ids| ids.get_mut(&hash)) {
+                    ids.retain(|id| id != doc_id);
+                    if ids.is_empty() {
+                        self.bands[band].remove(&hash);
+                    }
+                }
+            }
+        }
+
+        if let Some(canonical) = self.canonical.remove(doc_id) {
+            if let Some(members) = self.members.get_mut(&canonical) {
+                members.retain(|id| id != doc_id);
+                if members.is_empty() {
+                    self.members.remove(&canonical);
+                }
+            }
+            Some(canonical)
+        } else if let Some(mut members) = self.members.remove(doc_id) {
+            let successor = members.remove(0);
+            self.canonical.remove(&successor);
+            for member in &members {
+                self.canonical.insert(*member, successor);
+            }
+            if !members.is_empty() {
+                self.members.insert(successor, members);
+            }
+            Some(successor)
+        } else {
+            None
+        }
+    }
+
+    /// The canonical document of the group `doc_id` belongs to, itself if it has no copies.
+    pub fn canonical_of(&self, doc_id: &Uuid) -> Uuid {
+        self.canonical.get(doc_id).copied().unwrap_or(*doc_id)
+    }
+
+    /// Near-duplicates grouped under a canonical document.
+    pub fn duplicates_of(&self, canonical: &Uuid) -> &[Uuid] {
+        self.members
+            .get(canonical)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// Number of other documents in the group of `doc_id`.
+    pub fn group_size(&self, doc_id: &Uuid) -> usize {
+        self.duplicates_of(&self.canonical_of(doc_id)).len()
+    }
+
+    fn band_hashes(signature: &[u32]) -> impl Iterator<Item = (usize, u64)> + '_ {
+        signature
+            .chunks(Self::BAND_ROWS)
+            .enumerate()
+            .map(|(band, rows)| {
+                let hash = rows
+                    .iter()
+                    .fold(0u64, |hash, row| Self::mix(hash ^ *row as u64));
+                (band, hash)
+            })
+    }
+
+    /// SplitMix64 finalizer, spreads the bits of a hash before taking minima.
+    fn mix(value: u64) -> u64 {
+        let mut z = value.wrapping_add(0x9e3779b97f4a7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// FNV-1a, stable across Rust versions unlike the std hasher, since signatures are
+    /// persisted.
+    fn fnv1a(text: &str) -> u64 {
+        text.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        })
+    }
+}
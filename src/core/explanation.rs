@@ -0,0 +1,49 @@
+use serde::Serialize;
+use std::fmt;
+
+/// A node in the breakdown of how a document's score was computed.
+///
+/// The value of a node is derived from its details as stated in its description
+/// (a sum, a product, ...), leaves are the raw statistics.
+#[derive(Debug, Clone, Serialize)]
+pub struct Explanation {
+    pub value: f64,
+    pub description: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub details: Vec<Explanation>,
+}
+
+impl Explanation {
+    pub fn new(value: f64, description: impl Into<String>) -> Self {
+        Self {
+            value,
+            description: description.into(),
+            details: Vec::new(),
+        }
+    }
+
+    pub fn with_details(mut self, details: Vec<Explanation>) -> Self {
+        self.details = details;
+        self
+    }
+
+    fn write_indented(&self, f: &mut fmt::Formatter<'_>, depth: usize) -> fmt::Result {
+        writeln!(
+            f,
+            "{}{:.4} = {}",
+            "  ".repeat(depth),
+            self.value,
+            self.description
+        )?;
+        for detail in &self.details {
+            detail.write_indented(f, depth + 1)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Explanation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write_indented(f, 0)
+    }
+}
@@ -0,0 +1,238 @@
+use crate::core::Explanation;
+use anyhow::{Result, anyhow};
+use std::collections::HashMap;
+use std::hash::Hash;
+use uuid::Uuid;
+
+/// How lexical and vector hits of a hybrid query are combined into one score.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fusion {
+    /// Reciprocal rank fusion, `sum of 1 / (k + rank)` over both result lists
+    Rrf { k: f64 },
+    /// `alpha * lexical / max lexical + (1 - alpha) * cosine`
+    Weighted { alpha: f64 },
+}
+
+impl Fusion {
+    pub const DEFAULT_RRF_K: f64 = 60.0;
+
+    /// Parses `rrf`, `rrf:<k>` or `weighted:<alpha>`.
+    pub fn parse(spec: &str) -> Result<Self> {
+        let (name, value) = match spec.trim().split_once(':') {
+            Some((name, value)) => (name.trim().to_lowercase(), Some(value.trim())),
+            None => (spec.trim().to_lowercase(), None),
+        };
+        let value = value
+            .map(|value| {
+                value
+                    .parse::<f64>()
+                    .map_err(|_| anyhow!("Invalid fusion parameter '{}'", value))
+            })
+            .transpose()?;
+
+        match (name.as_str(), value) {
+            ("rrf", k) => {
+                let k = k.unwrap_or(Self::DEFAULT_RRF_K);
+                if k < 0.0 {
+                    return Err(anyhow!("RRF k must not be negative, got {}", k));
+                }
+                Ok(Fusion::Rrf { k })
+            }
+            ("weighted", alpha) => {
+                let alpha = alpha.unwrap_or(0.5);
+                if !(0.0..=1.0).contains(&alpha) {
+                    return Err(anyhow!(
+                        "Weighted alpha must be between 0 and 1, got {}",
+                        alpha
+                    ));
+                }
+                Ok(Fusion::Weighted { alpha })
+            }
+            _ => Err(anyhow!(
+                "Invalid fusion '{}', expected rrf, rrf:<k> or weighted:<alpha>",
+                spec
+            )),
+        }
+    }
+}
+
+impl Default for Fusion {
+    fn default() -> Self {
+        Fusion::Rrf {
+            k: Self::DEFAULT_RRF_K,
+        }
+    }
+}
+
+/// Lexical and vector hits of one query, keyed by document with their 1-based rank and score.
+/// Hits of several indexes are keyed by index and document, as ids may repeat across them.
+#[derive(Debug)]
+pub struct HybridHits<K = Uuid> {
+    lexical: HashMap<K, (usize, f64)>,
+    vector: HashMap<K, (usize, f64)>,
+    max_lexical: f64,
+}
+
+impl<K: Copy + Eq + Hash + Ord> HybridHits<K> {
+    /// Both lists must be sorted best first.
+    pub fn new(lexical: &[(K, f64)], vector: &[(K, f64)]) -> Self {
+        let ranked = |hits: &[(K, f64)]| {
+            hits.iter()
+                .enumerate()
+                .map(|(i, (doc_id, score))| (*doc_id, (i + 1, *score)))
+                .collect()
+        };
+
+        Self {
+            lexical: ranked(lexical),
+            vector: ranked(vector),
+            max_lexical: lexical.iter().map(|(_, score)| *score).fold(0.0, f64::max),
+        }
+    }
+
+    /// Combined scores of every document in either list. Without lexical hits, e.g. for a
+    /// vector-only query, documents keep their cosine similarity.
+    pub fn fuse(&self, fusion: &Fusion) -> Vec<(K, f64)> {
+        let mut doc_ids: Vec<K> = self
+            .lexical
+            .keys()
+            .chain(self.vector.keys())
+            .copied()
+            .collect();
+        doc_ids.sort_unstable();
+        doc_ids.dedup();
+
+        doc_ids
+            .into_iter()
+            .map(|doc_id| (doc_id, self.score(fusion, &doc_id)))
+            .collect()
+    }
+
+    fn score(&self, fusion: &Fusion, doc_id: &K) -> f64 {
+        if self.lexical.is_empty() {
+            return self.cosine(doc_id);
+        }
+
+        match fusion {
+            Fusion::Rrf { k } => {
+                let reciprocal = |hits: &HashMap<K, (usize, f64)>| {
+                    hits.get(doc_id)
+                        .map_or(0.0, |(rank, _)| 1.0 / (k + *rank as f64))
+                };
+                reciprocal(&self.lexical) + reciprocal(&self.vector)
+            }
+            Fusion::Weighted { alpha } => {
+                alpha * self.normalized_lexical(doc_id) + (1.0 - alpha) * self.cosine(doc_id)
+            }
+        }
+    }
+
+    /// Mirrors `fuse` for one document. `lexical` is the index explanation of its text score.
+    pub fn explain(
+        &self,
+        fusion: &Fusion,
+        doc_id: &K,
+        lexical: Option<Explanation>,
+    ) -> Explanation {
+        let cosine = Explanation::new(self.cosine(doc_id), "cosine similarity to query vector");
+        if self.lexical.is_empty() {
+            return cosine;
+        }
+
+        let score = self.score(fusion, doc_id);
+        match fusion {
+            Fusion::Rrf { k } => {
+                let mut details = Vec::new();
+                if let Some((rank, _)) = self.lexical.get(doc_id) {
+                    details.push(
+                        Explanation::new(
+                            1.0 / (k + *rank as f64),
+                            format!("1 / (k + rank) with k = {}, lexical rank {}", k, rank),
+                        )
+                        .with_details(lexical.into_iter().collect()),
+                    );
+                }
+                if let Some((rank, _)) = self.vector.get(doc_id) {
+                    details.push(
+                        Explanation::new(
+                            1.0 / (k + *rank as f64),
+                            format!("1 / (k + rank) with k = {}, vector rank {}", k, rank),
+                        )
+                        .with_details(vec![cosine]),
+                    );
+                }
+
+                Explanation::new(score, "reciprocal rank fusion, sum of:").with_details(details)
+            }
+            Fusion::Weighted { alpha } => {
+                let mut normalized = Explanation::new(
+                    self.normalized_lexical(doc_id),
+                    "lexical score divided by best lexical score",
+                );
+                if let Some(lexical) = lexical {
+                    normalized = normalized.with_details(vec![
+                        lexical,
+                        Explanation::new(self.max_lexical, "best lexical score"),
+                    ]);
+                }
+
+                Explanation::new(
+                    score,
+                    "weighted blend, alpha * lexical + (1 - alpha) * cosine",
+                )
+                .with_details(vec![
+                    Explanation::new(*alpha, "alpha"),
+                    normalized,
+                    cosine,
+                ])
+            }
+        }
+    }
+
+    fn normalized_lexical(&self, doc_id: &K) -> f64 {
+        match self.lexical.get(doc_id) {
+            Some((_, score)) if self.max_lexical > 0.0 => score / self.max_lexical,
+            _ => 0.0,
+        }
+    }
+
+    /// Negative similarities count as no similarity at all.
+    fn cosine(&self, doc_id: &K) -> f64 {
+        self.vector
+            .get(doc_id)
+            .map_or(0.0, |(_, similarity)| similarity.max(0.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_fusion_specs() {
+        assert_eq!(Fusion::parse("rrf").unwrap(), Fusion::default());
+        assert_eq!(
+            Fusion::parse(" RRF : 10 ").unwrap(),
+            Fusion::Rrf { k: 10.0 }
+        );
+        assert_eq!(
+            Fusion::parse("weighted").unwrap(),
+            Fusion::Weighted { alpha: 0.5 }
+        );
+        assert_eq!(
+            Fusion::parse("weighted:0.8").unwrap(),
+            Fusion::Weighted { alpha: 0.8 }
+        );
+
+        for spec in [
+            "",
+            "sum",
+            "rrf:-1",
+            "rrf:ten",
+            "weighted:1.5",
+            "weighted:-0.1",
+        ] {
+            assert!(Fusion::parse(spec).is_err(), "{:?}", spec);
+        }
+    }
+}
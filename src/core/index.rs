@@ -1,18 +1,28 @@
-use crate::core::{Document, Tokenizer};
+use crate::core::{
+    AnchorIndex, ChangeLog, CollectionStats, DocValues, Document, DocumentPassages, DocumentStore,
+    DuplicateIndex, DuplicateMode, Explanation, FieldStats, IndexSettings, LinkGraph, Passage,
+    PassageIndex, PassageTerms, PostingStore, QueryTerm, ScoringModel, Tokenizer, VectorIndex,
+};
+use anyhow::Result;
+use rayon::prelude::*;
 use serde::{Deserialize, Deserializer, Serialize};
+use std::borrow::Cow;
 use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
 use uuid::Uuid;
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Documents containing a term. Frequencies are kept in a persistent map, so changing the
+/// postings of a copy of the index does not copy them all.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PostingList {
-    pub term_frequency: HashMap<Uuid, usize>, // doc_id -> frequency
+    pub term_frequency: imbl::HashMap<Uuid, usize>, // doc_id -> frequency
     pub document_frequency: usize,
 }
 
 impl PostingList {
     pub fn new() -> Self {
         Self {
-            term_frequency: HashMap::new(),
+            term_frequency: imbl::HashMap::new(),
             document_frequency: 0,
         }
     }
@@ -30,11 +40,37 @@ impl PostingList {
     }
 }
 
-#[derive(Debug, Serialize)]
+impl Default for PostingList {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
 pub struct InvertedIndex {
-    pub index: HashMap<String, PostingList>,
-    pub documents: HashMap<Uuid, Document>,
+    pub index: PostingStore,
+    pub documents: DocumentStore,
     pub total_documents: usize,
+    pub doc_values: DocValues,
+    /// Number of indexed terms per document, used for BM25 length normalization
+    pub doc_lengths: imbl::HashMap<Uuid, usize>,
+    pub total_tokens: usize,
+    pub settings: IndexSettings,
+    pub link_graph: LinkGraph,
+    /// PageRank of crawled pages scaled so the best linked page has 1.0
+    pub authority: imbl::HashMap<Uuid, f64>,
+    pub anchors: AnchorIndex,
+    /// Static boosts of pinned or front matter boosted documents, only those other than 1.0
+    pub boosts: imbl::HashMap<Uuid, f64>,
+    pub vectors: VectorIndex,
+    pub duplicates: DuplicateIndex,
+    /// Long documents are ranked by their best passage instead of as a whole
+    pub passages: PassageIndex,
+    /// What changed since the index was created or loaded, for incremental saves
+    #[serde(skip)]
+    pub changes: ChangeLog,
+    #[serde(skip)]
+    doc_ids_by_path: imbl::HashMap<String, Uuid>,
     #[serde(skip)]
     tokenizer: Tokenizer,
 }
@@ -49,117 +85,816 @@ impl<'de> Deserialize<'de> for InvertedIndex {
             index: HashMap<String, PostingList>,
             documents: HashMap<Uuid, Document>,
             total_documents: usize,
+            doc_values: DocValues,
+            doc_lengths: imbl::HashMap<Uuid, usize>,
+            total_tokens: usize,
+            settings: IndexSettings,
+            link_graph: LinkGraph,
+            authority: imbl::HashMap<Uuid, f64>,
+            anchors: AnchorIndex,
+            boosts: imbl::HashMap<Uuid, f64>,
+            vectors: VectorIndex,
+            duplicates: DuplicateIndex,
+            passages: PassageIndex,
         }
 
         let data = InvertedIndexData::deserialize(deserializer)?;
+        let doc_ids_by_path = data
+            .documents
+            .values()
+            .map(|doc| (doc.path.clone(), doc.id))
+            .collect();
 
         Ok(InvertedIndex {
-            index: data.index,
-            documents: data.documents,
+            index: PostingStore::from(data.index),
+            documents: DocumentStore::from(data.documents),
             total_documents: data.total_documents,
-            tokenizer: Tokenizer::new(), // Always create a fresh tokenizer
+            doc_values: data.doc_values,
+            doc_lengths: data.doc_lengths,
+            total_tokens: data.total_tokens,
+            // Always create a fresh tokenizer matching the stored analyzer settings
+            tokenizer: Tokenizer::with_config(data.settings.analyzer.clone()),
+            settings: data.settings,
+            link_graph: data.link_graph,
+            authority: data.authority,
+            anchors: data.anchors,
+            boosts: data.boosts,
+            vectors: data.vectors,
+            duplicates: data.duplicates,
+            passages: data.passages,
+            changes: ChangeLog::default(),
+            doc_ids_by_path,
         })
     }
 }
 
+/// Everything about an index except its postings and documents, for storages that keep
+/// those under keys of their own.
+#[derive(Debug, Default)]
+pub struct IndexParts {
+    pub total_documents: usize,
+    pub doc_values: DocValues,
+    pub doc_lengths: imbl::HashMap<Uuid, usize>,
+    pub total_tokens: usize,
+    pub settings: IndexSettings,
+    pub link_graph: LinkGraph,
+    pub authority: imbl::HashMap<Uuid, f64>,
+    pub anchors: AnchorIndex,
+    pub boosts: imbl::HashMap<Uuid, f64>,
+    pub vectors: VectorIndex,
+    pub duplicates: DuplicateIndex,
+    pub passages: PassageIndex,
+}
+
+/// The counters and settings of an index, for storages that keep the per-document and
+/// per-page data under keys of their own.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct IndexMeta<'a> {
+    pub total_documents: usize,
+    pub total_tokens: usize,
+    pub settings: Cow<'a, IndexSettings>,
+    pub vector_dimensions: usize,
+    pub vector_entry_point: Option<Uuid>,
+}
+
 impl InvertedIndex {
+    /// Title and content are indexed together as a single text field.
+    const TEXT_FIELD: &'static str = "text";
+
     pub fn new() -> Self {
+        Self::with_settings(IndexSettings::default())
+    }
+
+    pub fn with_settings(settings: IndexSettings) -> Self {
         Self {
-            index: HashMap::new(),
-            documents: HashMap::new(),
+            index: PostingStore::new(),
+            documents: DocumentStore::new(),
             total_documents: 0,
-            tokenizer: Tokenizer::new(),
+            doc_values: DocValues::new(),
+            doc_lengths: imbl::HashMap::new(),
+            total_tokens: 0,
+            tokenizer: Tokenizer::with_config(settings.analyzer.clone()),
+            settings,
+            link_graph: LinkGraph::new(),
+            authority: imbl::HashMap::new(),
+            anchors: AnchorIndex::default(),
+            boosts: imbl::HashMap::new(),
+            vectors: VectorIndex::new(),
+            duplicates: DuplicateIndex::new(),
+            passages: PassageIndex::new(),
+            changes: ChangeLog::default(),
+            doc_ids_by_path: imbl::HashMap::new(),
         }
     }
 
-    pub fn add_document(&mut self, document: Document) {
-        let doc_id = document.id;
-        let tokens = self.tokenizer.tokenize(&document.get_text());
+    /// Reassembles an index stored in parts. Postings and documents may only be listed in
+    /// `index` and `documents` and loaded later.
+    pub fn from_parts(parts: IndexParts, index: PostingStore, documents: DocumentStore) -> Self {
+        let doc_values = parts.doc_values;
+        let doc_ids_by_path = documents
+            .ids()
+            .filter_map(|doc_id| {
+                let path = doc_values.get("path", doc_id)?.first()?;
+                Some((path.clone(), *doc_id))
+            })
+            .collect();
 
-        // Count term frequencies in this document
-        let mut term_freq: HashMap<String, usize> = HashMap::new();
-        for token in tokens {
-            *term_freq.entry(token).or_insert(0) += 1;
+        InvertedIndex {
+            index,
+            documents,
+            total_documents: parts.total_documents,
+            doc_values,
+            doc_lengths: parts.doc_lengths,
+            total_tokens: parts.total_tokens,
+            tokenizer: Tokenizer::with_config(parts.settings.analyzer.clone()),
+            settings: parts.settings,
+            link_graph: parts.link_graph,
+            authority: parts.authority,
+            anchors: parts.anchors,
+            boosts: parts.boosts,
+            vectors: parts.vectors,
+            duplicates: parts.duplicates,
+            passages: parts.passages,
+            changes: ChangeLog::default(),
+            doc_ids_by_path,
+        }
+    }
+
+    /// Borrows the counters and settings.
+    pub fn meta(&self) -> IndexMeta<'_> {
+        IndexMeta {
+            total_documents: self.total_documents,
+            total_tokens: self.total_tokens,
+            settings: Cow::Borrowed(&self.settings),
+            vector_dimensions: self.vectors.dimensions(),
+            vector_entry_point: self.vectors.entry_point(),
+        }
+    }
+
+    /// The passages of a split document with their postings, see `PassageIndex::entry`.
+    /// Fails if the document cannot be loaded.
+    pub fn passage_entry(&self, doc_id: &Uuid) -> Result<Option<DocumentPassages>> {
+        if !self.passages.contains(doc_id) {
+            return Ok(None);
+        }
+        let Some(document) = self.documents.try_get(doc_id)? else {
+            return Ok(None);
+        };
+        let terms: HashSet<String> = self
+            .tokenizer
+            .tokenize(&document.get_text())
+            .into_iter()
+            .collect();
+        Ok(self.passages.entry(doc_id, &terms))
+    }
+
+    /// Indexes a document and returns `true`, or returns `false` without indexing it when it
+    /// is a near-duplicate and duplicates are configured to be skipped. See `add_documents`.
+    pub fn add_document(&mut self, document: Document) -> Result<bool> {
+        Ok(self.add_documents(vec![document])? == 1)
+    }
+
+    /// Indexes many documents at once, like calling `add_document` for each in order, and
+    /// returns how many were not skipped as near-duplicates. An indexed document with the id
+    /// of a new one is replaced by it, and of several new documents with the same id only the
+    /// last is indexed. Tokenizing and counting runs on all cores, each thread
+    /// collecting postings for its share of the documents; those partial postings are then
+    /// merged into the index.
+    ///
+    /// Fails without changing the index when replaced documents or the postings of any
+    /// affected term cannot be loaded.
+    pub fn add_documents(&mut self, documents: Vec<Document>) -> Result<usize> {
+        // Each document would replace the one before it with the same id
+        let last: HashMap<Uuid, usize> = documents
+            .iter()
+            .enumerate()
+            .map(|(position, document)| (document.id, position))
+            .collect();
+        let documents: Vec<Document> = documents
+            .into_iter()
+            .enumerate()
+            .filter(|(position, document)| last[&document.id] == *position)
+            .map(|(_, document)| document)
+            .collect();
+
+        let tokenizer = &self.tokenizer;
+        let settings = &self.settings;
+        let analyzed: Vec<AnalyzedDocument> = documents
+            .into_par_iter()
+            .map(|document| AnalyzedDocument::new(document, tokenizer, settings))
+            .collect();
+
+        // Load everything that changes first, so nothing has changed when loading fails
+        let replaced: Vec<Uuid> = analyzed
+            .iter()
+            .map(|analyzed| analyzed.document.id)
+            .filter(|doc_id| self.documents.contains(doc_id))
+            .collect();
+        self.documents.hold(&replaced)?;
+        let replaced_terms: HashSet<String> = replaced
+            .iter()
+            .filter_map(|doc_id| self.documents.get(doc_id))
+            .flat_map(|document| self.tokenizer.tokenize(&document.get_text()))
+            .collect();
+        let terms: HashSet<&str> = analyzed
+            .iter()
+            .flat_map(|analyzed| analyzed.term_freq.keys())
+            .chain(&replaced_terms)
+            .map(String::as_str)
+            .collect();
+        self.index.hold(terms)?;
+        for doc_id in &replaced {
+            self.remove_document(doc_id)?;
         }
 
-        // Add to inverted index
-        for (term, freq) in term_freq {
-            let posting_list = self.index.entry(term).or_insert_with(PostingList::new);
-            posting_list.add_document(doc_id, freq);
+        self.changes.begin();
+        // Whether a document duplicates another depends on those before it
+        let mut analyzed: Vec<AnalyzedDocument> = analyzed
+            .into_iter()
+            .filter(|analyzed| self.register_duplicate(analyzed))
+            .collect();
+
+        let partial_postings: Vec<HashMap<String, Vec<(Uuid, usize)>>> = analyzed
+            .par_iter_mut()
+            .fold(HashMap::new, |mut postings, analyzed| {
+                let doc_id = analyzed.document.id;
+                for (term, freq) in std::mem::take(&mut analyzed.term_freq) {
+                    postings
+                        .entry(term)
+                        .or_insert_with(Vec::new)
+                        .push((doc_id, freq));
+                }
+                postings
+            })
+            .collect();
+        for postings in partial_postings {
+            for (term, entries) in postings {
+                self.changes.term(&term);
+                let posting_list = self.index.entry(term);
+                for (doc_id, freq) in entries {
+                    posting_list.add_document(doc_id, freq);
+                }
+            }
+        }
+
+        let count = analyzed.len();
+        for analyzed in analyzed {
+            self.store(analyzed);
         }
+        Ok(count)
+    }
+
+    /// Records the document's MinHash signature, returning `false` when it is a near-duplicate
+    /// that should be skipped.
+    fn register_duplicate(&mut self, analyzed: &AnalyzedDocument) -> bool {
+        let settings = &self.settings.duplicates;
+        let document = &analyzed.document;
+        let Some(signature) = &analyzed.signature else {
+            return true;
+        };
 
-        self.documents.insert(doc_id, document);
+        match self.duplicates.find(signature, settings.threshold) {
+            Some(canonical) if settings.mode == DuplicateMode::Skip => {
+                log::info!(
+                    "Skipping {}, near-duplicate of {}",
+                    document.path,
+                    self.documents
+                        .get(&canonical)
+                        .map_or_else(String::new, |doc| doc.path.clone())
+                );
+                return false;
+            }
+            Some(canonical) => {
+                self.duplicates.insert(document.id, signature.clone());
+                self.duplicates.group(document.id, canonical);
+                self.changes.links(canonical);
+            }
+            None => self.duplicates.insert(document.id, signature.clone()),
+        }
+        true
+    }
+
+    /// Stores everything about an analyzed document except its postings.
+    fn store(&mut self, analyzed: AnalyzedDocument) {
+        let AnalyzedDocument {
+            document,
+            length,
+            passages,
+            ..
+        } = analyzed;
+        let doc_id = document.id;
+
+        self.doc_lengths.insert(doc_id, length);
+        self.total_tokens += length;
+        if let Some(passages) = passages {
+            self.passages.insert_terms(doc_id, passages);
+        }
+        if document.boost != 1.0 {
+            self.boosts.insert(doc_id, document.boost);
+        }
+        if let Some(embedding) = &document.embedding {
+            match self.vectors.insert(doc_id, embedding) {
+                Ok(linked) => linked.into_iter().for_each(|id| self.changes.links(id)),
+                Err(e) => log::warn!("Skipping embedding of {}: {}", document.path, e),
+            }
+        }
+        self.doc_values.insert(&document);
+        self.doc_ids_by_path.insert(document.path.clone(), doc_id);
+        self.documents.insert(document);
+        self.changes.document(doc_id);
         self.total_documents += 1;
     }
 
-    pub fn remove_document(&mut self, doc_id: &Uuid) -> Option<Document> {
-        if let Some(document) = self.documents.remove(doc_id) {
-            let tokens = self.tokenizer.tokenize(&document.get_text());
-            let unique_tokens: HashSet<String> = tokens.into_iter().collect();
+    /// Removes a document and returns it, `None` if it is unknown. Fails without changing
+    /// the index when the document or the postings of its terms cannot be loaded.
+    pub fn remove_document(&mut self, doc_id: &Uuid) -> Result<Option<Document>> {
+        self.documents.hold([doc_id])?;
+        let Some(document) = self.documents.get(doc_id) else {
+            return Ok(None);
+        };
+        let tokens = self.tokenizer.tokenize(&document.get_text());
+        let unique_tokens: HashSet<String> = tokens.into_iter().collect();
+        self.index.hold(unique_tokens.iter().map(String::as_str))?;
+        drop(document);
+        let Some(document) = self.documents.remove(doc_id)? else {
+            return Ok(None);
+        };
 
-            for token in unique_tokens {
-                if let Some(posting_list) = self.index.get_mut(&token) {
-                    posting_list.remove_document(doc_id);
-                    if posting_list.document_frequency == 0 {
-                        self.index.remove(&token);
-                    }
+        self.changes.begin();
+        self.changes.document(*doc_id);
+        self.passages.remove(doc_id, &unique_tokens);
+        for token in unique_tokens {
+            self.changes.term(&token);
+            if let Some(posting_list) = self.index.get_mut(&token) {
+                posting_list.remove_document(doc_id);
+                if posting_list.document_frequency == 0 {
+                    self.index.remove(&token);
                 }
             }
+        }
 
-            self.total_documents -= 1;
-            Some(document)
-        } else {
-            None
+        self.doc_values.remove(doc_id);
+        // A newer document may have taken over the path
+        if self.doc_ids_by_path.get(&document.path) == Some(doc_id) {
+            self.doc_ids_by_path.remove(&document.path);
+        }
+        self.authority.remove(doc_id);
+        self.boosts.remove(doc_id);
+        for linking in self.vectors.remove(doc_id) {
+            self.changes.links(linking);
+        }
+        if let Some(canonical) = self.duplicates.remove(doc_id) {
+            self.changes.links(canonical);
         }
+        if let Some(length) = self.doc_lengths.remove(doc_id) {
+            self.total_tokens -= length;
+        }
+        self.total_documents -= 1;
+        Ok(Some(document))
     }
 
-    pub fn search(&self, query: &str) -> Vec<(Uuid, f64)> {
-        let query_tokens = self.tokenizer.tokenize(query);
-        if query_tokens.is_empty() {
+    /// Scores every document matching `query`. Searching one shard of a larger collection,
+    /// pass the `collection` statistics of all shards so scores are comparable across them.
+    pub fn search(&self, query: &str, collection: Option<&CollectionStats>) -> Vec<(Uuid, f64)> {
+        let query_terms = QueryTerm::parse(query, &self.tokenizer);
+        if query_terms.is_empty() {
             return Vec::new();
         }
 
+        let scoring = &self.settings.scoring;
         let mut scores: HashMap<Uuid, f64> = HashMap::new();
+        let mut passage_scores: HashMap<Uuid, Vec<f64>> = HashMap::new();
+
+        for QueryTerm { term: token, boost } in query_terms {
+            let text_stats = self.text_stats(collection).boosted(boost);
+            let anchor_stats = self.anchors.stats(self.settings.anchor_boost * boost);
 
-        for token in query_tokens {
             if let Some(posting_list) = self.index.get(&token) {
-                let idf = self.calculate_idf(posting_list.document_frequency);
+                let df =
+                    Self::document_frequency(&token, posting_list.document_frequency, collection);
 
                 for (doc_id, tf) in &posting_list.term_frequency {
-                    // Fixed TF calculation: Use log normalization with +1 to avoid zero
-                    let tf_score = if *tf > 0 {
-                        1.0 + (*tf as f64).ln() // This ensures tf_score > 1.0
-                    } else {
-                        0.0
-                    };
-
-                    let tf_idf = tf_score * idf;
-                    *scores.entry(*doc_id).or_insert(0.0) += tf_idf;
+                    if self.passages.contains(doc_id) {
+                        continue;
+                    }
+                    let length = self.doc_lengths.get(doc_id).copied().unwrap_or(0);
+                    *scores.entry(*doc_id).or_insert(0.0) +=
+                        scoring.term_weight(&text_stats, *tf, df, length);
+                }
+
+                for (doc_id, hits) in self.passages.postings(&token).into_iter().flatten() {
+                    let doc_scores = passage_scores
+                        .entry(*doc_id)
+                        .or_insert_with(|| vec![0.0; self.passages.passages(doc_id).len()]);
+                    for (passage, tf) in hits {
+                        let length = self.passages.length(doc_id, *passage);
+                        doc_scores[*passage] += scoring.term_weight(&text_stats, *tf, df, length);
+                    }
+                }
+            }
+
+            if let Some(targets) = self.anchors.postings(&token) {
+                let df = targets.len();
+
+                for (target, tf) in targets {
+                    if let Some(doc_id) = self.doc_ids_by_path.get(target) {
+                        let length = self.anchors.length(target);
+                        *scores.entry(*doc_id).or_insert(0.0) +=
+                            scoring.term_weight(&anchor_stats, *tf, df, length);
+                    }
                 }
             }
         }
 
-        let mut results: Vec<(Uuid, f64)> = scores.into_iter().collect();
-        results.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+        // Split documents score as their best passage
+        for (doc_id, doc_scores) in passage_scores {
+            *scores.entry(doc_id).or_insert(0.0) += doc_scores.into_iter().fold(0.0, f64::max);
+        }
+
+        let mut results: Vec<(Uuid, f64)> = scores
+            .into_iter()
+            .map(|(doc_id, score)| (doc_id, score * self.score_factor(&doc_id)))
+            .collect();
+        results.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
         results
     }
 
-    fn calculate_idf(&self, document_frequency: usize) -> f64 {
-        if document_frequency == 0 {
-            0.0
+    /// Breaks down the score `search` gives `doc_id` for `query`, term by term and field by field.
+    ///
+    /// Returns `None` when the document does not match the query.
+    pub fn explain(
+        &self,
+        query: &str,
+        doc_id: &Uuid,
+        collection: Option<&CollectionStats>,
+    ) -> Option<Explanation> {
+        let scoring = &self.settings.scoring;
+        let path = &self.documents.get(doc_id)?.path;
+        let query_terms = QueryTerm::parse(query, &self.tokenizer);
+        let best_passage = self.best_passage_number(&query_terms, doc_id, collection);
+        let mut details = Vec::new();
+        let mut passage_details = Vec::new();
+
+        for QueryTerm { term: token, boost } in query_terms {
+            let text_stats = self.text_stats(collection).boosted(boost);
+            let anchor_stats = self.anchors.stats(self.settings.anchor_boost * boost);
+
+            if let Some(posting_list) = self.index.get(&token) {
+                let df =
+                    Self::document_frequency(&token, posting_list.document_frequency, collection);
+
+                if let Some((passage, _)) = best_passage {
+                    let tf = self
+                        .passages
+                        .postings(&token)
+                        .and_then(|documents| documents.get(doc_id))
+                        .and_then(|hits| hits.iter().find(|(number, _)| *number == passage))
+                        .map(|(_, tf)| *tf);
+                    if let Some(tf) = tf {
+                        let length = self.passages.length(doc_id, passage);
+                        passage_details.push(scoring.explain_term(
+                            &text_stats,
+                            &token,
+                            tf,
+                            df,
+                            length,
+                        ));
+                    }
+                } else if !self.passages.contains(doc_id)
+                    && let Some(tf) = posting_list.term_frequency.get(doc_id)
+                {
+                    let length = self.doc_lengths.get(doc_id).copied().unwrap_or(0);
+                    details.push(scoring.explain_term(&text_stats, &token, *tf, df, length));
+                }
+            }
+
+            if let Some(targets) = self.anchors.postings(&token)
+                && let Some(tf) = targets.get(path)
+            {
+                details.push(scoring.explain_term(
+                    &anchor_stats,
+                    &token,
+                    *tf,
+                    targets.len(),
+                    self.anchors.length(path),
+                ));
+            }
+        }
+
+        if let Some((number, score)) = best_passage {
+            let passage = &self.passages.passages(doc_id)[number];
+            let description = match &passage.heading {
+                Some(heading) => format!("best passage, in section '{}', sum of:", heading),
+                None => "best passage, sum of:".to_string(),
+            };
+            details.insert(
+                0,
+                Explanation::new(score, description).with_details(passage_details),
+            );
+        }
+
+        if details.is_empty() {
+            return None;
+        }
+
+        let text_score: f64 = details.iter().map(|detail| detail.value).sum();
+        let text = Explanation::new(text_score, "sum of:").with_details(details);
+
+        let mut factors = Vec::new();
+
+        let authority = self.authority_factor(doc_id);
+        if authority != 1.0 {
+            factors.push(
+                Explanation::new(authority, "authority factor, 1 + weight * authority")
+                    .with_details(vec![
+                        Explanation::new(self.settings.authority_weight, "weight"),
+                        Explanation::new(
+                            self.authority.get(doc_id).copied().unwrap_or(0.0),
+                            "authority, PageRank relative to the best linked page",
+                        ),
+                    ]),
+            );
+        }
+
+        let boost = self.boost(doc_id);
+        if boost != 1.0 {
+            factors.push(Explanation::new(boost, "document boost"));
+        }
+
+        if factors.is_empty() {
+            return Some(text);
+        }
+
+        let score = factors.iter().fold(text_score, |score, f| score * f.value);
+        factors.insert(0, text);
+        Some(Explanation::new(score, "product of:").with_details(factors))
+    }
+
+    /// The passage of a split document that matches `query` best, `None` for documents
+    /// ranked as a whole or without a matching passage.
+    pub fn best_passage(
+        &self,
+        query: &str,
+        doc_id: &Uuid,
+        collection: Option<&CollectionStats>,
+    ) -> Option<&Passage> {
+        let query_terms = QueryTerm::parse(query, &self.tokenizer);
+        let (number, _) = self.best_passage_number(&query_terms, doc_id, collection)?;
+        self.passages.passages(doc_id).get(number)
+    }
+
+    /// Number and text score of the best matching passage, mirroring `search`. The first
+    /// passage wins ties.
+    fn best_passage_number(
+        &self,
+        query_terms: &[QueryTerm],
+        doc_id: &Uuid,
+        collection: Option<&CollectionStats>,
+    ) -> Option<(usize, f64)> {
+        let passages = self.passages.passages(doc_id);
+        if passages.is_empty() {
+            return None;
+        }
+
+        let mut scores = vec![0.0; passages.len()];
+        for QueryTerm { term, boost } in query_terms {
+            let Some(df) = self.index.document_frequency(term) else {
+                continue;
+            };
+            let hits = self
+                .passages
+                .postings(term)
+                .and_then(|documents| documents.get(doc_id));
+            let text_stats = self.text_stats(collection).boosted(*boost);
+            let df = Self::document_frequency(term, df, collection);
+            for (passage, tf) in hits.into_iter().flatten() {
+                let length = self.passages.length(doc_id, *passage);
+                scores[*passage] += self
+                    .settings
+                    .scoring
+                    .term_weight(&text_stats, *tf, df, length);
+            }
+        }
+
+        scores
+            .into_iter()
+            .enumerate()
+            .filter(|(_, score)| *score > 0.0)
+            .fold(
+                None,
+                |best: Option<(usize, f64)>, (number, score)| match best {
+                    Some((_, best_score)) if best_score >= score => best,
+                    _ => Some((number, score)),
+                },
+            )
+    }
+
+    /// The `max_terms` terms of a document with the highest TF-IDF weight, boosted relative to
+    /// the strongest one. Terms that occur in no other document cannot find similar documents
+    /// and are skipped. Returns an empty list for unknown documents. For a shard, `collection`
+    /// holds the statistics of all shards for the document's terms, see `term_stats`.
+    pub fn distinctive_terms(
+        &self,
+        doc_id: &Uuid,
+        max_terms: usize,
+        collection: Option<&CollectionStats>,
+    ) -> Vec<QueryTerm> {
+        let mut weighted: Vec<(String, f64)> = self
+            .term_vector(doc_id, collection)
+            .into_iter()
+            .filter(|(term, _)| {
+                self.index
+                    .document_frequency(term)
+                    .is_some_and(|df| Self::document_frequency(term, df, collection) > 1)
+            })
+            .collect();
+        weighted.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        weighted.truncate(max_terms);
+
+        let max_weight = weighted.first().map_or(1.0, |(_, weight)| *weight);
+        weighted
+            .into_iter()
+            .map(|(term, weight)| QueryTerm {
+                term,
+                boost: weight / max_weight,
+            })
+            .collect()
+    }
+
+    /// How often each term occurs in a document, empty for unknown documents.
+    pub fn term_frequencies(&self, doc_id: &Uuid) -> HashMap<String, usize> {
+        let mut term_freq: HashMap<String, usize> = HashMap::new();
+        if let Some(document) = self.documents.get(doc_id) {
+            for token in self.tokenizer.tokenize(&document.get_text()) {
+                *term_freq.entry(token).or_insert(0) += 1;
+            }
+        }
+        term_freq
+    }
+
+    /// TF-IDF weight of every term in a document, empty for unknown documents. For a shard,
+    /// `collection` holds the statistics of all shards, see `term_stats`.
+    pub fn term_vector(
+        &self,
+        doc_id: &Uuid,
+        collection: Option<&CollectionStats>,
+    ) -> HashMap<String, f64> {
+        let stats = self.text_stats(collection);
+        let length = self.doc_lengths.get(doc_id).copied().unwrap_or(0);
+        self.term_frequencies(doc_id)
+            .into_iter()
+            .filter_map(|(term, tf)| {
+                let df = self.index.document_frequency(&term)?;
+                let df = Self::document_frequency(&term, df, collection);
+                let weight = ScoringModel::TfIdf.term_weight(&stats, tf, df, length);
+                Some((term, weight))
+            })
+            .collect()
+    }
+
+    /// Sets the static boost of a document, e.g. to pin an official page above similar
+    /// content. A boost of 1.0 removes it. Returns `false` if the document is unknown and
+    /// fails if it cannot be loaded.
+    pub fn set_boost(&mut self, doc_id: &Uuid, boost: f64) -> Result<bool> {
+        let Some(document) = self.documents.get_mut(doc_id)? else {
+            return Ok(false);
+        };
+
+        document.boost = boost;
+        self.changes.begin();
+        self.changes.document(*doc_id);
+        if boost == 1.0 {
+            self.boosts.remove(doc_id);
         } else {
-            // Smooth IDF to give some weight even to common terms
-            let smooth_idf =
-                ((self.total_documents as f64 + 1.0) / (document_frequency as f64 + 1.0)).ln();
-            smooth_idf + 1.0 // Add base score to avoid complete zeros
+            self.boosts.insert(*doc_id, boost);
+        }
+        Ok(true)
+    }
+
+    pub fn doc_id_by_path(&self, path: &str) -> Option<Uuid> {
+        self.doc_ids_by_path.get(path).copied()
+    }
+
+    /// Merges freshly crawled links into the stored graph and recomputes authority scores
+    /// and the anchor text field.
+    pub fn update_link_graph(&mut self, links: LinkGraph) {
+        self.add_links(links);
+        let ranks = self.link_graph.pagerank();
+        self.set_authority(&ranks);
+    }
+
+    /// Merges freshly crawled links into the stored graph and updates the anchor text field
+    /// with them, replacing the anchor text of pages crawled again. Authority scores are left
+    /// as they are, see `set_authority`.
+    pub fn add_links(&mut self, links: LinkGraph) {
+        self.changes.begin();
+        for (source, links) in links.iter() {
+            let previous = self.link_graph.outlinks(source);
+            self.changes.page(source);
+            for link in previous.iter().chain(links) {
+                self.changes.page(&link.target);
+            }
+            self.anchors.remove_links(source, previous, &self.tokenizer);
+            self.anchors.add_links(source, links, &self.tokenizer);
         }
+        self.link_graph.merge(links);
     }
 
-    pub fn get_document(&self, doc_id: &Uuid) -> Option<&Document> {
+    /// Sets the authority of every document from the PageRank of its page, see
+    /// `LinkGraph::pagerank`, scaled so the best linked page has 1.0.
+    pub fn set_authority(&mut self, ranks: &HashMap<String, f64>) {
+        self.changes.begin();
+        let max_rank = ranks.values().copied().fold(0.0, f64::max);
+        let authority: imbl::HashMap<Uuid, f64> = self
+            .doc_ids_by_path
+            .iter()
+            .filter_map(|(path, doc_id)| {
+                let rank = ranks.get(path)?;
+                Some((*doc_id, rank / max_rank))
+            })
+            .collect();
+        for (doc_id, previous) in &self.authority {
+            if authority.get(doc_id) != Some(previous) {
+                self.changes.links(*doc_id);
+            }
+        }
+        for doc_id in authority.keys() {
+            if !self.authority.contains_key(doc_id) {
+                self.changes.links(*doc_id);
+            }
+        }
+        self.authority = authority;
+
+        log::info!(
+            "Computed authority for {} documents from {} links",
+            self.authority.len(),
+            self.link_graph.link_count()
+        );
+    }
+
+    /// Query-independent multiplier of a document's text score: authority times boost.
+    pub(crate) fn score_factor(&self, doc_id: &Uuid) -> f64 {
+        self.authority_factor(doc_id) * self.boost(doc_id)
+    }
+
+    fn authority_factor(&self, doc_id: &Uuid) -> f64 {
+        let authority = self.authority.get(doc_id).copied().unwrap_or(0.0);
+        1.0 + self.settings.authority_weight * authority
+    }
+
+    fn boost(&self, doc_id: &Uuid) -> f64 {
+        self.boosts.get(doc_id).copied().unwrap_or(1.0)
+    }
+
+    /// Document and token counts of the text field and the document frequency of each query
+    /// term. Summed over the shards of a collection, they let every shard score with the same
+    /// IDF and average length.
+    pub fn collection_stats(&self, query: &str) -> CollectionStats {
+        let terms = QueryTerm::parse(query, &self.tokenizer);
+        self.term_stats(terms.iter().map(|query_term| query_term.term.as_str()))
+    }
+
+    /// Document count, token count and the document frequencies of `terms`, to be merged
+    /// with those of the other shards.
+    pub fn term_stats<'a>(&self, terms: impl IntoIterator<Item = &'a str>) -> CollectionStats {
+        CollectionStats {
+            doc_count: self.total_documents,
+            total_tokens: self.total_tokens,
+            document_frequencies: terms
+                .into_iter()
+                .map(|term| {
+                    let df = self.index.document_frequency(term).unwrap_or(0);
+                    (term.to_string(), df)
+                })
+                .collect(),
+        }
+    }
+
+    fn text_stats(&self, collection: Option<&CollectionStats>) -> FieldStats {
+        let (doc_count, total_tokens) = match collection {
+            Some(collection) => (collection.doc_count, collection.total_tokens),
+            None => (self.total_documents, self.total_tokens),
+        };
+        FieldStats {
+            name: Self::TEXT_FIELD,
+            doc_count,
+            total_tokens,
+            boost: 1.0,
+        }
+    }
+
+    /// `df`, the document frequency of `term` in this index, or that of the collection.
+    fn document_frequency(term: &str, df: usize, collection: Option<&CollectionStats>) -> usize {
+        collection
+            .and_then(|collection| collection.document_frequencies.get(term).copied())
+            .unwrap_or(df)
+    }
+
+    pub fn get_document(&self, doc_id: &Uuid) -> Option<Arc<Document>> {
         self.documents.get(doc_id)
     }
 
-    pub fn get_all_documents(&self) -> Vec<&Document> {
+    pub fn get_all_documents(&self) -> Vec<Arc<Document>> {
         self.documents.values().collect()
     }
 }
@@ -169,3 +904,95 @@ impl Default for InvertedIndex {
         Self::new()
     }
 }
+
+/// A document tokenized and counted without access to the index, so analysis can run on any
+/// thread.
+struct AnalyzedDocument {
+    document: Document,
+    /// Number of indexed terms
+    length: usize,
+    term_freq: HashMap<String, usize>,
+    /// MinHash signature, `None` when duplicate detection is off or the document is too short
+    signature: Option<Vec<u32>>,
+    passages: Option<PassageTerms>,
+}
+
+impl AnalyzedDocument {
+    fn new(document: Document, tokenizer: &Tokenizer, settings: &IndexSettings) -> Self {
+        let tokens = tokenizer.tokenize(&document.get_text());
+        let signature = match settings.duplicates.mode {
+            DuplicateMode::Off => None,
+            _ => DuplicateIndex::signature(&tokens),
+        };
+        let passages = PassageTerms::analyze(&document, &settings.passages, tokenizer);
+
+        let length = tokens.len();
+        let mut term_freq: HashMap<String, usize> = HashMap::new();
+        for token in tokens {
+            *term_freq.entry(token).or_insert(0) += 1;
+        }
+
+        Self {
+            document,
+            length,
+            term_freq,
+            signature,
+            passages,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn document(id: u128, content: &str, path: &str) -> Document {
+        let mut document = Document::new("Note".to_string(), content.to_string(), path.to_string());
+        document.id = Uuid::from_u128(id);
+        document
+    }
+
+    #[test]
+    fn indexes_only_the_last_document_with_an_id() {
+        let mut batch = InvertedIndex::new();
+        let indexed = batch
+            .add_documents(vec![
+                document(1, "alpha beta", "one.md"),
+                document(2, "delta", "two.md"),
+                document(1, "gamma", "one.md"),
+            ])
+            .unwrap();
+
+        let mut one_by_one = InvertedIndex::new();
+        one_by_one
+            .add_document(document(2, "delta", "two.md"))
+            .unwrap();
+        one_by_one
+            .add_document(document(1, "gamma", "one.md"))
+            .unwrap();
+
+        assert_eq!(indexed, 2);
+        assert_eq!(batch.total_documents, 2);
+        assert_eq!(batch.total_tokens, one_by_one.total_tokens);
+        assert_eq!(batch.index.len(), one_by_one.index.len());
+        assert!(batch.search("alpha", None).is_empty());
+        assert_eq!(batch.search("gamma", None).len(), 1);
+    }
+
+    #[test]
+    fn removing_a_document_keeps_the_path_of_its_successor() {
+        let mut index = InvertedIndex::new();
+        index
+            .add_document(document(1, "first crawl", "page.md"))
+            .unwrap();
+        index
+            .add_document(document(2, "second crawl", "page.md"))
+            .unwrap();
+        assert_eq!(index.doc_id_by_path("page.md"), Some(Uuid::from_u128(2)));
+
+        index.remove_document(&Uuid::from_u128(1)).unwrap();
+        assert_eq!(index.doc_id_by_path("page.md"), Some(Uuid::from_u128(2)));
+        index.remove_document(&Uuid::from_u128(2)).unwrap();
+        assert_eq!(index.doc_id_by_path("page.md"), None);
+    }
+}
@@ -0,0 +1,124 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Link {
+    pub target: String,
+    /// Visible text of the `<a>` element
+    #[serde(default)]
+    pub anchor_text: String,
+}
+
+/// Outgoing links of every crawled page, keyed by page URL, in a persistent map so copies
+/// share them until changed.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LinkGraph {
+    outlinks: imbl::HashMap<String, Vec<Link>>,
+}
+
+impl LinkGraph {
+    const DAMPING: f64 = 0.85;
+    const MAX_ITERATIONS: usize = 50;
+    const TOLERANCE: f64 = 1e-6;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records the links found on `source`, replacing those of an earlier crawl.
+    pub fn set_outlinks(&mut self, source: String, links: Vec<Link>) {
+        self.outlinks.insert(source, links);
+    }
+
+    /// Whether the links of `source` were recorded, even if it has none.
+    pub fn contains(&self, source: &str) -> bool {
+        self.outlinks.contains_key(source)
+    }
+
+    pub fn outlinks(&self, source: &str) -> &[Link] {
+        self.outlinks
+            .get(source)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &[Link])> {
+        self.outlinks
+            .iter()
+            .map(|(source, links)| (source, links.as_slice()))
+    }
+
+    pub fn merge(&mut self, other: LinkGraph) {
+        self.outlinks.extend(other.outlinks);
+    }
+
+    pub fn page_count(&self) -> usize {
+        self.outlinks.len()
+    }
+
+    pub fn link_count(&self) -> usize {
+        self.outlinks.values().map(Vec::len).sum()
+    }
+
+    /// PageRank of every page in the graph, crawled or only linked to. Scores sum to 1.
+    ///
+    /// Duplicate and self links are ignored; pages without outgoing links spread their
+    /// rank evenly over all pages.
+    pub fn pagerank(&self) -> HashMap<String, f64> {
+        let mut ids: HashMap<&str, usize> = HashMap::new();
+        for (source, links) in &self.outlinks {
+            let next = ids.len();
+            ids.entry(source.as_str()).or_insert(next);
+            for link in links {
+                let next = ids.len();
+                ids.entry(link.target.as_str()).or_insert(next);
+            }
+        }
+
+        let n = ids.len();
+        if n == 0 {
+            return HashMap::new();
+        }
+
+        let mut edges: Vec<Vec<usize>> = vec![Vec::new(); n];
+        for (source, links) in &self.outlinks {
+            let from = ids[source.as_str()];
+            let targets: HashSet<usize> = links
+                .iter()
+                .map(|link| ids[link.target.as_str()])
+                .filter(|to| *to != from)
+                .collect();
+            edges[from] = targets.into_iter().collect();
+        }
+
+        let base = (1.0 - Self::DAMPING) / n as f64;
+        let mut ranks = vec![1.0 / n as f64; n];
+
+        for _ in 0..Self::MAX_ITERATIONS {
+            let dangling: f64 = edges
+                .iter()
+                .zip(&ranks)
+                .filter(|(targets, _)| targets.is_empty())
+                .map(|(_, rank)| rank)
+                .sum();
+
+            let mut next = vec![base + Self::DAMPING * dangling / n as f64; n];
+            for (from, targets) in edges.iter().enumerate() {
+                let share = Self::DAMPING * ranks[from] / targets.len().max(1) as f64;
+                for to in targets {
+                    next[*to] += share;
+                }
+            }
+
+            let change: f64 = next.iter().zip(&ranks).map(|(a, b)| (a - b).abs()).sum();
+            ranks = next;
+            if change < Self::TOLERANCE {
+                break;
+            }
+        }
+
+        ids.into_iter()
+            .map(|(url, id)| (url.to_string(), ranks[id]))
+            .collect()
+    }
+}
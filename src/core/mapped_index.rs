@@ -0,0 +1,482 @@
+use crate::core::{Document, FieldStats, IndexSettings, InvertedIndex, QueryTerm, Tokenizer};
+use anyhow::{Context, Result, anyhow};
+use memmap2::Mmap;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::path::Path;
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// A read-only index file that is memory-mapped and queried in place.
+///
+/// Opening one only reads the header and settings. Term lookups binary search the sorted term
+/// dictionary, postings are scanned straight from the file and stored documents are decoded
+/// one at a time when a result needs them, so the operating system decides which pages stay
+/// resident. Documents are ranked as a whole by their title and content; passages, anchor
+/// text, doc values and vectors stay with the in-memory `InvertedIndex`.
+///
+/// Layout, all integers little-endian:
+///
+/// ```text
+/// header    magic, counts and the offset of every section
+/// settings  IndexSettings as JSON
+/// docs      per document sorted by id: id, stored offset and length, token count, score factor
+/// paths     per document sorted by path: path offset and length, document ordinal
+/// terms     per term sorted by bytes: term offset and length, postings offset, document frequency
+/// postings  per posting sorted by ordinal: document ordinal (u32), term frequency (u32)
+/// strings   term and path bytes
+/// stored    documents serialized with bincode
+/// ```
+pub struct MappedIndex {
+    mmap: Mmap,
+    settings: IndexSettings,
+    tokenizer: Tokenizer,
+    doc_count: usize,
+    term_count: usize,
+    total_tokens: usize,
+    docs_offset: usize,
+    paths_offset: usize,
+    terms_offset: usize,
+    postings_offset: usize,
+    strings_offset: usize,
+    stored_offset: usize,
+}
+
+impl MappedIndex {
+    const MAGIC: &'static [u8; 8] = b"MSEMAP01";
+    const HEADER_FIELDS: usize = 11;
+    const HEADER_LEN: usize = 8 + 8 * Self::HEADER_FIELDS;
+    const DOC_RECORD: usize = 48;
+    const PATH_RECORD: usize = 24;
+    const TERM_RECORD: usize = 32;
+    const POSTING: usize = 8;
+
+    /// Writes the documents and text postings of `index` to `path`. The file is written next
+    /// to its destination and renamed over it, so readers never see a partial file.
+    pub fn write(index: &InvertedIndex, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let mut documents = index.documents.try_values().collect::<Result<Vec<_>>>()?;
+        documents.sort_by_key(|document| document.id);
+        let ordinals: HashMap<Uuid, u32> = documents
+            .iter()
+            .enumerate()
+            .map(|(ordinal, document)| (document.id, ordinal as u32))
+            .collect();
+
+        let mut docs = Vec::with_capacity(documents.len() * Self::DOC_RECORD);
+        let mut stored = Vec::new();
+        for document in &documents {
+            let bytes = bincode::serialize(&**document).context("Failed to serialize document")?;
+            let length = index.doc_lengths.get(&document.id).copied().unwrap_or(0);
+            docs.extend_from_slice(document.id.as_bytes());
+            Self::push(&mut docs, stored.len());
+            Self::push(&mut docs, bytes.len());
+            Self::push(&mut docs, length);
+            docs.extend_from_slice(&index.score_factor(&document.id).to_le_bytes());
+            stored.extend_from_slice(&bytes);
+        }
+
+        let mut strings = Vec::new();
+        let mut by_path: Vec<(usize, &Arc<Document>)> = documents.iter().enumerate().collect();
+        by_path.sort_by(|a, b| a.1.path.cmp(&b.1.path));
+        let mut paths = Vec::with_capacity(documents.len() * Self::PATH_RECORD);
+        for (ordinal, document) in by_path {
+            Self::push(&mut paths, strings.len());
+            Self::push(&mut paths, document.path.len());
+            Self::push(&mut paths, ordinal);
+            strings.extend_from_slice(document.path.as_bytes());
+        }
+
+        let mut terms = index.index.iter().collect::<Result<Vec<_>>>()?;
+        terms.sort_by(|a, b| a.0.as_bytes().cmp(b.0.as_bytes()));
+        let mut term_records = Vec::with_capacity(terms.len() * Self::TERM_RECORD);
+        let mut postings = Vec::new();
+        for (term, posting_list) in &terms {
+            let mut entries: Vec<(u32, u32)> = posting_list
+                .term_frequency
+                .iter()
+                .filter_map(|(doc_id, tf)| Some((*ordinals.get(doc_id)?, *tf as u32)))
+                .collect();
+            entries.sort_unstable();
+
+            Self::push(&mut term_records, strings.len());
+            Self::push(&mut term_records, term.len());
+            Self::push(&mut term_records, postings.len());
+            Self::push(&mut term_records, entries.len());
+            strings.extend_from_slice(term.as_bytes());
+            for (ordinal, tf) in entries {
+                postings.extend_from_slice(&ordinal.to_le_bytes());
+                postings.extend_from_slice(&tf.to_le_bytes());
+            }
+        }
+
+        let settings =
+            serde_json::to_vec(&index.settings).context("Failed to serialize settings")?;
+        let sections = [&settings, &docs, &paths, &term_records, &postings, &strings];
+        let mut offsets = Vec::with_capacity(sections.len() + 1);
+        let mut offset = Self::HEADER_LEN;
+        for section in sections {
+            offsets.push(offset);
+            offset += section.len();
+        }
+        offsets.push(offset);
+
+        let mut bytes = Vec::with_capacity(offset + stored.len());
+        bytes.extend_from_slice(Self::MAGIC);
+        Self::push(&mut bytes, documents.len());
+        Self::push(&mut bytes, terms.len());
+        Self::push(&mut bytes, index.total_tokens);
+        Self::push(&mut bytes, offsets[0]);
+        Self::push(&mut bytes, settings.len());
+        for offset in &offsets[1..] {
+            Self::push(&mut bytes, *offset);
+        }
+        for section in sections {
+            bytes.extend_from_slice(section);
+        }
+        bytes.extend_from_slice(&stored);
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create directory: {:?}", parent))?;
+        }
+        let temporary = path.with_extension("tmp");
+        fs::write(&temporary, bytes)
+            .with_context(|| format!("Failed to write mapped index: {:?}", temporary))?;
+        fs::rename(&temporary, path)
+            .with_context(|| format!("Failed to replace mapped index: {:?}", path))
+    }
+
+    /// Opens an index file, checking that its sections fit the file. Offsets inside records
+    /// are checked when they are read, so a damaged file fails searches instead of crashing.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let file =
+            File::open(path).with_context(|| format!("Failed to open mapped index: {:?}", path))?;
+        // Safety: `write` replaces index files by renaming, it never modifies a mapped file
+        let mmap = unsafe { Mmap::map(&file) }
+            .with_context(|| format!("Failed to map index file: {:?}", path))?;
+
+        if mmap.len() < Self::HEADER_LEN || &mmap[..8] != Self::MAGIC {
+            return Err(anyhow!("Not a mapped index file: {:?}", path));
+        }
+        let header = |field: usize| Self::read_u64(&mmap, 8 + 8 * field);
+        let (doc_count, term_count, total_tokens) = (header(0)?, header(1)?, header(2)?);
+        let (settings_offset, settings_len) = (header(3)?, header(4)?);
+        let (docs_offset, paths_offset, terms_offset) = (header(5)?, header(6)?, header(7)?);
+        let (postings_offset, strings_offset, stored_offset) =
+            (header(8)?, header(9)?, header(10)?);
+
+        let section_end = |offset: usize, count: usize, record: usize| {
+            count
+                .checked_mul(record)
+                .and_then(|length| offset.checked_add(length))
+        };
+        let valid = settings_offset == Self::HEADER_LEN
+            && section_end(settings_offset, settings_len, 1) == Some(docs_offset)
+            && section_end(docs_offset, doc_count, Self::DOC_RECORD) == Some(paths_offset)
+            && section_end(paths_offset, doc_count, Self::PATH_RECORD) == Some(terms_offset)
+            && section_end(terms_offset, term_count, Self::TERM_RECORD) == Some(postings_offset)
+            && postings_offset <= strings_offset
+            && strings_offset <= stored_offset
+            && stored_offset <= mmap.len();
+        if !valid {
+            return Err(anyhow!("Corrupt mapped index file: {:?}", path));
+        }
+
+        let settings: IndexSettings = serde_json::from_slice(&mmap[settings_offset..docs_offset])
+            .context("Failed to read mapped index settings")?;
+
+        Ok(Self {
+            tokenizer: Tokenizer::with_config(settings.analyzer.clone()),
+            settings,
+            mmap,
+            doc_count,
+            term_count,
+            total_tokens,
+            docs_offset,
+            paths_offset,
+            terms_offset,
+            postings_offset,
+            strings_offset,
+            stored_offset,
+        })
+    }
+
+    pub fn settings(&self) -> &IndexSettings {
+        &self.settings
+    }
+
+    pub fn total_documents(&self) -> usize {
+        self.doc_count
+    }
+
+    pub fn term_count(&self) -> usize {
+        self.term_count
+    }
+
+    /// Scores documents like `InvertedIndex::search` without passages and anchor text.
+    pub fn search(&self, query: &str) -> Result<Vec<(Uuid, f64)>> {
+        let scoring = &self.settings.scoring;
+        let stats = FieldStats {
+            name: "text",
+            doc_count: self.doc_count,
+            total_tokens: self.total_tokens,
+            boost: 1.0,
+        };
+        let mut scores: HashMap<usize, f64> = HashMap::new();
+
+        for QueryTerm { term, boost } in QueryTerm::parse(query, &self.tokenizer) {
+            let Some(record) = self.find_term(&term)? else {
+                continue;
+            };
+            let stats = stats.boosted(boost);
+            let df = self.read(record + 24)?;
+            let postings = self.section(
+                self.postings_offset,
+                self.strings_offset,
+                self.read(record + 16)?,
+                df.checked_mul(Self::POSTING),
+            )?;
+
+            for entry in postings.chunks_exact(Self::POSTING) {
+                let ordinal = Self::read_u32(entry, 0)?;
+                let tf = Self::read_u32(entry, 4)?;
+                let length = self.read(self.doc_record(ordinal)? + 32)?;
+                *scores.entry(ordinal).or_insert(0.0) +=
+                    scoring.term_weight(&stats, tf, df, length);
+            }
+        }
+
+        let mut results = scores
+            .into_iter()
+            .map(|(ordinal, score)| {
+                let factor = f64::from_le_bytes(self.bytes(self.doc_record(ordinal)? + 40)?);
+                Ok((self.doc_id(ordinal)?, score * factor))
+            })
+            .collect::<Result<Vec<(Uuid, f64)>>>()?;
+        results.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        Ok(results)
+    }
+
+    /// Decodes a stored document, `None` if it is not in the index.
+    pub fn get_document(&self, doc_id: &Uuid) -> Result<Option<Document>> {
+        let ordinal = Self::binary_search(self.doc_count, |ordinal| {
+            Ok(self.doc_id(ordinal)?.cmp(doc_id))
+        })?;
+        ordinal.map(|ordinal| self.document(ordinal)).transpose()
+    }
+
+    pub fn doc_id_by_path(&self, path: &str) -> Result<Option<Uuid>> {
+        let record = Self::binary_search(self.doc_count, |number| {
+            Ok(self
+                .string(self.paths_offset + number * Self::PATH_RECORD)?
+                .cmp(path.as_bytes()))
+        })?;
+        record
+            .map(|record| {
+                let ordinal = self.read(self.paths_offset + record * Self::PATH_RECORD + 16)?;
+                self.doc_id(ordinal)
+            })
+            .transpose()
+    }
+
+    /// Every stored document, decoded while iterating.
+    pub fn documents(&self) -> impl Iterator<Item = Result<Document>> + '_ {
+        (0..self.doc_count).map(|ordinal| self.document(ordinal))
+    }
+
+    fn document(&self, ordinal: usize) -> Result<Document> {
+        let record = self.doc_record(ordinal)?;
+        let stored = self.section(
+            self.stored_offset,
+            self.mmap.len(),
+            self.read(record + 16)?,
+            Some(self.read(record + 24)?),
+        )?;
+        bincode::deserialize(stored)
+            .with_context(|| format!("Failed to decode mapped document {}", ordinal))
+    }
+
+    /// Position of the dictionary record of `term`.
+    fn find_term(&self, term: &str) -> Result<Option<usize>> {
+        let number = Self::binary_search(self.term_count, |number| {
+            Ok(self
+                .string(self.terms_offset + number * Self::TERM_RECORD)?
+                .cmp(term.as_bytes()))
+        })?;
+        Ok(number.map(|number| self.terms_offset + number * Self::TERM_RECORD))
+    }
+
+    fn doc_record(&self, ordinal: usize) -> Result<usize> {
+        if ordinal >= self.doc_count {
+            return Err(Self::corrupt());
+        }
+        Ok(self.docs_offset + ordinal * Self::DOC_RECORD)
+    }
+
+    fn doc_id(&self, ordinal: usize) -> Result<Uuid> {
+        Ok(Uuid::from_bytes(self.bytes(self.doc_record(ordinal)?)?))
+    }
+
+    /// The bytes referenced by the offset and length at the start of a record.
+    fn string(&self, record: usize) -> Result<&[u8]> {
+        self.section(
+            self.strings_offset,
+            self.stored_offset,
+            self.read(record)?,
+            Some(self.read(record + 8)?),
+        )
+    }
+
+    /// `length` bytes at `offset` into the section from `start` to `end`, `None` standing for
+    /// a length that overflowed.
+    fn section(
+        &self,
+        start: usize,
+        end: usize,
+        offset: usize,
+        length: Option<usize>,
+    ) -> Result<&[u8]> {
+        let from = start.checked_add(offset);
+        let to = from
+            .zip(length)
+            .and_then(|(from, length)| from.checked_add(length));
+        match (from, to) {
+            (Some(from), Some(to)) if to <= end => Ok(&self.mmap[from..to]),
+            _ => Err(Self::corrupt()),
+        }
+    }
+
+    fn bytes<const N: usize>(&self, position: usize) -> Result<[u8; N]> {
+        position
+            .checked_add(N)
+            .and_then(|end| self.mmap.get(position..end))
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or_else(Self::corrupt)
+    }
+
+    fn read(&self, position: usize) -> Result<usize> {
+        Self::read_u64(&self.mmap, position)
+    }
+
+    fn read_u32(bytes: &[u8], position: usize) -> Result<usize> {
+        let value = bytes
+            .get(position..position + 4)
+            .ok_or_else(Self::corrupt)?;
+        Ok(u32::from_le_bytes(value.try_into().expect("slice has 4 bytes")) as usize)
+    }
+
+    fn read_u64(bytes: &[u8], position: usize) -> Result<usize> {
+        let value = bytes
+            .get(position..position + 8)
+            .ok_or_else(Self::corrupt)?;
+        let value = u64::from_le_bytes(value.try_into().expect("slice has 8 bytes"));
+        usize::try_from(value).map_err(|_| Self::corrupt())
+    }
+
+    fn corrupt() -> anyhow::Error {
+        anyhow!("Mapped index file is corrupt: a record points outside of its section")
+    }
+
+    fn push(bytes: &mut Vec<u8>, value: usize) {
+        bytes.extend_from_slice(&(value as u64).to_le_bytes());
+    }
+
+    /// Finds the record in `0..count` for which `compare` returns `Equal`, records being
+    /// sorted so that `compare` is ascending.
+    fn binary_search(
+        count: usize,
+        compare: impl Fn(usize) -> Result<Ordering>,
+    ) -> Result<Option<usize>> {
+        let (mut low, mut high) = (0, count);
+        while low < high {
+            let middle = low + (high - low) / 2;
+            match compare(middle)? {
+                Ordering::Less => low = middle + 1,
+                Ordering::Greater => high = middle,
+                Ordering::Equal => return Ok(Some(middle)),
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn index() -> InvertedIndex {
+        let mut index = InvertedIndex::new();
+        for (title, content) in [
+            ("Rust", "Ownership and borrowing keep memory safe"),
+            ("Python", "Garbage collection manages memory"),
+            ("Go", "Goroutines and channels"),
+        ] {
+            index
+                .add_document(Document::new(
+                    title.to_string(),
+                    content.to_string(),
+                    format!("{}.md", title.to_lowercase()),
+                ))
+                .unwrap();
+        }
+        index
+    }
+
+    #[test]
+    fn round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("index.mmap");
+        let index = index();
+        MappedIndex::write(&index, &path).unwrap();
+
+        let mapped = MappedIndex::open(&path).unwrap();
+        assert_eq!(mapped.total_documents(), 3);
+        assert_eq!(mapped.term_count(), index.index.len());
+
+        let hits = mapped.search("memory").unwrap();
+        assert_eq!(hits.len(), 2);
+        for ((doc_id, score), (expected_id, expected_score)) in
+            hits.iter().zip(index.search("memory", None))
+        {
+            assert_eq!(*doc_id, expected_id);
+            assert!((score - expected_score).abs() < 1e-9);
+        }
+
+        let doc_id = mapped.doc_id_by_path("rust.md").unwrap().unwrap();
+        assert_eq!(mapped.get_document(&doc_id).unwrap().unwrap().title, "Rust");
+        assert_eq!(mapped.doc_id_by_path("missing.md").unwrap(), None);
+        assert_eq!(mapped.documents().filter(Result::is_ok).count(), 3);
+    }
+
+    #[test]
+    fn damaged_files_fail_without_panicking() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("index.mmap");
+        MappedIndex::write(&index(), &path).unwrap();
+        let bytes = fs::read(&path).unwrap();
+
+        // Cut off inside the stored documents: opens, but reading documents fails
+        fs::write(&path, &bytes[..bytes.len() - 10]).unwrap();
+        let mapped = MappedIndex::open(&path).unwrap();
+        assert!(mapped.documents().any(|document| document.is_err()));
+
+        // Cut off inside the sections
+        fs::write(&path, &bytes[..MappedIndex::HEADER_LEN + 10]).unwrap();
+        assert!(MappedIndex::open(&path).is_err());
+
+        // Term records pointing past their section
+        fs::write(&path, &bytes).unwrap();
+        let mapped = MappedIndex::open(&path).unwrap();
+        let mut damaged = bytes.clone();
+        for record in 0..mapped.term_count {
+            let position = mapped.terms_offset + record * MappedIndex::TERM_RECORD + 16;
+            damaged[position..position + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+        }
+        fs::write(&path, &damaged).unwrap();
+        let mapped = MappedIndex::open(&path).unwrap();
+        assert!(mapped.search("memory").is_err());
+    }
+}
@@ -1,9 +1,45 @@
+pub mod anchor_index;
+pub mod changes;
+pub mod collapse;
+pub mod decay;
+pub mod doc_values;
 pub mod document;
+pub mod document_store;
+pub mod duplicates;
+pub mod explanation;
+pub mod hybrid;
 pub mod index;
+pub mod link_graph;
+pub mod mapped_index;
+pub mod passages;
+pub mod posting_store;
+pub mod query;
 pub mod ranking;
+pub mod scoring;
+pub mod search_options;
+pub mod settings;
 pub mod tokenizer;
+pub mod vector_index;
 
-pub use document::Document;
-pub use index::InvertedIndex;
-pub use ranking::TfIdfRanker;
-pub use tokenizer::Tokenizer;
+pub use anchor_index::AnchorIndex;
+pub use changes::{ChangeLog, SavedChanges};
+pub use collapse::{Collapse, CollapseKey};
+pub use decay::{Decay, DecayFunction, parse_timestamp};
+pub use doc_values::{DocValues, Facet, FacetCount};
+pub use document::{Document, Section};
+pub use document_store::{DocumentSource, DocumentStore};
+pub use duplicates::{DuplicateEntry, DuplicateIndex, DuplicateMode, DuplicateSettings};
+pub use explanation::Explanation;
+pub use hybrid::{Fusion, HybridHits};
+pub use index::{IndexMeta, IndexParts, InvertedIndex, PostingList};
+pub use link_graph::{Link, LinkGraph};
+pub use mapped_index::MappedIndex;
+pub use passages::{DocumentPassages, Passage, PassageIndex, PassageSettings, PassageTerms};
+pub use posting_store::{PostingSource, PostingStore};
+pub use query::QueryTerm;
+pub use ranking::{Hit, Hybrid, Owner, Retrieved, SearchResults, TfIdfRanker};
+pub use scoring::{CollectionStats, FieldStats, ScoringModel};
+pub use search_options::{FieldFilter, SearchOptions, SortOrder, SortSpec};
+pub use settings::IndexSettings;
+pub use tokenizer::{AnalyzerConfig, Tokenizer};
+pub use vector_index::{VectorIndex, VectorNode};
@@ -0,0 +1,281 @@
+use crate::core::{Document, Section, Tokenizer};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// How long documents are split into passages at index time.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PassageSettings {
+    pub enabled: bool,
+    /// Documents with fewer words are ranked as a whole
+    pub min_words: usize,
+    /// Words per passage; longer sections are split into overlapping windows
+    pub window: usize,
+    /// Words shared by consecutive windows of one section
+    pub overlap: usize,
+}
+
+impl Default for PassageSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            min_words: 300,
+            window: 150,
+            overlap: 30,
+        }
+    }
+}
+
+/// A stretch of a document's content, ranked on its own.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Passage {
+    /// Heading of the section the passage belongs to, `None` before the first heading
+    pub heading: Option<String>,
+    pub anchor: Option<String>,
+    /// Word range `start..end` of the document content
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Passage {
+    /// Splits a document at its section headings, and sections longer than the window into
+    /// overlapping windows. Returns no passages for documents below `min_words`.
+    pub fn split(document: &Document, settings: &PassageSettings) -> Vec<Passage> {
+        let words = document.content.split_whitespace().count();
+        if !settings.enabled || words < settings.min_words {
+            return Vec::new();
+        }
+
+        let window = settings.window.max(1);
+        let step = window.saturating_sub(settings.overlap).max(1);
+
+        // Text before the first heading forms a section without one
+        let mut bounds: Vec<(Option<&Section>, usize)> = vec![(None, 0)];
+        let mut sections: Vec<&Section> = document
+            .sections
+            .iter()
+            .filter(|section| section.start < words)
+            .collect();
+        sections.sort_by_key(|section| section.start);
+        for section in sections {
+            match bounds.last_mut() {
+                Some(last) if last.1 >= section.start => *last = (Some(section), last.1),
+                _ => bounds.push((Some(section), section.start)),
+            }
+        }
+
+        let mut passages = Vec::new();
+        for (i, (section, start)) in bounds.iter().enumerate() {
+            let end = bounds.get(i + 1).map_or(words, |(_, next)| *next);
+            let mut from = *start;
+            while from < end {
+                let to = (from + window).min(end);
+                passages.push(Passage {
+                    heading: section.map(|section| section.heading.clone()),
+                    anchor: section.and_then(|section| section.anchor.clone()),
+                    start: from,
+                    end: to,
+                });
+                if to == end {
+                    break;
+                }
+                from += step;
+            }
+        }
+
+        passages
+    }
+
+    /// The passage's words of `content`.
+    pub fn text(&self, content: &str) -> String {
+        content
+            .split_whitespace()
+            .skip(self.start)
+            .take(self.end - self.start)
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+/// The passages of one document with their term counts, ready to be indexed.
+#[derive(Debug, Clone)]
+pub struct PassageTerms {
+    passages: Vec<Passage>,
+    lengths: Vec<usize>,
+    term_freqs: Vec<HashMap<String, usize>>,
+}
+
+impl PassageTerms {
+    /// Splits and tokenizes a document, `None` when it is too short to be split. The title
+    /// counts as part of every passage.
+    pub fn analyze(
+        document: &Document,
+        settings: &PassageSettings,
+        tokenizer: &Tokenizer,
+    ) -> Option<Self> {
+        let passages = Passage::split(document, settings);
+        if passages.is_empty() {
+            return None;
+        }
+
+        let mut lengths = Vec::with_capacity(passages.len());
+        let mut term_freqs = Vec::with_capacity(passages.len());
+        for passage in &passages {
+            let text = format!("{} {}", document.title, passage.text(&document.content));
+            let tokens = tokenizer.tokenize(&text);
+            lengths.push(tokens.len());
+
+            let mut term_freq: HashMap<String, usize> = HashMap::new();
+            for token in tokens {
+                *term_freq.entry(token).or_insert(0) += 1;
+            }
+            term_freqs.push(term_freq);
+        }
+
+        Some(Self {
+            passages,
+            lengths,
+            term_freqs,
+        })
+    }
+}
+
+/// What the passage index keeps about one split document.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DocumentPassages {
+    pub passages: Vec<Passage>,
+    /// Number of terms in each passage
+    pub lengths: Vec<usize>,
+    /// Each term with the passages it occurs in and how often
+    pub terms: Vec<(String, Vec<(usize, usize)>)>,
+}
+
+/// Passage-level postings of the documents long enough to be split.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PassageIndex {
+    /// Passages of each split document, in content order
+    passages: imbl::HashMap<Uuid, Vec<Passage>>,
+    /// Term -> document -> (passage number, term frequency)
+    postings: imbl::HashMap<String, imbl::HashMap<Uuid, Vec<(usize, usize)>>>,
+    /// Number of terms in each passage of a document
+    lengths: imbl::HashMap<Uuid, Vec<usize>>,
+}
+
+impl PassageIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Splits and indexes a document. The title counts as part of every passage.
+    pub fn insert(
+        &mut self,
+        document: &Document,
+        settings: &PassageSettings,
+        tokenizer: &Tokenizer,
+    ) {
+        if let Some(terms) = PassageTerms::analyze(document, settings, tokenizer) {
+            self.insert_terms(document.id, terms);
+        }
+    }
+
+    /// Indexes the passages of a document analyzed beforehand.
+    pub fn insert_terms(&mut self, doc_id: Uuid, terms: PassageTerms) {
+        for (number, term_freq) in terms.term_freqs.into_iter().enumerate() {
+            for (term, freq) in term_freq {
+                self.postings
+                    .entry(term)
+                    .or_default()
+                    .entry(doc_id)
+                    .or_default()
+                    .push((number, freq));
+            }
+        }
+
+        self.lengths.insert(doc_id, terms.lengths);
+        self.passages.insert(doc_id, terms.passages);
+    }
+
+    /// The passages of a split document with their postings, `None` for others; `terms` must
+    /// include every term of its text.
+    pub fn entry<'a>(
+        &self,
+        doc_id: &Uuid,
+        terms: impl IntoIterator<Item = &'a String>,
+    ) -> Option<DocumentPassages> {
+        let passages = self.passages.get(doc_id)?.clone();
+        let mut terms: Vec<(String, Vec<(usize, usize)>)> = terms
+            .into_iter()
+            .filter_map(|term| {
+                let hits = self.postings.get(term)?.get(doc_id)?;
+                Some((term.clone(), hits.clone()))
+            })
+            .collect();
+        terms.sort();
+        Some(DocumentPassages {
+            passages,
+            lengths: self.lengths.get(doc_id).cloned().unwrap_or_default(),
+            terms,
+        })
+    }
+
+    /// Restores a document from its `entry`.
+    pub fn insert_entry(&mut self, doc_id: Uuid, entry: DocumentPassages) {
+        for (term, hits) in entry.terms {
+            self.postings.entry(term).or_default().insert(doc_id, hits);
+        }
+        self.lengths.insert(doc_id, entry.lengths);
+        self.passages.insert(doc_id, entry.passages);
+    }
+
+    /// Forgets a document; `terms` must include every term of its text.
+    pub fn remove<'a>(&mut self, doc_id: &Uuid, terms: impl IntoIterator<Item = &'a String>) {
+        if self.passages.remove(doc_id).is_none() {
+            return;
+        }
+        self.lengths.remove(doc_id);
+
+        for term in terms {
+            if let Some(documents) = self.postings.get_mut(term) {
+                documents.remove(doc_id);
+                if documents.is_empty() {
+                    self.postings.remove(term);
+                }
+            }
+        }
+    }
+
+    /// Whether the document was split and is ranked by its passages.
+    pub fn contains(&self, doc_id: &Uuid) -> bool {
+        self.passages.contains_key(doc_id)
+    }
+
+    /// Number of documents split into passages.
+    pub fn len(&self) -> usize {
+        self.passages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.passages.is_empty()
+    }
+
+    pub fn passages(&self, doc_id: &Uuid) -> &[Passage] {
+        self.passages
+            .get(doc_id)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// Documents containing `term` with the passages it occurs in and how often.
+    pub fn postings(&self, term: &str) -> Option<&imbl::HashMap<Uuid, Vec<(usize, usize)>>> {
+        self.postings.get(term)
+    }
+
+    pub fn length(&self, doc_id: &Uuid, passage: usize) -> usize {
+        self.lengths
+            .get(doc_id)
+            .and_then(|lengths| lengths.get(passage))
+            .copied()
+            .unwrap_or(0)
+    }
+}
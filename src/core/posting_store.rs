@@ -0,0 +1,270 @@
+use crate::core::PostingList;
+use anyhow::{Result, anyhow};
+use lru::LruCache;
+use serde::ser::{Error, SerializeMap};
+use serde::{Serialize, Serializer};
+use std::collections::HashMap;
+use std::fmt;
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex, MutexGuard};
+use uuid::Uuid;
+
+/// Where a `PostingStore` reads postings it does not hold, e.g. a storage tree.
+pub trait PostingSource: Send + Sync {
+    /// Term frequency of every document containing `term`.
+    fn load_postings(&self, term: &str) -> Result<Option<imbl::HashMap<Uuid, usize>>>;
+}
+
+/// The postings of an index by term. Like `DocumentStore`, postings changed in memory are
+/// held until they are saved and released, and the others are read from the source when a
+/// query needs them, keeping the most recently read ones in a shared cache. Document
+/// frequencies are always in memory, so statistics never load postings. Copies share the
+/// map of terms until one of them changes.
+#[derive(Clone)]
+pub struct PostingStore {
+    terms: imbl::HashMap<String, Slot>,
+    source: Option<Arc<dyn PostingSource>>,
+    cache: Arc<Mutex<PostingCache>>,
+}
+
+/// Loaded values by key and the change log revision they are as of.
+type PostingCache = LruCache<(String, u64), Arc<PostingList>>;
+
+/// Postings in memory, or the document frequency and change log revision the source holds
+/// them as of.
+#[derive(Clone)]
+enum Slot {
+    Held(Arc<PostingList>),
+    Stored {
+        document_frequency: usize,
+        revision: u64,
+    },
+}
+
+impl PostingStore {
+    /// Posting lists read from the source that are kept in memory.
+    pub const CACHE_SIZE: usize = 4096;
+
+    pub fn new() -> Self {
+        Self {
+            terms: imbl::HashMap::new(),
+            source: None,
+            cache: Arc::new(Mutex::new(LruCache::new(
+                NonZeroUsize::new(Self::CACHE_SIZE).expect("cache size is not zero"),
+            ))),
+        }
+    }
+
+    /// A store of `terms` with their document frequencies, whose postings are loaded from
+    /// `source` on demand.
+    pub fn with_source(
+        terms: impl IntoIterator<Item = (String, usize)>,
+        source: Arc<dyn PostingSource>,
+    ) -> Self {
+        Self {
+            terms: terms
+                .into_iter()
+                .map(|(term, document_frequency)| {
+                    let slot = Slot::Stored {
+                        document_frequency,
+                        revision: 0,
+                    };
+                    (term, slot)
+                })
+                .collect(),
+            source: Some(source),
+            ..Self::new()
+        }
+    }
+
+    /// The postings of `term`, loading them if needed. Postings that cannot be loaded are
+    /// reported and treated as missing, see `try_get`.
+    pub fn get(&self, term: &str) -> Option<Arc<PostingList>> {
+        self.try_get(term).unwrap_or_else(|e| {
+            log::warn!("{:#}", e);
+            None
+        })
+    }
+
+    /// The postings of `term`, loading them if needed. Fails for postings that cannot be
+    /// loaded.
+    pub fn try_get(&self, term: &str) -> Result<Option<Arc<PostingList>>> {
+        let (document_frequency, revision) = match self.terms.get(term) {
+            None => return Ok(None),
+            Some(Slot::Held(postings)) => return Ok(Some(Arc::clone(postings))),
+            Some(Slot::Stored {
+                document_frequency,
+                revision,
+            }) => (*document_frequency, *revision),
+        };
+        let key = (term.to_string(), revision);
+        if let Some(postings) = self.cache().get(&key) {
+            return Ok(Some(Arc::clone(postings)));
+        }
+
+        let source = self
+            .source
+            .as_ref()
+            .ok_or_else(|| anyhow!("Postings of '{}' have no storage to load them from", term))?;
+        let term_frequency = source
+            .load_postings(term)
+            .and_then(|postings| postings.ok_or_else(|| anyhow!("Postings are missing")))
+            .map_err(|e| e.context(format!("Failed to load postings of '{}'", term)))?;
+        let postings = Arc::new(PostingList {
+            term_frequency,
+            document_frequency,
+        });
+        self.cache().put(key, Arc::clone(&postings));
+        Ok(Some(postings))
+    }
+
+    /// Documents containing `term`, without loading its postings.
+    pub fn document_frequency(&self, term: &str) -> Option<usize> {
+        self.terms.get(term).map(|slot| match slot {
+            Slot::Held(postings) => postings.document_frequency,
+            Slot::Stored {
+                document_frequency, ..
+            } => *document_frequency,
+        })
+    }
+
+    /// Loads the postings of `terms` that are not held and holds them, so they can be
+    /// changed without loading anything. Fails, holding what was loaded before, for
+    /// postings that cannot be loaded.
+    pub fn hold<'a>(&mut self, terms: impl IntoIterator<Item = &'a str>) -> Result<()> {
+        for term in terms {
+            if let Some(Slot::Stored { .. }) = self.terms.get(term) {
+                let postings = self
+                    .try_get(term)?
+                    .ok_or_else(|| anyhow!("Postings of '{}' are missing", term))?;
+                self.terms.insert(term.to_string(), Slot::Held(postings));
+            }
+        }
+        Ok(())
+    }
+
+    /// The postings of `term` to change, creating them if the term is new. The postings
+    /// must be held, see `hold`.
+    pub fn entry(&mut self, term: String) -> &mut PostingList {
+        let slot = self
+            .terms
+            .entry(term)
+            .or_insert_with(|| Slot::Held(Arc::default()));
+        match slot {
+            Slot::Held(postings) => Arc::make_mut(postings),
+            Slot::Stored { .. } => panic!("postings must be held to be changed"),
+        }
+    }
+
+    /// The postings of `term` to change, if the term is indexed. The postings must be held,
+    /// see `hold`.
+    pub fn get_mut(&mut self, term: &str) -> Option<&mut PostingList> {
+        match self.terms.get_mut(term)? {
+            Slot::Held(postings) => Some(Arc::make_mut(postings)),
+            Slot::Stored { .. } => panic!("postings must be held to be changed"),
+        }
+    }
+
+    pub fn remove(&mut self, term: &str) {
+        self.terms.remove(term);
+    }
+
+    /// Drops held postings from memory once `source` holds them, each as of the change log
+    /// revision given with it, and reads them from there from now on.
+    pub fn release(
+        &mut self,
+        terms: impl IntoIterator<Item = (String, u64)>,
+        source: &Arc<dyn PostingSource>,
+    ) {
+        self.source = Some(Arc::clone(source));
+        let mut cache = self.cache.lock().expect("posting cache lock poisoned");
+        for (term, revision) in terms {
+            let Some(slot) = self.terms.get_mut(&term) else {
+                continue;
+            };
+            if let Slot::Held(postings) = slot {
+                let stored = Slot::Stored {
+                    document_frequency: postings.document_frequency,
+                    revision,
+                };
+                if let Slot::Held(postings) = std::mem::replace(slot, stored) {
+                    cache.put((term, revision), postings);
+                }
+            }
+        }
+    }
+
+    pub fn contains_key(&self, term: &str) -> bool {
+        self.terms.contains_key(term)
+    }
+
+    pub fn len(&self) -> usize {
+        self.terms.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.terms.is_empty()
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &String> {
+        self.terms.keys()
+    }
+
+    /// Every term with its postings, loading those not held. Fails at the first postings
+    /// that cannot be loaded.
+    pub fn iter(&self) -> impl Iterator<Item = Result<(&String, Arc<PostingList>)>> {
+        self.terms.keys().map(|term| {
+            let postings = self
+                .try_get(term)?
+                .ok_or_else(|| anyhow!("Postings of '{}' are missing", term))?;
+            Ok((term, postings))
+        })
+    }
+
+    fn cache(&self) -> MutexGuard<'_, PostingCache> {
+        self.cache.lock().expect("posting cache lock poisoned")
+    }
+}
+
+impl Default for PostingStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl From<HashMap<String, PostingList>> for PostingStore {
+    fn from(terms: HashMap<String, PostingList>) -> Self {
+        Self {
+            terms: terms
+                .into_iter()
+                .map(|(term, postings)| (term, Slot::Held(Arc::new(postings))))
+                .collect(),
+            ..Self::new()
+        }
+    }
+}
+
+impl fmt::Debug for PostingStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let held = self
+            .terms
+            .values()
+            .filter(|slot| matches!(slot, Slot::Held(_)));
+        f.debug_struct("PostingStore")
+            .field("terms", &self.terms.len())
+            .field("held", &held.count())
+            .finish()
+    }
+}
+
+/// Serializes as a map from term to postings, like the `HashMap` it replaces.
+impl Serialize for PostingStore {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.terms.len()))?;
+        for entry in self.iter() {
+            let (term, postings) = entry.map_err(|e| S::Error::custom(format!("{:#}", e)))?;
+            map.serialize_entry(term, &*postings)?;
+        }
+        map.end()
+    }
+}
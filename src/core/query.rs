@@ -0,0 +1,71 @@
+use crate::core::Tokenizer;
+use std::fmt;
+
+/// An analyzed query term together with its query-time boost.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryTerm {
+    pub term: String,
+    pub boost: f64,
+}
+
+impl QueryTerm {
+    /// Analyzes `query` with `tokenizer`, honoring `word^boost` suffixes such as `rust^3`.
+    ///
+    /// A boost applies to every token its word is analyzed into; words without one get 1.0.
+    pub fn parse(query: &str, tokenizer: &Tokenizer) -> Vec<QueryTerm> {
+        query
+            .split_whitespace()
+            .flat_map(|word| {
+                let (word, boost) = Self::split_boost(word);
+                tokenizer
+                    .tokenize(word)
+                    .into_iter()
+                    .map(move |term| QueryTerm {
+                        term,
+                        boost: boost.unwrap_or(1.0),
+                    })
+            })
+            .collect()
+    }
+
+    /// Splits `rust^2.5` into `("rust", Some(2.5))`. Words without a valid, positive
+    /// boost are returned unchanged, since a zero boost would drop the term silently.
+    pub fn split_boost(word: &str) -> (&str, Option<f64>) {
+        if let Some((text, boost)) = word.rsplit_once('^')
+            && let Ok(boost) = boost.parse::<f64>()
+            && boost.is_finite()
+            && boost > 0.0
+        {
+            (text, Some(boost))
+        } else {
+            (word, None)
+        }
+    }
+}
+
+/// Formats as `term^boost`, the syntax `QueryTerm::parse` reads back.
+impl fmt::Display for QueryTerm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.boost == 1.0 {
+            write!(f, "{}", self.term)
+        } else {
+            write!(f, "{}^{:.3}", self.term, self.boost)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_boost_accepts_only_positive_finite_boosts() {
+        assert_eq!(QueryTerm::split_boost("rust^2.5"), ("rust", Some(2.5)));
+        assert_eq!(QueryTerm::split_boost("rust^0.5"), ("rust", Some(0.5)));
+        for word in [
+            "rust^0", "rust^0.0", "rust^-1", "rust^inf", "rust^NaN", "rust^", "rust",
+        ] {
+            assert_eq!(QueryTerm::split_boost(word), (word, None), "{}", word);
+        }
+    }
+}
@@ -1,41 +1,603 @@
-use crate::core::{Document, InvertedIndex};
+use crate::core::{
+    CollectionStats, Document, Explanation, Facet, Fusion, HybridHits, InvertedIndex, MappedIndex,
+    Passage, QueryTerm, SearchOptions, SortSpec,
+};
+use anyhow::{Result, anyhow};
+use chrono::Utc;
+use rayon::prelude::*;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use uuid::Uuid;
 
 #[derive(Debug)]
 pub struct SearchResult {
     pub document: Document,
     pub score: f64,
     pub snippet: String,
+    /// Score breakdown, only filled in when `SearchOptions::explain` is set
+    pub explanation: Option<Explanation>,
+    /// Number of near-duplicates of this document in the index
+    pub duplicates: usize,
+    /// Collapse group of the document, when `SearchOptions::collapse` is set
+    pub group: Option<String>,
+    /// Hits from the same group dropped by collapsing, reported on the group's best hit
+    pub more_in_group: usize,
+    /// Best matching passage of a document split into passages; the snippet is taken from it
+    pub passage: Option<Passage>,
+    /// Index the hit came from, set when several indexes are searched at once
+    pub index: Option<String>,
+}
+
+impl SearchResult {
+    /// The document's path, pointing at the heading of the best passage when it has one.
+    pub fn url(&self) -> String {
+        match self
+            .passage
+            .as_ref()
+            .and_then(|passage| passage.anchor.as_ref())
+        {
+            Some(anchor) => format!("{}#{}", self.document.path, anchor),
+            None => self.document.path.clone(),
+        }
+    }
+}
+
+/// A page of results together with aggregations over the full matching set.
+#[derive(Debug, Default)]
+pub struct SearchResults {
+    pub results: Vec<SearchResult>,
+    pub total_hits: usize,
+    pub facets: Vec<Facet>,
+}
+
+/// Hits of a search before lexical and vector scores are fused, best first.
+#[derive(Debug, Default)]
+pub struct Retrieved {
+    pub lexical: Vec<Hit>,
+    /// Nearest neighbours of the query vector, if the search has one
+    pub nearest: Option<Vec<Hit>>,
+}
+
+impl Retrieved {
+    /// Moves the hits to the index `offset` positions further, placing the hits of one index
+    /// among the shards or indexes ranked together.
+    pub fn offset(mut self, offset: usize) -> Self {
+        for hit in self
+            .lexical
+            .iter_mut()
+            .chain(self.nearest.iter_mut().flatten())
+        {
+            hit.index += offset;
+        }
+        self
+    }
+
+    /// Merges the hits of several shards, keeping the best `num_candidates` neighbours.
+    pub fn merge(parts: impl IntoIterator<Item = Retrieved>, num_candidates: usize) -> Self {
+        let mut merged = Retrieved::default();
+        for part in parts {
+            merged.lexical.extend(part.lexical);
+            if let Some(nearest) = part.nearest {
+                merged.nearest.get_or_insert_with(Vec::new).extend(nearest);
+            }
+        }
+
+        let best_first = |a: &Hit, b: &Hit| {
+            b.score
+                .total_cmp(&a.score)
+                .then(a.doc_id.cmp(&b.doc_id))
+                .then(a.index.cmp(&b.index))
+        };
+        merged.lexical.sort_by(best_first);
+        if let Some(nearest) = &mut merged.nearest {
+            nearest.sort_by(best_first);
+            nearest.truncate(num_candidates);
+        }
+        merged
+    }
+
+    /// Scales the lexical scores so the best is 1.0 and returns the factor they were scaled
+    /// by. Indexes with their own statistics or scoring model score on different scales,
+    /// which normalised scores can be ranked together across. Vector similarities are
+    /// comparable already.
+    pub fn normalize(&mut self) -> f64 {
+        let best = self.lexical.iter().map(|hit| hit.score).fold(0.0, f64::max);
+        if best > 0.0 {
+            for hit in &mut self.lexical {
+                hit.score /= best;
+            }
+            1.0 / best
+        } else {
+            1.0
+        }
+    }
+}
+
+/// Lexical and vector hits keyed by the position of their index and their document.
+pub type Hybrid = HybridHits<(usize, Uuid)>;
+
+/// A scored document of one of the indexes ranked together, by the index's position.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Hit {
+    pub index: usize,
+    pub doc_id: Uuid,
+    pub score: f64,
+}
+
+/// The index a shard belongs to when the shards of several indexes are ranked together.
+#[derive(Debug, Clone)]
+pub struct Owner {
+    /// Name of the index, labelling the shard's results
+    pub name: String,
+    /// Statistics of all shards of the index, see `TfIdfRanker::collection_options`
+    pub collection_stats: Option<Arc<CollectionStats>>,
+    /// Factor `Retrieved::normalize` scaled the lexical scores of the index by
+    pub scale: f64,
 }
 
 pub struct TfIdfRanker;
 
 impl TfIdfRanker {
+    /// Top hits reordered by diversification, at least the requested page.
+    const DIVERSIFY_WINDOW: usize = 50;
+
     pub fn rank_documents(
         index: &InvertedIndex,
         query: &str,
         limit: Option<usize>,
     ) -> Vec<SearchResult> {
-        let scored_docs = index.search(query);
-        let limit = limit.unwrap_or(10);
+        let options = SearchOptions::new().with_limit(limit.unwrap_or(10));
+        Self::rank(index, query, &options).results
+    }
+
+    pub fn rank(index: &InvertedIndex, query: &str, options: &SearchOptions) -> SearchResults {
+        Self::rank_shards(&[index], query, options)
+    }
+
+    /// Ranks the shards of one index as if they were a single index. Shards score with the
+    /// document frequencies and lengths of all shards, gathered in a first pass, and their
+    /// hits are merged before anything else happens to them: fusion, sorting, collapsing
+    /// and diversification all see the hits of every shard at once.
+    pub fn rank_shards(
+        shards: &[&InvertedIndex],
+        query: &str,
+        options: &SearchOptions,
+    ) -> SearchResults {
+        let options = Self::collection_options(shards, query, options);
+        let retrieved = Self::retrieve_shards(shards, query, &options);
+        let (hits, hybrid) = Self::fuse(retrieved, &options);
+        Self::rank_hits(shards, &[], query, hits, hybrid.as_ref(), &options)
+    }
+
+    /// `options` with the collection statistics of all `shards` of an index for `query`,
+    /// unchanged for an index of one shard.
+    pub fn collection_options<'a>(
+        shards: &[&InvertedIndex],
+        query: &str,
+        options: &'a SearchOptions,
+    ) -> Cow<'a, SearchOptions> {
+        match shards {
+            [_] => Cow::Borrowed(options),
+            _ => {
+                let mut collection = CollectionStats::default();
+                for index in shards {
+                    collection.merge(index.collection_stats(query));
+                }
+                Cow::Owned(options.clone().with_collection_stats(Arc::new(collection)))
+            }
+        }
+    }
 
-        scored_docs
+    /// Retrieves the hits of every shard on its own thread and merges them, tagged with the
+    /// shard's position. See `collection_options` for the statistics shards must share.
+    pub fn retrieve_shards(
+        shards: &[&InvertedIndex],
+        query: &str,
+        options: &SearchOptions,
+    ) -> Retrieved {
+        let retrieved: Vec<Retrieved> = shards
+            .par_iter()
+            .enumerate()
+            .map(|(position, index)| Self::retrieve(index, query, options).offset(position))
+            .collect();
+        Retrieved::merge(retrieved, options.num_candidates)
+    }
+
+    /// The lexical hits of one index and, for queries with a vector, its nearest neighbours,
+    /// both best first and at position 0. Searching a shard, `options.collection_stats` must
+    /// cover all shards.
+    pub fn retrieve(index: &InvertedIndex, query: &str, options: &SearchOptions) -> Retrieved {
+        let hits = |scored_docs: Vec<(Uuid, f64)>| {
+            scored_docs
+                .into_iter()
+                .map(|(doc_id, score)| Hit {
+                    index: 0,
+                    doc_id,
+                    score,
+                })
+                .collect()
+        };
+        let lexical = hits(index.search(query, options.collection_stats.as_deref()));
+        let nearest = options.vector.as_ref().map(|vector| {
+            hits(index.vectors.search(
+                vector,
+                options.num_candidates,
+                options.num_candidates.max(options.limit),
+            ))
+        });
+        Retrieved { lexical, nearest }
+    }
+
+    /// Combines lexical hits and nearest neighbours into one score per document. Ranks and
+    /// the best lexical score are taken over the whole lists, so the hits of several shards
+    /// must be merged before they are fused.
+    pub fn fuse(retrieved: Retrieved, options: &SearchOptions) -> (Vec<Hit>, Option<Hybrid>) {
+        let Some(nearest) = retrieved.nearest else {
+            return (retrieved.lexical, None);
+        };
+
+        let keyed = |hits: &[Hit]| -> Vec<_> {
+            hits.iter()
+                .map(|hit| ((hit.index, hit.doc_id), hit.score))
+                .collect()
+        };
+        let hybrid = HybridHits::new(&keyed(&retrieved.lexical), &keyed(&nearest));
+        let hits = hybrid
+            .fuse(&options.fusion)
             .into_iter()
-            .take(limit)
-            .filter_map(|(doc_id, score)| {
-                index.get_document(&doc_id).map(|doc| {
-                    let snippet = Self::generate_snippet(&doc.content, query, 150);
+            .map(|((index, doc_id), score)| Hit {
+                index,
+                doc_id,
+                score,
+            })
+            .collect();
+        (hits, Some(hybrid))
+    }
+
+    /// Applies decay, filters, sorting, collapsing and diversification to the hits of
+    /// `indexes` together and loads the requested page. `owners` holds the index of every
+    /// shard when the shards of several indexes are ranked, and is empty otherwise.
+    pub fn rank_hits(
+        indexes: &[&InvertedIndex],
+        owners: &[Owner],
+        query: &str,
+        mut hits: Vec<Hit>,
+        hybrid: Option<&Hybrid>,
+        options: &SearchOptions,
+    ) -> SearchResults {
+        let now = Utc::now().timestamp();
+        if let Some(decay) = &options.decay {
+            for hit in &mut hits {
+                if let Some(timestamp) = Self::timestamp(indexes[hit.index], &hit.doc_id) {
+                    hit.score *= decay.factor(timestamp, now);
+                }
+            }
+        }
+        if !options.filters.is_empty() {
+            hits.retain(|hit| options.matches(&indexes[hit.index].doc_values, &hit.doc_id));
+        }
+        hits.sort_by(|a, b| {
+            options.sort.compare_in(
+                &indexes[a.index].doc_values,
+                &(a.doc_id, a.score),
+                &indexes[b.index].doc_values,
+                &(b.doc_id, b.score),
+            )
+        });
+        if options.collapse_duplicates {
+            let mut seen = HashSet::new();
+            hits.retain(|hit| seen.insert(indexes[hit.index].duplicates.canonical_of(&hit.doc_id)));
+        }
+
+        let facets = options
+            .facets
+            .iter()
+            .map(|field| {
+                let counts = indexes
+                    .iter()
+                    .enumerate()
+                    .map(|(position, index)| {
+                        let doc_ids = hits
+                            .iter()
+                            .filter(|hit| hit.index == position)
+                            .map(|hit| &hit.doc_id);
+                        index.doc_values.facet_counts(field, doc_ids, usize::MAX)
+                    })
+                    .collect();
+                Facet::merge(field, counts, options.facet_size)
+            })
+            .collect();
+
+        // Facets and the hit count include hits dropped by group collapsing below
+        let total_hits = hits.len();
+        let mut dropped = match &options.collapse {
+            Some(collapse) => collapse.apply(&mut hits, |hit| {
+                collapse.group(indexes[hit.index], &hit.doc_id)
+            }),
+            None => HashMap::new(),
+        };
+        if let Some(lambda) = options.diversify {
+            Self::diversify(indexes, &mut hits, lambda, options.limit);
+        }
+
+        let mut results = Self::load_results(indexes, owners, query, hits, options, now, hybrid);
+        for result in &mut results {
+            if let Some(group) = &result.group {
+                result.more_in_group = dropped.remove(group).unwrap_or(0);
+            }
+        }
+
+        SearchResults {
+            total_hits,
+            results,
+            facets,
+        }
+    }
+
+    /// Ranks the documents of a memory-mapped index, which only supports matching title and
+    /// content. Fails for options other than the limit, see `check_mapped`.
+    pub fn rank_mapped(
+        index: &MappedIndex,
+        query: &str,
+        options: &SearchOptions,
+    ) -> Result<SearchResults> {
+        Self::check_mapped(options)?;
+        let scored_docs = index.search(query)?;
+        let total_hits = scored_docs.len();
+        let mut results = Vec::new();
+        for (doc_id, score) in scored_docs.into_iter().take(options.limit) {
+            let Some(document) = index.get_document(&doc_id)? else {
+                continue;
+            };
+            results.push(SearchResult {
+                snippet: Self::generate_snippet(&document.content, query, 150),
+                document,
+                score,
+                explanation: None,
+                duplicates: 0,
+                group: None,
+                more_in_group: 0,
+                passage: None,
+                index: None,
+            });
+        }
+
+        Ok(SearchResults {
+            results,
+            total_hits,
+            facets: Vec::new(),
+        })
+    }
+
+    /// Fails naming the options a memory-mapped index cannot apply, which hold no doc values,
+    /// vectors or duplicate groups. Ignoring them would return wrong results that look valid.
+    pub fn check_mapped(options: &SearchOptions) -> Result<()> {
+        let requested = [
+            ("facets", !options.facets.is_empty()),
+            ("filter", !options.filters.is_empty()),
+            ("sort", options.sort != SortSpec::relevance()),
+            ("explain", options.explain),
+            ("decay", options.decay.is_some()),
+            ("vector", options.vector.is_some()),
+            ("fusion", options.fusion != Fusion::default()),
+            ("collapse_duplicates", options.collapse_duplicates),
+            ("collapse", options.collapse.is_some()),
+            ("diversify", options.diversify.is_some()),
+        ];
+        let unsupported: Vec<&str> = requested
+            .iter()
+            .filter(|(_, requested)| *requested)
+            .map(|(name, _)| *name)
+            .collect();
+        if unsupported.is_empty() {
+            Ok(())
+        } else {
+            Err(anyhow!(
+                "Not supported by memory-mapped indexes: {}",
+                unsupported.join(", ")
+            ))
+        }
+    }
+
+    fn load_results(
+        indexes: &[&InvertedIndex],
+        owners: &[Owner],
+        query: &str,
+        hits: Vec<Hit>,
+        options: &SearchOptions,
+        now: i64,
+        hybrid: Option<&Hybrid>,
+    ) -> Vec<SearchResult> {
+        hits.into_iter()
+            .take(options.limit)
+            .filter_map(|hit| {
+                let index = indexes[hit.index];
+                let owner = owners.get(hit.index);
+                let collection = match owner {
+                    Some(owner) => owner.collection_stats.as_deref(),
+                    None => options.collection_stats.as_deref(),
+                };
+                index.get_document(&hit.doc_id).map(|doc| {
+                    let passage = index.best_passage(query, &hit.doc_id, collection).cloned();
+                    let snippet = match &passage {
+                        Some(passage) => {
+                            Self::generate_snippet(&passage.text(&doc.content), query, 150)
+                        }
+                        None => Self::generate_snippet(&doc.content, query, 150),
+                    };
                     SearchResult {
-                        document: doc.clone(),
-                        score,
+                        document: (*doc).clone(),
+                        score: hit.score,
                         snippet,
+                        duplicates: index.duplicates.group_size(&hit.doc_id),
+                        group: options
+                            .collapse
+                            .as_ref()
+                            .and_then(|collapse| collapse.group(index, &hit.doc_id)),
+                        more_in_group: 0,
+                        passage,
+                        index: owner.map(|owner| owner.name.clone()),
+                        explanation: options
+                            .explain
+                            .then(|| Self::explain(index, owner, query, &hit, options, now, hybrid))
+                            .flatten(),
                     }
                 })
             })
             .collect()
     }
 
+    /// Index explanation, scaled like the lexical scores of its index when several indexes
+    /// are ranked together, fused with the vector similarity for hybrid queries and
+    /// multiplied by the freshness factor when decay is enabled.
+    fn explain(
+        index: &InvertedIndex,
+        owner: Option<&Owner>,
+        query: &str,
+        hit: &Hit,
+        options: &SearchOptions,
+        now: i64,
+        hybrid: Option<&Hybrid>,
+    ) -> Option<Explanation> {
+        let doc_id = &hit.doc_id;
+        let lexical = match owner {
+            Some(owner) => index
+                .explain(query, doc_id, owner.collection_stats.as_deref())
+                .map(|explanation| {
+                    Explanation::new(
+                        explanation.value * owner.scale,
+                        "score normalised to the best hit of its index, product of:",
+                    )
+                    .with_details(vec![
+                        explanation,
+                        Explanation::new(owner.scale, "1 / best score of the index"),
+                    ])
+                }),
+            None => index.explain(query, doc_id, options.collection_stats.as_deref()),
+        };
+        let explanation = match hybrid {
+            Some(hybrid) => hybrid.explain(&options.fusion, &(hit.index, *doc_id), lexical),
+            None => lexical?,
+        };
+
+        match (&options.decay, Self::timestamp(index, doc_id)) {
+            (Some(decay), Some(timestamp)) => {
+                let freshness = decay.explain(timestamp, now);
+                Some(
+                    Explanation::new(explanation.value * freshness.value, "product of:")
+                        .with_details(vec![explanation, freshness]),
+                )
+            }
+            _ => Some(explanation),
+        }
+    }
+
+    /// Maximal marginal relevance: greedily picks the hit with the best
+    /// `lambda * relevance - (1 - lambda) * similarity to the hits picked so far`, so the
+    /// first page is not filled with variations of one document. Relevance is the score
+    /// divided by the best score, similarity the cosine of the TF-IDF term vectors. Only the
+    /// top hits are reordered, scores stay unchanged.
+    fn diversify(indexes: &[&InvertedIndex], hits: &mut [Hit], lambda: f64, limit: usize) {
+        let lambda = lambda.clamp(0.0, 1.0);
+        let window = hits.len().min(Self::DIVERSIFY_WINDOW.max(limit));
+        let candidates = &mut hits[..window];
+
+        // Hits of several indexes are weighted with the statistics of all of them together
+        let collection = match indexes {
+            [_] => None,
+            _ => {
+                let terms: HashSet<String> = candidates
+                    .iter()
+                    .flat_map(|hit| indexes[hit.index].term_frequencies(&hit.doc_id).into_keys())
+                    .collect();
+                let mut collection = CollectionStats::default();
+                for index in indexes {
+                    collection.merge(index.term_stats(terms.iter().map(String::as_str)));
+                }
+                Some(collection)
+            }
+        };
+        let vectors: Vec<HashMap<String, f64>> = candidates
+            .iter()
+            .map(|hit| {
+                let index = indexes[hit.index];
+                Self::unit_vector(index.term_vector(&hit.doc_id, collection.as_ref()))
+            })
+            .collect();
+        let max_score = candidates.iter().map(|hit| hit.score).fold(0.0, f64::max);
+        let relevance = |score: f64| {
+            if max_score > 0.0 {
+                score / max_score
+            } else {
+                0.0
+            }
+        };
+
+        let mut remaining: Vec<usize> = (0..window).collect();
+        let mut max_similarity = vec![0.0; window];
+        let mut order = Vec::with_capacity(window);
+
+        while !remaining.is_empty() {
+            let (position, _) = remaining
+                .iter()
+                .enumerate()
+                .map(|(position, &i)| {
+                    let mmr = lambda * relevance(candidates[i].score)
+                        - (1.0 - lambda) * max_similarity[i];
+                    (position, mmr)
+                })
+                // The earliest hit wins ties, keeping the original order when nothing differs.
+                // Similarities are sums in hash order, so ties are allowed rounding noise.
+                .fold((0, f64::NEG_INFINITY), |best, current| {
+                    if current.1 > best.1 + 1e-9 {
+                        current
+                    } else {
+                        best
+                    }
+                });
+            let picked = remaining.remove(position);
+            for &i in &remaining {
+                let similarity = Self::cosine(&vectors[picked], &vectors[i]);
+                max_similarity[i] = f64::max(max_similarity[i], similarity);
+            }
+            order.push(candidates[picked]);
+        }
+
+        candidates.copy_from_slice(&order);
+    }
+
+    fn unit_vector(mut vector: HashMap<String, f64>) -> HashMap<String, f64> {
+        let norm = vector.values().map(|w| w * w).sum::<f64>().sqrt();
+        if norm > 0.0 {
+            for weight in vector.values_mut() {
+                *weight /= norm;
+            }
+        }
+        vector
+    }
+
+    fn cosine(a: &HashMap<String, f64>, b: &HashMap<String, f64>) -> f64 {
+        let (small, large) = if a.len() <= b.len() { (a, b) } else { (b, a) };
+        small
+            .iter()
+            .filter_map(|(term, weight)| large.get(term).map(|other| weight * other))
+            .sum()
+    }
+
+    fn timestamp(index: &InvertedIndex, doc_id: &Uuid) -> Option<i64> {
+        index
+            .doc_values
+            .get("timestamp", doc_id)
+            .and_then(|values| values.first())
+            .and_then(|value| value.parse().ok())
+    }
+
     fn generate_snippet(content: &str, query: &str, max_length: usize) -> String {
-        let query_words: Vec<&str> = query.split_whitespace().collect();
+        let query_words: Vec<&str> = query
+            .split_whitespace()
+            .map(|word| QueryTerm::split_boost(word).0)
+            .collect();
         let words: Vec<&str> = content.split_whitespace().collect();
 
         if words.is_empty() {
@@ -0,0 +1,165 @@
+use crate::core::Explanation;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// How matching terms are turned into a relevance score.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ScoringModel {
+    /// `(1 + ln(tf)) * (ln((N + 1) / (df + 1)) + 1)`
+    #[default]
+    TfIdf,
+    /// Okapi BM25 with term frequency saturation `k1` and length normalization `b`
+    Bm25 { k1: f64, b: f64 },
+}
+
+/// Collection statistics of one searchable field.
+#[derive(Debug, Clone, Copy)]
+pub struct FieldStats {
+    pub name: &'static str,
+    /// Number of entries (documents or link targets) that have this field
+    pub doc_count: usize,
+    pub total_tokens: usize,
+    pub boost: f64,
+}
+
+impl FieldStats {
+    /// The same statistics with the boost multiplied by a query-time `boost`.
+    pub fn boosted(self, boost: f64) -> Self {
+        Self {
+            boost: self.boost * boost,
+            ..self
+        }
+    }
+
+    pub fn average_length(&self) -> f64 {
+        if self.doc_count == 0 {
+            0.0
+        } else {
+            self.total_tokens as f64 / self.doc_count as f64
+        }
+    }
+}
+
+/// Text field statistics of a whole collection for the terms of one query, gathered from
+/// every shard before scoring so each shard computes the same IDF.
+#[derive(Debug, Clone, Default)]
+pub struct CollectionStats {
+    pub doc_count: usize,
+    pub total_tokens: usize,
+    /// Documents containing each query term
+    pub document_frequencies: HashMap<String, usize>,
+}
+
+impl CollectionStats {
+    /// Adds the statistics of another shard.
+    pub fn merge(&mut self, other: CollectionStats) {
+        self.doc_count += other.doc_count;
+        self.total_tokens += other.total_tokens;
+        for (term, df) in other.document_frequencies {
+            *self.document_frequencies.entry(term).or_insert(0) += df;
+        }
+    }
+}
+
+impl ScoringModel {
+    /// Score contribution of a term occurring `tf` times in a field of `length` tokens,
+    /// where `df` entries of the field contain the term.
+    pub fn term_weight(&self, field: &FieldStats, tf: usize, df: usize, length: usize) -> f64 {
+        let weight = match self {
+            ScoringModel::TfIdf => Self::tf_score(tf) * Self::idf(field, df),
+            ScoringModel::Bm25 { k1, b } => {
+                Self::bm25_tf(field, tf, *k1, *b, length) * Self::bm25_idf(field, df)
+            }
+        };
+        weight * field.boost
+    }
+
+    /// Mirrors `term_weight`, keeping every intermediate value.
+    pub fn explain_term(
+        &self,
+        field: &FieldStats,
+        term: &str,
+        tf: usize,
+        df: usize,
+        length: usize,
+    ) -> Explanation {
+        let freq = Explanation::new(tf as f64, "freq, occurrences of term within field");
+        let df_detail = Explanation::new(df as f64, "df, entries containing term");
+        let n_detail = Explanation::new(field.doc_count as f64, "N, entries with this field");
+
+        let (tf_part, idf_part) = match self {
+            ScoringModel::TfIdf => (
+                Explanation::new(Self::tf_score(tf), "tf, log normalized as 1 + ln(freq)")
+                    .with_details(vec![freq]),
+                Explanation::new(
+                    Self::idf(field, df),
+                    "idf, computed as ln((N + 1) / (df + 1)) + 1",
+                )
+                .with_details(vec![df_detail, n_detail]),
+            ),
+            ScoringModel::Bm25 { k1, b } => (
+                Explanation::new(
+                    Self::bm25_tf(field, tf, *k1, *b, length),
+                    "tf, saturated as freq * (k1 + 1) / (freq + k1 * (1 - b + b * dl / avgdl))",
+                )
+                .with_details(vec![
+                    freq,
+                    Explanation::new(*k1, "k1, term frequency saturation"),
+                    Explanation::new(*b, "b, length normalization"),
+                    Explanation::new(length as f64, "dl, length of field"),
+                    Explanation::new(field.average_length(), "avgdl, average field length"),
+                ]),
+                Explanation::new(
+                    Self::bm25_idf(field, df),
+                    "idf, computed as ln(1 + (N - df + 0.5) / (df + 0.5))",
+                )
+                .with_details(vec![df_detail, n_detail]),
+            ),
+        };
+
+        Explanation::new(
+            tf_part.value * idf_part.value * field.boost,
+            format!("weight({}:{}), product of:", field.name, term),
+        )
+        .with_details(vec![
+            tf_part,
+            idf_part,
+            Explanation::new(field.boost, "boost"),
+        ])
+    }
+
+    /// Log-normalized term frequency, `1 + ln(tf)` so a single occurrence scores 1.0.
+    fn tf_score(tf: usize) -> f64 {
+        if tf > 0 { 1.0 + (tf as f64).ln() } else { 0.0 }
+    }
+
+    fn idf(field: &FieldStats, document_frequency: usize) -> f64 {
+        if document_frequency == 0 {
+            0.0
+        } else {
+            // Smooth IDF to give some weight even to common terms
+            let smooth_idf =
+                ((field.doc_count as f64 + 1.0) / (document_frequency as f64 + 1.0)).ln();
+            smooth_idf + 1.0 // Add base score to avoid complete zeros
+        }
+    }
+
+    fn bm25_tf(field: &FieldStats, tf: usize, k1: f64, b: f64, length: usize) -> f64 {
+        let tf = tf as f64;
+        let average = field.average_length();
+        let length_ratio = if average > 0.0 {
+            length as f64 / average
+        } else {
+            1.0
+        };
+
+        tf * (k1 + 1.0) / (tf + k1 * (1.0 - b + b * length_ratio))
+    }
+
+    fn bm25_idf(field: &FieldStats, document_frequency: usize) -> f64 {
+        let n = field.doc_count as f64;
+        let df = document_frequency as f64;
+        (1.0 + (n - df + 0.5) / (df + 0.5)).ln()
+    }
+}
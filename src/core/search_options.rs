@@ -0,0 +1,368 @@
+use crate::core::{Collapse, CollectionStats, Decay, DocValues, Fusion};
+use anyhow::{Result, anyhow};
+use std::cmp::Ordering;
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// Restricts results to documents whose `field` column contains `value`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldFilter {
+    pub field: String,
+    pub value: String,
+}
+
+impl FieldFilter {
+    /// Parses a comma-separated list of `field:value` pairs.
+    pub fn parse_list(spec: &str) -> Result<Vec<FieldFilter>> {
+        spec.split(',')
+            .map(str::trim)
+            .filter(|part| !part.is_empty())
+            .map(|part| {
+                let (field, value) = part
+                    .split_once(':')
+                    .ok_or_else(|| anyhow!("Invalid filter '{}', expected field:value", part))?;
+                Ok(FieldFilter {
+                    field: field.trim().to_lowercase(),
+                    value: value.trim().to_string(),
+                })
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
+
+/// One `field:order` entry of a sort specification. The field `score` is the relevance score,
+/// any other name is looked up in the doc-value columns.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SortField {
+    pub field: String,
+    pub order: SortOrder,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SortSpec {
+    pub fields: Vec<SortField>,
+}
+
+impl SortSpec {
+    /// Sorts by descending relevance.
+    pub fn relevance() -> Self {
+        Self {
+            fields: vec![SortField {
+                field: "score".to_string(),
+                order: SortOrder::Desc,
+            }],
+        }
+    }
+
+    /// Parses `date:desc,score:desc` style specifications. The order defaults to
+    /// descending for `score` and ascending for every other field.
+    pub fn parse(spec: &str) -> Result<Self> {
+        let fields = spec
+            .split(',')
+            .map(str::trim)
+            .filter(|part| !part.is_empty())
+            .map(|part| {
+                let (field, order) = match part.split_once(':') {
+                    Some((field, order)) => (field.trim().to_lowercase(), Some(order.trim())),
+                    None => (part.to_lowercase(), None),
+                };
+
+                let order = match order.map(str::to_lowercase).as_deref() {
+                    Some("asc") => SortOrder::Asc,
+                    Some("desc") => SortOrder::Desc,
+                    None if field == "score" => SortOrder::Desc,
+                    None => SortOrder::Asc,
+                    Some(other) => {
+                        return Err(anyhow!(
+                            "Invalid sort order '{}' for field '{}', expected asc or desc",
+                            other,
+                            field
+                        ));
+                    }
+                };
+
+                Ok(SortField { field, order })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        if fields.is_empty() {
+            return Err(anyhow!("Empty sort specification"));
+        }
+
+        Ok(Self { fields })
+    }
+
+    /// Compares two scored documents. Documents missing a field sort after those that have it,
+    /// and ties are broken by score, then path, then id so the order is always deterministic.
+    pub fn compare(&self, doc_values: &DocValues, a: &(Uuid, f64), b: &(Uuid, f64)) -> Ordering {
+        self.compare_in(doc_values, a, doc_values, b)
+    }
+
+    /// Like `compare` for documents of different indexes, each with its own doc values.
+    pub fn compare_in(
+        &self,
+        a_values: &DocValues,
+        a: &(Uuid, f64),
+        b_values: &DocValues,
+        b: &(Uuid, f64),
+    ) -> Ordering {
+        self.fields
+            .iter()
+            .map(|sort| {
+                let ordering = if sort.field == "score" {
+                    a.1.total_cmp(&b.1)
+                } else {
+                    let a_value = a_values.get(&sort.field, &a.0).and_then(|v| v.first());
+                    let b_value = b_values.get(&sort.field, &b.0).and_then(|v| v.first());
+                    match (a_value, b_value) {
+                        (Some(a_value), Some(b_value)) => Self::compare_values(a_value, b_value),
+                        (Some(_), None) => return Ordering::Less,
+                        (None, Some(_)) => return Ordering::Greater,
+                        (None, None) => Ordering::Equal,
+                    }
+                };
+
+                match sort.order {
+                    SortOrder::Asc => ordering,
+                    SortOrder::Desc => ordering.reverse(),
+                }
+            })
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or(Ordering::Equal)
+            .then_with(|| b.1.total_cmp(&a.1))
+            .then_with(|| {
+                let a_path = a_values.get("path", &a.0).and_then(|v| v.first());
+                let b_path = b_values.get("path", &b.0).and_then(|v| v.first());
+                a_path.cmp(&b_path)
+            })
+            .then_with(|| a.0.cmp(&b.0))
+    }
+
+    /// Numbers compare numerically and sort before everything else, which compares
+    /// case-insensitively. Keeping the two apart makes this a total order for mixed columns.
+    fn compare_values(a: &str, b: &str) -> Ordering {
+        match (a.parse::<f64>(), b.parse::<f64>()) {
+            (Ok(a_number), Ok(b_number)) => a_number
+                .total_cmp(&b_number)
+                .then_with(|| a.to_lowercase().cmp(&b.to_lowercase())),
+            (Ok(_), Err(_)) => Ordering::Less,
+            (Err(_), Ok(_)) => Ordering::Greater,
+            (Err(_), Err(_)) => a.to_lowercase().cmp(&b.to_lowercase()),
+        }
+    }
+}
+
+impl Default for SortSpec {
+    fn default() -> Self {
+        Self::relevance()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SearchOptions {
+    pub limit: usize,
+    pub facets: Vec<String>,
+    pub facet_size: usize,
+    pub filters: Vec<FieldFilter>,
+    pub sort: SortSpec,
+    pub explain: bool,
+    /// Freshness decay applied to each document's timestamp, off by default
+    pub decay: Option<Decay>,
+    /// Query embedding; combined with the text query through `fusion`, or used alone
+    /// when the text query is empty
+    pub vector: Option<Vec<f32>>,
+    pub fusion: Fusion,
+    /// Nearest neighbours retrieved for the query vector before filtering and fusion
+    pub num_candidates: usize,
+    /// Show only the best match of each group of near-duplicates, off by default so results
+    /// list every indexed copy unless asked otherwise
+    pub collapse_duplicates: bool,
+    /// Keep only the best hits per host, directory or field
+    pub collapse: Option<Collapse>,
+    /// Maximal marginal relevance trade-off between relevance (1.0) and novelty (0.0),
+    /// off by default
+    pub diversify: Option<f64>,
+    /// Statistics of the whole collection when ranking one of its shards
+    pub collection_stats: Option<Arc<CollectionStats>>,
+}
+
+impl SearchOptions {
+    pub fn new() -> Self {
+        Self {
+            limit: 10,
+            facets: Vec::new(),
+            facet_size: 10,
+            filters: Vec::new(),
+            sort: SortSpec::relevance(),
+            explain: false,
+            decay: None,
+            vector: None,
+            fusion: Fusion::default(),
+            num_candidates: 100,
+            collapse_duplicates: false,
+            collapse: None,
+            diversify: None,
+            collection_stats: None,
+        }
+    }
+
+    pub fn with_limit(mut self, limit: usize) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    /// Field names are matched case-insensitively; blank names are dropped.
+    pub fn with_facets(mut self, facets: Vec<String>) -> Self {
+        self.facets = facets
+            .iter()
+            .map(|facet| facet.trim().to_lowercase())
+            .filter(|facet| !facet.is_empty())
+            .collect();
+        self
+    }
+
+    pub fn with_facet_size(mut self, facet_size: usize) -> Self {
+        self.facet_size = facet_size;
+        self
+    }
+
+    pub fn with_filters(mut self, filters: Vec<FieldFilter>) -> Self {
+        self.filters = filters;
+        self
+    }
+
+    pub fn with_sort(mut self, sort: SortSpec) -> Self {
+        self.sort = sort;
+        self
+    }
+
+    pub fn with_explain(mut self, explain: bool) -> Self {
+        self.explain = explain;
+        self
+    }
+
+    pub fn with_decay(mut self, decay: Decay) -> Self {
+        self.decay = Some(decay);
+        self
+    }
+
+    pub fn with_vector(mut self, vector: Vec<f32>) -> Self {
+        self.vector = Some(vector);
+        self
+    }
+
+    pub fn with_fusion(mut self, fusion: Fusion) -> Self {
+        self.fusion = fusion;
+        self
+    }
+
+    pub fn with_num_candidates(mut self, num_candidates: usize) -> Self {
+        self.num_candidates = num_candidates;
+        self
+    }
+
+    pub fn with_collapse_duplicates(mut self, collapse_duplicates: bool) -> Self {
+        self.collapse_duplicates = collapse_duplicates;
+        self
+    }
+
+    pub fn with_collapse(mut self, collapse: Collapse) -> Self {
+        self.collapse = Some(collapse);
+        self
+    }
+
+    pub fn with_diversify(mut self, lambda: f64) -> Self {
+        self.diversify = Some(lambda);
+        self
+    }
+
+    pub fn with_collection_stats(mut self, collection_stats: Arc<CollectionStats>) -> Self {
+        self.collection_stats = Some(collection_stats);
+        self
+    }
+
+    /// Filters on the same field are OR-ed, filters on different fields AND-ed.
+    pub fn matches(&self, doc_values: &DocValues, doc_id: &Uuid) -> bool {
+        let mut fields: Vec<&str> = self.filters.iter().map(|f| f.field.as_str()).collect();
+        fields.sort_unstable();
+        fields.dedup();
+
+        fields.into_iter().all(|field| {
+            let values = doc_values.get(field, doc_id).unwrap_or_default();
+            self.filters
+                .iter()
+                .filter(|filter| filter.field == field)
+                .any(|filter| values.iter().any(|v| v.eq_ignore_ascii_case(&filter.value)))
+        })
+    }
+}
+
+impl Default for SearchOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compare_values_is_a_total_order_for_mixed_values() {
+        let mut values = vec!["1a", "10", "9", "b", "A", "-2.5", "10.0", "z9"];
+        values.sort_by(|a, b| SortSpec::compare_values(a, b));
+        assert_eq!(values, ["-2.5", "9", "10", "10.0", "1a", "A", "b", "z9"]);
+
+        for a in &values {
+            for b in &values {
+                for c in &values {
+                    let ab = SortSpec::compare_values(a, b);
+                    if ab == SortSpec::compare_values(b, c) && ab.is_ne() {
+                        assert_eq!(SortSpec::compare_values(a, c), ab, "{} {} {}", a, b, c);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn parses_sort_specs() {
+        let spec = SortSpec::parse("Timestamp:DESC, title ,score").unwrap();
+        let fields: Vec<(&str, SortOrder)> = spec
+            .fields
+            .iter()
+            .map(|field| (field.field.as_str(), field.order))
+            .collect();
+        assert_eq!(
+            fields,
+            [
+                ("timestamp", SortOrder::Desc),
+                ("title", SortOrder::Asc),
+                ("score", SortOrder::Desc),
+            ]
+        );
+        assert_eq!(SortSpec::parse("score").unwrap(), SortSpec::relevance());
+
+        for spec in ["", " , ", "title:up"] {
+            assert!(SortSpec::parse(spec).is_err(), "{:?}", spec);
+        }
+    }
+
+    #[test]
+    fn parses_filter_lists() {
+        let filters = FieldFilter::parse_list(" Tag: rust ,, domain:docs.rs:443").unwrap();
+        let filters: Vec<(&str, &str)> = filters
+            .iter()
+            .map(|filter| (filter.field.as_str(), filter.value.as_str()))
+            .collect();
+        assert_eq!(filters, [("tag", "rust"), ("domain", "docs.rs:443")]);
+        assert!(FieldFilter::parse_list("").unwrap().is_empty());
+        assert!(FieldFilter::parse_list("tag:rust,rust").is_err());
+    }
+}
@@ -0,0 +1,46 @@
+use crate::core::tokenizer::AnalyzerConfig;
+use crate::core::{DuplicateSettings, PassageSettings, ScoringModel};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+/// Per-index configuration, persisted together with the index it describes.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IndexSettings {
+    pub analyzer: AnalyzerConfig,
+    pub scoring: ScoringModel,
+    /// How strongly link authority lifts a score: `score * (1 + authority_weight * authority)`
+    pub authority_weight: f64,
+    /// Boost of the anchor text field relative to the title/content field
+    pub anchor_boost: f64,
+    /// Near-duplicate detection at index time
+    pub duplicates: DuplicateSettings,
+    /// Splitting of long documents into separately ranked passages
+    pub passages: PassageSettings,
+}
+
+impl Default for IndexSettings {
+    fn default() -> Self {
+        Self {
+            analyzer: AnalyzerConfig::default(),
+            scoring: ScoringModel::default(),
+            authority_weight: 0.5,
+            anchor_boost: 1.5,
+            duplicates: DuplicateSettings::default(),
+            passages: PassageSettings::default(),
+        }
+    }
+}
+
+impl IndexSettings {
+    /// Reads settings from a JSON file such as
+    /// `{"scoring": {"bm25": {"k1": 1.2, "b": 0.75}}, "analyzer": {"stop_words": false}}`.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let json = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read settings file: {:?}", path))?;
+        serde_json::from_str(&json).with_context(|| format!("Invalid settings file: {:?}", path))
+    }
+}
@@ -1,30 +1,66 @@
 use regex::Regex;
+use serde::{Deserialize, Serialize};
 use std::collections::HashSet;
 
-#[derive(Debug)]
+/// Controls how text is split into index terms.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AnalyzerConfig {
+    /// Words shorter than this are dropped
+    pub min_token_length: usize,
+    /// Drop common English words such as "the" or "and"
+    pub stop_words: bool,
+}
+
+impl Default for AnalyzerConfig {
+    fn default() -> Self {
+        Self {
+            min_token_length: 3,
+            stop_words: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Tokenizer {
     pub word_regex: Regex,
     stop_words: HashSet<String>,
+    config: AnalyzerConfig,
 }
 
 impl Tokenizer {
     pub fn new() -> Self {
+        Self::with_config(AnalyzerConfig::default())
+    }
+
+    pub fn with_config(config: AnalyzerConfig) -> Self {
         let word_regex = Regex::new(r"\b[a-zA-Z]+\b").unwrap();
-        let stop_words = Self::load_stop_words();
+        let stop_words = if config.stop_words {
+            Self::load_stop_words()
+        } else {
+            HashSet::new()
+        };
 
         Self {
             word_regex,
             stop_words,
+            config,
         }
     }
 
+    pub fn config(&self) -> &AnalyzerConfig {
+        &self.config
+    }
+
     pub fn tokenize(&self, text: &str) -> Vec<String> {
         let text = text.to_lowercase();
 
         self.word_regex
             .find_iter(&text)
             .map(|mat| mat.as_str().to_string())
-            .filter(|word| word.len() > 2 && !self.stop_words.contains(word))
+            .filter(|word| {
+                word.len() >= self.config.min_token_length && !self.stop_words.contains(word)
+            })
             .collect()
     }
 
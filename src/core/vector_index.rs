@@ -0,0 +1,444 @@
+use anyhow::{Result, anyhow};
+use imbl::HashMap;
+use serde::{Deserialize, Serialize};
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashSet};
+use uuid::Uuid;
+
+/// One stored vector with its links, the unit a storage keeps per document.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VectorNode {
+    /// Unit length copy of the embedding, so cosine similarity is a dot product
+    vector: Vec<f32>,
+    /// Neighbor ids per layer, from layer 0 up to the node's level
+    layers: Vec<Vec<Uuid>>,
+}
+
+impl VectorNode {
+    fn level(&self) -> usize {
+        self.layers.len() - 1
+    }
+}
+
+/// A node id ordered by its distance to the query.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Candidate {
+    distance: f32,
+    id: Uuid,
+}
+
+impl Eq for Candidate {}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.distance
+            .total_cmp(&other.distance)
+            .then_with(|| self.id.cmp(&other.id))
+    }
+}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Approximate nearest neighbour index over document embeddings (HNSW, cosine similarity).
+///
+/// Every embedding must have the dimensionality of the first one inserted. Nodes are kept in
+/// a persistent map, so copies share them until changed.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct VectorIndex {
+    dimensions: usize,
+    nodes: HashMap<Uuid, VectorNode>,
+    entry_point: Option<Uuid>,
+}
+
+impl VectorIndex {
+    /// Neighbors kept per node on the upper layers, twice as many on layer 0
+    const M: usize = 16;
+    const EF_CONSTRUCTION: usize = 100;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reassembles an index from its nodes, see `node` and `entry_point`.
+    pub fn from_nodes(
+        dimensions: usize,
+        entry_point: Option<Uuid>,
+        nodes: HashMap<Uuid, VectorNode>,
+    ) -> Self {
+        Self {
+            dimensions,
+            nodes,
+            entry_point,
+        }
+    }
+
+    pub fn node(&self, id: &Uuid) -> Option<&VectorNode> {
+        self.nodes.get(id)
+    }
+
+    /// The node searches start from, on the top layer.
+    pub fn entry_point(&self) -> Option<Uuid> {
+        self.entry_point
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Dimensionality of the stored vectors, 0 while the index is empty.
+    pub fn dimensions(&self) -> usize {
+        if self.nodes.is_empty() {
+            0
+        } else {
+            self.dimensions
+        }
+    }
+
+    /// Checks that `vector` can be inserted or searched for.
+    pub fn validate(&self, vector: &[f32]) -> Result<()> {
+        if vector.is_empty() {
+            return Err(anyhow!("Embedding must not be empty"));
+        }
+        if vector.iter().any(|v| !v.is_finite()) {
+            return Err(anyhow!("Embedding contains NaN or infinite values"));
+        }
+        let dimensions = self.dimensions();
+        if dimensions != 0 && vector.len() != dimensions {
+            return Err(anyhow!(
+                "Embedding has {} dimensions, index expects {}",
+                vector.len(),
+                dimensions
+            ));
+        }
+        Ok(())
+    }
+
+    /// Adds or replaces a vector and returns the other nodes whose links changed.
+    pub fn insert(&mut self, id: Uuid, vector: &[f32]) -> Result<Vec<Uuid>> {
+        self.validate(vector)?;
+        let mut changed = self.remove(&id);
+
+        if self.nodes.is_empty() {
+            self.dimensions = vector.len();
+        }
+
+        let vector = Self::normalize(vector);
+        let level = Self::random_level(&id);
+
+        let Some(entry_point) = self.entry_point else {
+            self.nodes.insert(
+                id,
+                VectorNode {
+                    vector,
+                    layers: vec![Vec::new(); level + 1],
+                },
+            );
+            self.entry_point = Some(id);
+            return Ok(changed);
+        };
+
+        let top_level = self.nodes[&entry_point].level();
+        let mut entry_points = vec![entry_point];
+
+        // Greedy descent through the layers above the new node's level
+        for layer in (level + 1..=top_level).rev() {
+            entry_points = self.search_layer(&vector, &entry_points, 1, layer)[..1]
+                .iter()
+                .map(|c| c.id)
+                .collect();
+        }
+
+        let mut layers = vec![Vec::new(); level + 1];
+        let mut linked = Vec::new();
+        for layer in (0..=level.min(top_level)).rev() {
+            let candidates =
+                self.search_layer(&vector, &entry_points, Self::EF_CONSTRUCTION, layer);
+            layers[layer] = candidates
+                .iter()
+                .take(Self::M)
+                .map(|candidate| candidate.id)
+                .collect();
+            linked.push(layer);
+            entry_points = candidates.iter().map(|candidate| candidate.id).collect();
+        }
+
+        self.nodes.insert(id, VectorNode { vector, layers });
+
+        for layer in linked {
+            let neighbors = self.nodes[&id].layers[layer].clone();
+            for neighbor in neighbors {
+                self.connect(neighbor, id, layer);
+                changed.push(neighbor);
+            }
+        }
+
+        if level > top_level {
+            self.entry_point = Some(id);
+        }
+
+        changed.sort();
+        changed.dedup();
+        Ok(changed)
+    }
+
+    /// Removes a vector and every link pointing at it, and returns the nodes that linked to
+    /// it. Returns nothing for unknown ids.
+    pub fn remove(&mut self, id: &Uuid) -> Vec<Uuid> {
+        if self.nodes.remove(id).is_none() {
+            return Vec::new();
+        }
+
+        // Only nodes linking to it are changed, the others stay shared with copies
+        let linking: Vec<Uuid> = self
+            .nodes
+            .iter()
+            .filter(|(_, node)| node.layers.iter().any(|neighbors| neighbors.contains(id)))
+            .map(|(linking, _)| *linking)
+            .collect();
+        for linking in &linking {
+            for neighbors in &mut self.nodes[linking].layers {
+                neighbors.retain(|neighbor| neighbor != id);
+            }
+        }
+
+        if self.entry_point == Some(*id) {
+            self.entry_point = self
+                .nodes
+                .iter()
+                .max_by(|a, b| a.1.level().cmp(&b.1.level()).then_with(|| b.0.cmp(a.0)))
+                .map(|(id, _)| *id);
+        }
+
+        linking
+    }
+
+    /// The approximately `k` most similar vectors to `query` with their cosine similarity,
+    /// best first. `ef` trades speed for recall and is raised to at least `k`.
+    pub fn search(&self, query: &[f32], k: usize, ef: usize) -> Vec<(Uuid, f64)> {
+        let Some(entry_point) = self.entry_point else {
+            return Vec::new();
+        };
+        if k == 0 || self.validate(query).is_err() {
+            return Vec::new();
+        }
+
+        let query = Self::normalize(query);
+        let mut entry_points = vec![entry_point];
+        for layer in (1..=self.nodes[&entry_point].level()).rev() {
+            entry_points = self.search_layer(&query, &entry_points, 1, layer)[..1]
+                .iter()
+                .map(|c| c.id)
+                .collect();
+        }
+
+        self.search_layer(&query, &entry_points, ef.max(k), 0)
+            .into_iter()
+            .take(k)
+            .map(|candidate| (candidate.id, 1.0 - candidate.distance as f64))
+            .collect()
+    }
+
+    /// Best-first search of one layer, returning up to `ef` candidates closest first.
+    fn search_layer(
+        &self,
+        query: &[f32],
+        entry_points: &[Uuid],
+        ef: usize,
+        layer: usize,
+    ) -> Vec<Candidate> {
+        let mut visited: HashSet<Uuid> = entry_points.iter().copied().collect();
+        let mut candidates: BinaryHeap<Reverse<Candidate>> = BinaryHeap::new();
+        let mut nearest: BinaryHeap<Candidate> = BinaryHeap::new();
+
+        for id in entry_points {
+            let candidate = Candidate {
+                distance: self.distance(query, id),
+                id: *id,
+            };
+            candidates.push(Reverse(candidate));
+            nearest.push(candidate);
+        }
+
+        while let Some(Reverse(current)) = candidates.pop() {
+            if let Some(furthest) = nearest.peek()
+                && current.distance > furthest.distance
+                && nearest.len() >= ef
+            {
+                break;
+            }
+
+            let Some(neighbors) = self.nodes[&current.id].layers.get(layer) else {
+                continue;
+            };
+
+            for neighbor in neighbors {
+                if !visited.insert(*neighbor) {
+                    continue;
+                }
+
+                let candidate = Candidate {
+                    distance: self.distance(query, neighbor),
+                    id: *neighbor,
+                };
+                let closer = nearest
+                    .peek()
+                    .is_none_or(|furthest| candidate.distance < furthest.distance);
+                if nearest.len() < ef || closer {
+                    candidates.push(Reverse(candidate));
+                    nearest.push(candidate);
+                    if nearest.len() > ef {
+                        nearest.pop();
+                    }
+                }
+            }
+        }
+
+        nearest.into_sorted_vec()
+    }
+
+    /// Adds a link `from -> to`, keeping only the closest neighbors once the layer is full.
+    fn connect(&mut self, from: Uuid, to: Uuid, layer: usize) {
+        let max_neighbors = if layer == 0 { 2 * Self::M } else { Self::M };
+
+        let mut neighbors = self.nodes[&from].layers[layer].clone();
+        if neighbors.contains(&to) {
+            return;
+        }
+        neighbors.push(to);
+
+        if neighbors.len() > max_neighbors {
+            let origin = &self.nodes[&from].vector;
+            let mut by_distance: Vec<Candidate> = neighbors
+                .iter()
+                .map(|id| Candidate {
+                    distance: self.distance(origin, id),
+                    id: *id,
+                })
+                .collect();
+            by_distance.sort();
+            neighbors = by_distance
+                .into_iter()
+                .take(max_neighbors)
+                .map(|candidate| candidate.id)
+                .collect();
+        }
+
+        if let Some(node) = self.nodes.get_mut(&from) {
+            node.layers[layer] = neighbors;
+        }
+    }
+
+    fn distance(&self, query: &[f32], id: &Uuid) -> f32 {
+        let vector = &self.nodes[id].vector;
+        1.0 - query.iter().zip(vector).map(|(a, b)| a * b).sum::<f32>()
+    }
+
+    fn normalize(vector: &[f32]) -> Vec<f32> {
+        let norm = vector.iter().map(|v| v * v).sum::<f32>().sqrt();
+        if norm == 0.0 {
+            vector.to_vec()
+        } else {
+            vector.iter().map(|v| v / norm).collect()
+        }
+    }
+
+    /// Draws the node's top layer from the id, which is random for v4 UUIDs, so the level
+    /// is stable across rebuilds: `floor(-ln(u) / ln(M))` for `u` uniform in `(0, 1]`.
+    fn random_level(id: &Uuid) -> usize {
+        // The low bits of a v4 UUID are random, the high ones hold the variant
+        let bits = id.as_u64_pair().1 & ((1 << 53) - 1);
+        let uniform = (bits + 1) as f64 / (1u64 << 53) as f64;
+        (-uniform.ln() / (Self::M as f64).ln()).floor() as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// SplitMix64, for vectors and ids that are random but the same on every run
+    fn random(state: &mut u64) -> u64 {
+        *state = state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = *state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    fn random_vector(state: &mut u64, dimensions: usize) -> Vec<f32> {
+        (0..dimensions)
+            .map(|_| (random(state) >> 40) as f32 / (1u64 << 24) as f32 - 0.5)
+            .collect()
+    }
+
+    fn cosine(a: &[f32], b: &[f32]) -> f64 {
+        let dot: f32 = a.iter().zip(b).map(|(a, b)| a * b).sum();
+        let norm = |v: &[f32]| v.iter().map(|v| v * v).sum::<f32>().sqrt();
+        (dot / (norm(a) * norm(b))) as f64
+    }
+
+    #[test]
+    fn finds_most_of_the_exact_nearest_neighbours() {
+        let mut state = 42;
+        let mut index = VectorIndex::new();
+        let mut vectors = Vec::new();
+        for _ in 0..1000 {
+            let id = Uuid::from_u64_pair(random(&mut state), random(&mut state));
+            let vector = random_vector(&mut state, 16);
+            index.insert(id, &vector).unwrap();
+            vectors.push((id, vector));
+        }
+
+        let k = 10;
+        let mut found = 0;
+        for _ in 0..50 {
+            let query = random_vector(&mut state, 16);
+            let mut exact: Vec<(Uuid, f64)> = vectors
+                .iter()
+                .map(|(id, vector)| (*id, cosine(&query, vector)))
+                .collect();
+            exact.sort_by(|a, b| b.1.total_cmp(&a.1));
+            let exact: HashSet<Uuid> = exact.iter().take(k).map(|(id, _)| *id).collect();
+
+            let approximate = index.search(&query, k, 64);
+            assert_eq!(approximate.len(), k);
+            found += approximate
+                .iter()
+                .filter(|(id, _)| exact.contains(id))
+                .count();
+        }
+
+        let recall = found as f64 / (50 * k) as f64;
+        assert!(recall >= 0.9, "recall {} below 0.9", recall);
+    }
+
+    #[test]
+    fn removed_vectors_are_not_found() {
+        let mut state = 7;
+        let mut index = VectorIndex::new();
+        let ids: Vec<Uuid> = (0..200)
+            .map(|_| Uuid::from_u64_pair(random(&mut state), random(&mut state)))
+            .collect();
+        for id in &ids {
+            index.insert(*id, &random_vector(&mut state, 8)).unwrap();
+        }
+        for id in ids.iter().step_by(2) {
+            index.remove(id);
+        }
+
+        assert_eq!(index.len(), 100);
+        let query = random_vector(&mut state, 8);
+        let results = index.search(&query, 20, 64);
+        assert_eq!(results.len(), 20);
+        let removed: HashSet<&Uuid> = ids.iter().step_by(2).collect();
+        assert!(results.iter().all(|(id, _)| !removed.contains(id)));
+    }
+}
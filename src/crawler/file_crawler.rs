@@ -1,7 +1,9 @@
-use crate::core::Document;
+use crate::core::{Document, Section, parse_timestamp};
 use anyhow::{Context, Result};
+use std::collections::HashMap;
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
 use walkdir::WalkDir;
 
 pub struct FileCrawler {
@@ -16,40 +18,177 @@ impl FileCrawler {
     }
 
     pub fn crawl(&self) -> Result<Vec<Document>> {
-        let mut documents = Vec::new();
-
-        for entry in WalkDir::new(&self.root_path) {
-            let entry = entry.context("Failed to read directory entry")?;
-
-            if entry.file_type().is_file() {
-                if let Some(extension) = entry.path().extension() {
-                    if extension == "txt" || extension == "md" {
-                        match self.process_file(entry.path()) {
-                            Ok(doc) => documents.push(doc),
-                            Err(e) => {
-                                log::warn!("Failed to process file {:?}: {}", entry.path(), e);
-                            }
+        self.documents().collect()
+    }
+
+    /// Reads the `.txt` and `.md` files one at a time while walking the directory. Files
+    /// that cannot be read are logged and skipped, unreadable directories end the walk with
+    /// an error.
+    pub fn documents(&self) -> impl Iterator<Item = Result<Document>> + '_ {
+        WalkDir::new(&self.root_path)
+            .into_iter()
+            .filter_map(move |entry| {
+                let entry = match entry.context("Failed to read directory entry") {
+                    Ok(entry) => entry,
+                    Err(e) => return Some(Err(e)),
+                };
+
+                if entry.file_type().is_file()
+                    && let Some(extension) = entry.path().extension()
+                    && (extension == "txt" || extension == "md")
+                {
+                    match self.process_file(entry.path()) {
+                        Ok(doc) => return Some(Ok(doc)),
+                        Err(e) => {
+                            log::warn!("Failed to process file {:?}: {}", entry.path(), e);
                         }
                     }
                 }
-            }
-        }
-
-        Ok(documents)
+                None
+            })
     }
 
     fn process_file(&self, path: &Path) -> Result<Document> {
-        let content =
+        let raw =
             fs::read_to_string(path).with_context(|| format!("Failed to read file: {:?}", path))?;
 
-        let title = path
-            .file_stem()
-            .and_then(|stem| stem.to_str())
-            .unwrap_or("Untitled")
-            .to_string();
+        let (mut metadata, content) = Self::split_front_matter(&raw);
+
+        let title = metadata.remove("title").unwrap_or_else(|| {
+            path.file_stem()
+                .and_then(|stem| stem.to_str())
+                .unwrap_or("Untitled")
+                .to_string()
+        });
+
+        // A date in the front matter wins over the file's modification time
+        let timestamp = metadata
+            .get("date")
+            .and_then(|date| parse_timestamp(date))
+            .or_else(|| Self::modified_time(path));
+
+        // An unusable boost is not worth dropping the document for
+        let boost = match metadata.remove("boost") {
+            Some(boost) => boost.trim().parse().unwrap_or_else(|_| {
+                log::warn!(
+                    "Ignoring invalid boost '{}' in {:?}, using 1.0",
+                    boost,
+                    path
+                );
+                1.0
+            }),
+            None => 1.0,
+        };
+
+        let sections = if path.extension().is_some_and(|extension| extension == "md") {
+            Self::markdown_sections(content)
+        } else {
+            Vec::new()
+        };
 
         let path_str = path.to_string_lossy().to_string();
 
-        Ok(Document::new(title, content, path_str))
+        Ok(Document::new(title, content.to_string(), path_str)
+            .with_metadata(metadata)
+            .with_timestamp(timestamp)
+            .with_boost(boost)
+            .with_sections(sections))
+    }
+
+    /// ATX headings (`## Title`) outside fenced code blocks, with GitHub style anchors.
+    fn markdown_sections(content: &str) -> Vec<Section> {
+        let mut sections = Vec::new();
+        let mut anchors: HashMap<String, usize> = HashMap::new();
+        let mut position = 0;
+        let mut in_code_block = false;
+
+        for line in content.lines() {
+            let line = line.trim_start();
+            if line.starts_with("```") || line.starts_with("~~~") {
+                in_code_block = !in_code_block;
+            } else if !in_code_block && let Some(heading) = Self::markdown_heading(line) {
+                // Repeated headings get -1, -2, ... appended like on GitHub
+                let slug = Self::slugify(heading);
+                let seen = anchors.entry(slug.clone()).or_insert(0);
+                let anchor = match *seen {
+                    0 => slug,
+                    n => format!("{}-{}", slug, n),
+                };
+                *seen += 1;
+
+                sections.push(Section {
+                    heading: heading.to_string(),
+                    anchor: Some(anchor),
+                    start: position,
+                });
+            }
+            position += line.split_whitespace().count();
+        }
+
+        sections
+    }
+
+    fn markdown_heading(line: &str) -> Option<&str> {
+        let level = line.chars().take_while(|c| *c == '#').count();
+        let text = line[level..].strip_prefix([' ', '\t'])?;
+        let text = text.trim().trim_end_matches('#').trim();
+        ((1..=6).contains(&level) && !text.is_empty()).then_some(text)
+    }
+
+    fn slugify(heading: &str) -> String {
+        heading
+            .to_lowercase()
+            .chars()
+            .filter_map(|c| match c {
+                c if c.is_alphanumeric() || c == '-' || c == '_' => Some(c),
+                c if c.is_whitespace() => Some('-'),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn modified_time(path: &Path) -> Option<i64> {
+        let modified = fs::metadata(path).and_then(|meta| meta.modified()).ok()?;
+        modified
+            .duration_since(UNIX_EPOCH)
+            .ok()
+            .map(|duration| duration.as_secs() as i64)
+    }
+
+    /// Splits a leading `---` delimited block of `key: value` lines off the file.
+    ///
+    /// List values written as `[a, b]` are flattened to `a, b`.
+    fn split_front_matter(raw: &str) -> (HashMap<String, String>, &str) {
+        let mut metadata = HashMap::new();
+
+        let Some(rest) = raw
+            .strip_prefix("---\n")
+            .or_else(|| raw.strip_prefix("---\r\n"))
+        else {
+            return (metadata, raw);
+        };
+
+        let Some(end) = rest.find("\n---") else {
+            return (metadata, raw);
+        };
+
+        for line in rest[..end].lines() {
+            if let Some((key, value)) = line.split_once(':') {
+                let value = value
+                    .trim()
+                    .trim_start_matches('[')
+                    .trim_end_matches(']')
+                    .split(',')
+                    .map(|part| part.trim().trim_matches(|c| c == '"' || c == '\''))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                metadata.insert(key.trim().to_lowercase(), value);
+            }
+        }
+
+        let body = &rest[end + "\n---".len()..];
+        let body = body.split_once('\n').map_or("", |(_, body)| body);
+
+        (metadata, body)
     }
 }
@@ -2,4 +2,4 @@ pub mod file_crawler;
 pub mod web_crawler;
 
 pub use file_crawler::FileCrawler;
-pub use web_crawler::WebCrawler;
+pub use web_crawler::{CrawledPage, WebCrawler};
@@ -1,5 +1,7 @@
-use crate::core::Document;
+use crate::core::{Document, Link, LinkGraph, Section, parse_timestamp};
 use anyhow::{Context, Result};
+use chrono::Utc;
+use futures::stream::{self, Stream, StreamExt};
 use reqwest::Client;
 use scraper::{Html, Selector};
 use std::collections::{HashSet, VecDeque};
@@ -7,6 +9,31 @@ use std::time::Duration;
 use tokio::time::sleep;
 use url::Url;
 
+/// A crawled document with the outgoing links found on it.
+#[derive(Debug, Clone)]
+pub struct CrawledPage {
+    pub document: Document,
+    /// `None` for documents that cannot link, such as local files
+    pub links: Option<Vec<Link>>,
+}
+
+impl From<Document> for CrawledPage {
+    fn from(document: Document) -> Self {
+        Self {
+            document,
+            links: None,
+        }
+    }
+}
+
+/// Position of a running crawl.
+struct CrawlState {
+    queue: VecDeque<Url>,
+    visited: HashSet<String>,
+    pages_crawled: usize,
+    requests: usize,
+}
+
 pub struct WebCrawler {
     client: Client,
     max_pages: usize,
@@ -46,62 +73,107 @@ impl WebCrawler {
     }
 
     pub async fn crawl(&self, start_urls: Vec<String>) -> Result<Vec<Document>> {
+        let (documents, _) = self.crawl_with_links(start_urls).await?;
+        Ok(documents)
+    }
+
+    /// Crawls like `crawl`, also returning the outgoing links of every crawled page.
+    pub async fn crawl_with_links(
+        &self,
+        start_urls: Vec<String>,
+    ) -> Result<(Vec<Document>, LinkGraph)> {
         let mut documents = Vec::new();
-        let mut visited = HashSet::new();
-        let mut queue = VecDeque::new();
+        let mut link_graph = LinkGraph::new();
 
-        // Add start URLs to queue
-        for url in start_urls {
-            if let Ok(parsed_url) = Url::parse(&url) {
-                queue.push_back(parsed_url);
-            }
+        let pages = self.pages(start_urls);
+        futures::pin_mut!(pages);
+        while let Some(page) = pages.next().await {
+            link_graph.set_outlinks(page.document.path.clone(), page.links.unwrap_or_default());
+            documents.push(page.document);
         }
 
-        let mut pages_crawled = 0;
+        Ok((documents, link_graph))
+    }
+
+    /// Crawls breadth-first from `start_urls`, yielding every page as soon as it is parsed.
+    /// Pages that fail to load are logged and skipped.
+    pub fn pages(&self, start_urls: Vec<String>) -> impl Stream<Item = CrawledPage> + '_ {
+        // Add start URLs to queue
+        let queue: VecDeque<Url> = start_urls
+            .iter()
+            .filter_map(|url| Url::parse(url).ok())
+            .collect();
+        let state = CrawlState {
+            queue,
+            visited: HashSet::new(),
+            pages_crawled: 0,
+            requests: 0,
+        };
+
+        stream::unfold(state, move |mut state| async move {
+            let page = self.next_page(&mut state).await;
+            if page.is_none() {
+                log::info!(
+                    "Web crawling completed. Crawled {} pages",
+                    state.pages_crawled
+                );
+            }
+            page.map(|page| (page, state))
+        })
+    }
 
-        while let Some(url) = queue.pop_front() {
-            if pages_crawled >= self.max_pages {
+    async fn next_page(&self, state: &mut CrawlState) -> Option<CrawledPage> {
+        while let Some(url) = state.queue.pop_front() {
+            if state.pages_crawled >= self.max_pages {
                 break;
             }
 
             let url_string = url.to_string();
-            if visited.contains(&url_string) {
+            if state.visited.contains(&url_string) || !self.is_allowed_domain(&url) {
                 continue;
             }
+            state.visited.insert(url_string);
 
-            if !self.is_allowed_domain(&url) {
-                continue;
+            // Be polite - add delay between requests
+            if state.requests > 0 {
+                sleep(Duration::from_millis(self.delay_ms)).await;
             }
-
-            visited.insert(url_string.clone());
+            state.requests += 1;
 
             match self.fetch_and_parse(&url).await {
                 Ok((document, links)) => {
                     log::info!("Crawled: {}", document.title);
-                    documents.push(document);
-                    pages_crawled += 1;
+                    state.pages_crawled += 1;
 
                     // Add found links to queue
-                    for link in links {
-                        if !visited.contains(&link.to_string()) {
-                            queue.push_back(link);
+                    for (link, _) in &links {
+                        if !state.visited.contains(&link.to_string()) {
+                            state.queue.push_back(link.clone());
                         }
                     }
+
+                    let links = links
+                        .into_iter()
+                        .map(|(link, anchor_text)| Link {
+                            target: link.to_string(),
+                            anchor_text,
+                        })
+                        .collect();
+                    return Some(CrawledPage {
+                        document,
+                        links: Some(links),
+                    });
                 }
                 Err(e) => {
                     log::warn!("Failed to crawl {}: {}", url, e);
                 }
             }
-
-            // Be polite - add delay between requests
-            sleep(Duration::from_millis(self.delay_ms)).await;
         }
 
-        log::info!("Web crawling completed. Crawled {} pages", pages_crawled);
-        Ok(documents)
+        None
     }
 
-    async fn fetch_and_parse(&self, url: &Url) -> Result<(Document, Vec<Url>)> {
+    async fn fetch_and_parse(&self, url: &Url) -> Result<(Document, Vec<(Url, String)>)> {
         // Fetch the page
         let response = self
             .client
@@ -114,6 +186,14 @@ impl WebCrawler {
             return Err(anyhow::anyhow!("HTTP error: {}", response.status()));
         }
 
+        // Pages without a Last-Modified header are dated by when they were crawled
+        let timestamp = response
+            .headers()
+            .get(reqwest::header::LAST_MODIFIED)
+            .and_then(|value| value.to_str().ok())
+            .and_then(parse_timestamp)
+            .unwrap_or_else(|| Utc::now().timestamp());
+
         let html = response
             .text()
             .await
@@ -133,10 +213,14 @@ impl WebCrawler {
         // Extract text content (remove scripts, styles, etc.)
         let content = self.extract_text_content(&document);
 
+        let sections = self.extract_sections(&document, &content);
+
         // Extract links
         let links = self.extract_links(&document, url)?;
 
-        let doc = Document::new(title, content, url.to_string());
+        let doc = Document::new(title, content, url.to_string())
+            .with_timestamp(Some(timestamp))
+            .with_sections(sections);
 
         Ok((doc, links))
     }
@@ -182,13 +266,11 @@ impl WebCrawler {
         }
 
         // If no main content found, fall back to body
-        if !found_main_content {
-            if let Ok(body_selector) = Selector::parse("body") {
-                for body in cleaned_document.select(&body_selector) {
-                    let text = self.extract_element_text(&body, &unwanted_selectors);
-                    if !text.trim().is_empty() {
-                        content_parts.push(text);
-                    }
+        if !found_main_content && let Ok(body_selector) = Selector::parse("body") {
+            for body in cleaned_document.select(&body_selector) {
+                let text = self.extract_element_text(&body, &unwanted_selectors);
+                if !text.trim().is_empty() {
+                    content_parts.push(text);
                 }
             }
         }
@@ -207,16 +289,62 @@ impl WebCrawler {
         self.clean_extracted_text(&content)
     }
 
+    /// Locates the page's headings in the extracted `content`, in document order. Their
+    /// anchor is the `id` of the heading or of an element inside it; headings outside the
+    /// extracted content, e.g. in navigation, are skipped.
+    fn extract_sections(&self, document: &Html, content: &str) -> Vec<Section> {
+        let heading_selector = Selector::parse("h1, h2, h3, h4, h5, h6").unwrap();
+        let id_selector = Selector::parse("[id], a[name]").unwrap();
+        let words: Vec<&str> = content.split_whitespace().collect();
+        let mut sections = Vec::new();
+        let mut position = 0;
+
+        for element in document.select(&heading_selector) {
+            let heading = self.clean_extracted_text(&element.text().collect::<Vec<_>>().join(" "));
+            let heading_words: Vec<&str> = heading.split_whitespace().collect();
+            if heading_words.is_empty() {
+                continue;
+            }
+
+            let Some(offset) = words[position..]
+                .windows(heading_words.len())
+                .position(|window| window == heading_words.as_slice())
+            else {
+                continue;
+            };
+
+            let length = heading_words.len();
+            let anchor = element.value().id().map(str::to_string).or_else(|| {
+                element.select(&id_selector).next().and_then(|inner| {
+                    let value = inner.value();
+                    value
+                        .id()
+                        .or_else(|| value.attr("name"))
+                        .map(str::to_string)
+                })
+            });
+
+            sections.push(Section {
+                heading,
+                anchor,
+                start: position + offset,
+            });
+            position += offset + length;
+        }
+
+        sections
+    }
+
     fn element_contains_unwanted_tags(
         &self,
         element: &scraper::ElementRef,
         unwanted_selectors: &[&str],
     ) -> bool {
         for &unwanted in unwanted_selectors {
-            if let Ok(selector) = Selector::parse(unwanted) {
-                if element.select(&selector).next().is_some() {
-                    return true;
-                }
+            if let Ok(selector) = Selector::parse(unwanted)
+                && element.select(&selector).next().is_some()
+            {
+                return true;
             }
         }
         false
@@ -297,17 +425,25 @@ impl WebCrawler {
         result.split_whitespace().collect::<Vec<_>>().join(" ")
     }
 
-    fn extract_links(&self, document: &Html, base_url: &Url) -> Result<Vec<Url>> {
+    /// Returns every HTTP(S) link on the page together with its anchor text.
+    fn extract_links(&self, document: &Html, base_url: &Url) -> Result<Vec<(Url, String)>> {
         let link_selector = Selector::parse("a[href]").unwrap();
         let mut links = Vec::new();
 
         for element in document.select(&link_selector) {
             if let Some(href) = element.value().attr("href") {
                 // Resolve relative URLs
-                if let Ok(absolute_url) = base_url.join(href) {
+                if let Ok(mut absolute_url) = base_url.join(href) {
+                    // Links to sections of a page point at the page itself
+                    absolute_url.set_fragment(None);
                     // Only include HTTP/HTTPS URLs
                     if absolute_url.scheme() == "http" || absolute_url.scheme() == "https" {
-                        links.push(absolute_url);
+                        let mut anchor_text = self
+                            .clean_extracted_text(&element.text().collect::<Vec<_>>().join(" "));
+                        if anchor_text.is_empty() {
+                            anchor_text = element.value().attr("title").unwrap_or("").to_string();
+                        }
+                        links.push((absolute_url, anchor_text));
                     }
                 }
             }
@@ -15,7 +15,7 @@
 //!
 //! ## Example
 //!
-//! ```
+//! ```no_run
 //! use mini_search_engine::{SearchEngine, SledStorage};
 //! use std::sync::Arc;
 //!
@@ -42,7 +42,7 @@ pub mod search;
 pub mod storage;
 pub mod web;
 
-pub use core::{Document, InvertedIndex, TfIdfRanker, Tokenizer};
+pub use core::{Document, InvertedIndex, SearchOptions, TfIdfRanker, Tokenizer};
 pub use crawler::FileCrawler;
-pub use search::SearchEngine;
+pub use search::{IndexCatalog, SearchEngine};
 pub use storage::{JsonStorage, SledStorage, Storage};
@@ -1,11 +1,9 @@
 use clap::Parser;
 use mini_search_engine::{
     api::{CliApp, HttpServer},
-    search::SearchEngine,
-    storage::SledStorage,
+    search::IndexCatalog,
 };
 use std::sync::Arc;
-use tokio;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
@@ -19,13 +17,19 @@ async fn main() -> anyhow::Result<()> {
         let port = args.get(2).and_then(|s| s.parse().ok()).unwrap_or(3030);
 
         // FIX: Use the SAME storage as CLI (Sled instead of JSON)
-        let storage = Arc::new(SledStorage::new("data/index/search_index.db")?);
-        let engine = Arc::new(SearchEngine::new(storage));
+        let catalog = Arc::new(IndexCatalog::open("data/index/search_index.db")?);
+
+        // Serve a read-only index written by `compact` without loading it into memory
+        if args.iter().any(|arg| arg == "--mapped") {
+            catalog
+                .open_mapped(IndexCatalog::DEFAULT_INDEX, "data/index/search_index.mmap")
+                .await?;
+        }
 
         // FIX: Load existing index if available
         println!("📚 Loading existing search index...");
-        match engine.load_index().await {
-            Ok(()) => {
+        match catalog.get(IndexCatalog::DEFAULT_INDEX).await {
+            Ok(engine) => {
                 let (doc_count, term_count) = engine.get_stats().await;
                 println!(
                     "✅ Loaded index: {} documents, {} terms",
@@ -43,23 +47,21 @@ async fn main() -> anyhow::Result<()> {
             }
             Err(e) => {
                 println!("⚠️  Could not load existing index: {}", e);
-                println!("   Starting with empty index. Use CLI or API to add content.");
+                return Err(e);
             }
         }
+        println!("📂 Indexes: {}", catalog.names().join(", "));
 
-        HttpServer::run(engine, port).await;
+        HttpServer::run(catalog, port).await?;
     } else {
         // CLI mode - keep existing logic
         let cli = CliApp::parse();
 
         // Use Sled storage for CLI mode
-        let storage = Arc::new(SledStorage::new("data/index/search_index.db")?);
-        let engine = SearchEngine::new(storage);
-
-        // Load existing index if available
-        engine.load_index().await?;
+        let catalog = IndexCatalog::open("data/index/search_index.db")?
+            .with_ingest_options(cli.ingest_options());
 
-        cli.run(&engine).await?;
+        cli.run(&catalog).await?;
     }
 
     Ok(())
@@ -0,0 +1,252 @@
+use crate::core::{Document, Section};
+use anyhow::{Context, Result, anyhow};
+use futures::stream::{self, Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+use uuid::Uuid;
+
+/// One line of a newline-delimited JSON export. Imported records without an id get a new
+/// one; those with the id of an indexed document replace it. Optional fields are left out
+/// of the line when unset and default like they do for a new document.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DocumentRecord {
+    #[serde(default)]
+    pub id: Option<Uuid>,
+    pub title: String,
+    pub path: String,
+    pub content: String,
+    #[serde(default)]
+    pub metadata: HashMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub boost: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub embedding: Option<Vec<f32>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sections: Vec<Section>,
+}
+
+impl From<&Document> for DocumentRecord {
+    fn from(document: &Document) -> Self {
+        Self {
+            id: Some(document.id),
+            title: document.title.clone(),
+            path: document.path.clone(),
+            content: document.content.clone(),
+            metadata: document.metadata.clone(),
+            timestamp: document.timestamp,
+            boost: (document.boost != 1.0).then_some(document.boost),
+            embedding: document.embedding.clone(),
+            sections: document.sections.clone(),
+        }
+    }
+}
+
+impl From<DocumentRecord> for Document {
+    fn from(record: DocumentRecord) -> Self {
+        let mut document = Document::new(record.title, record.content, record.path)
+            .with_metadata(record.metadata)
+            .with_timestamp(record.timestamp)
+            .with_sections(record.sections);
+        if let Some(boost) = record.boost {
+            document = document.with_boost(boost);
+        }
+        if let Some(embedding) = record.embedding {
+            document = document.with_embedding(embedding);
+        }
+        if let Some(id) = record.id {
+            document.id = id;
+        }
+        document
+    }
+}
+
+/// Writes documents as NDJSON and returns how many were written.
+pub fn write_documents(
+    documents: impl IntoIterator<Item = impl Borrow<Document>>,
+    mut writer: impl Write,
+) -> Result<usize> {
+    let mut count = 0;
+    for document in documents {
+        writer.write_all(&to_line(document.borrow())?)?;
+        count += 1;
+    }
+    writer.flush().context("Failed to write documents")?;
+    Ok(count)
+}
+
+/// A document as one NDJSON line, newline included.
+pub fn to_line(document: &Document) -> Result<Vec<u8>> {
+    let mut line = serde_json::to_vec(&DocumentRecord::from(document))
+        .context("Failed to serialize document")?;
+    line.push(b'\n');
+    Ok(line)
+}
+
+/// Reads NDJSON documents one line at a time. Blank lines are skipped; a malformed line is
+/// an error naming its line number.
+pub fn read_documents(reader: impl BufRead) -> impl Iterator<Item = Result<Document>> {
+    reader
+        .split(b'\n')
+        .enumerate()
+        .filter_map(|(number, line)| match line {
+            Ok(line) => parse_line(&line, number + 1),
+            Err(e) => Some(Err(anyhow!(e).context("Failed to read documents"))),
+        })
+}
+
+/// Like `read_documents`, for bytes arriving in chunks, e.g. a request body. Documents are
+/// yielded as soon as their line is complete.
+pub fn document_stream<S, B>(chunks: S) -> impl Stream<Item = Result<Document>>
+where
+    S: Stream<Item = Result<B>>,
+    B: AsRef<[u8]>,
+{
+    struct Lines<S> {
+        chunks: std::pin::Pin<Box<S>>,
+        buffer: Vec<u8>,
+        number: usize,
+        done: bool,
+    }
+
+    let lines = Lines {
+        chunks: Box::pin(chunks),
+        buffer: Vec::new(),
+        number: 0,
+        done: false,
+    };
+    stream::unfold(lines, |mut lines| async move {
+        loop {
+            let end = lines.buffer.iter().position(|byte| *byte == b'\n');
+            if end.is_some() || (lines.done && !lines.buffer.is_empty()) {
+                let end = end.map_or(lines.buffer.len(), |end| end + 1);
+                let line: Vec<u8> = lines.buffer.drain(..end).collect();
+                lines.number += 1;
+                match parse_line(&line, lines.number) {
+                    Some(document) => return Some((document, lines)),
+                    None => continue,
+                }
+            }
+            if lines.done {
+                return None;
+            }
+
+            match lines.chunks.next().await {
+                Some(Ok(chunk)) => lines.buffer.extend_from_slice(chunk.as_ref()),
+                Some(Err(e)) => {
+                    lines.done = true;
+                    lines.buffer.clear();
+                    return Some((Err(e.context("Failed to read documents")), lines));
+                }
+                None => lines.done = true,
+            }
+        }
+    })
+}
+
+fn parse_line(line: &[u8], number: usize) -> Option<Result<Document>> {
+    if line.iter().all(u8::is_ascii_whitespace) {
+        return None;
+    }
+    Some(
+        serde_json::from_slice::<DocumentRecord>(line)
+            .map(Document::from)
+            .with_context(|| format!("Invalid document on line {}", number)),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+
+    fn documents() -> Vec<Document> {
+        let full = Document::new(
+            "Ownership".to_string(),
+            "Each value has an owner.\nLines \"quoted\" too.".to_string(),
+            "https://docs.example/ownership".to_string(),
+        )
+        .with_metadata(HashMap::from([(
+            "tags".to_string(),
+            "rust, memory".to_string(),
+        )]))
+        .with_timestamp(Some(1_700_000_000))
+        .with_boost(2.5)
+        .with_embedding(vec![0.25, -1.0, 3.5])
+        .with_sections(vec![Section {
+            heading: "Rules".to_string(),
+            anchor: Some("rules".to_string()),
+            start: 3,
+        }]);
+        let minimal = Document::new(
+            "Notes".to_string(),
+            "Plain text".to_string(),
+            "notes.txt".to_string(),
+        );
+        vec![full, minimal]
+    }
+
+    fn as_json(documents: &[Document]) -> Vec<serde_json::Value> {
+        documents
+            .iter()
+            .map(|document| serde_json::to_value(document).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn round_trips_documents() {
+        let documents = documents();
+        let mut ndjson = Vec::new();
+        assert_eq!(write_documents(&documents, &mut ndjson).unwrap(), 2);
+
+        let lines: Vec<&str> = std::str::from_utf8(&ndjson).unwrap().lines().collect();
+        assert_eq!(lines.len(), 2);
+        for field in ["timestamp", "boost", "embedding", "sections"] {
+            assert!(lines[0].contains(field), "{}", field);
+            assert!(!lines[1].contains(field), "{}", field);
+        }
+
+        let read = read_documents(ndjson.as_slice())
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(as_json(&read), as_json(&documents));
+    }
+
+    #[test]
+    fn streams_documents_split_across_chunks() {
+        let documents = documents();
+        let mut ndjson = Vec::new();
+        write_documents(&documents, &mut ndjson).unwrap();
+        // A blank line and a last line without a newline
+        ndjson.extend_from_slice(b"\n");
+        ndjson.extend_from_slice(&to_line(&documents[0]).unwrap());
+        ndjson.pop();
+
+        let chunks = ndjson
+            .chunks(7)
+            .map(|chunk| Ok(chunk.to_vec()))
+            .collect::<Vec<Result<Vec<u8>>>>();
+        let streamed = block_on(document_stream(stream::iter(chunks)).collect::<Vec<_>>())
+            .into_iter()
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        let mut expected = documents.clone();
+        expected.push(documents[0].clone());
+        assert_eq!(as_json(&streamed), as_json(&expected));
+    }
+
+    #[test]
+    fn reports_malformed_lines_and_assigns_missing_ids() {
+        let ndjson = b"{\"title\": \"A\", \"path\": \"a.md\", \"content\": \"text\"}\n\n{oops}\n";
+        let read: Vec<Result<Document>> = read_documents(&ndjson[..]).collect();
+        assert_eq!(read.len(), 2);
+        let document = read[0].as_ref().unwrap();
+        assert_eq!((document.title.as_str(), document.boost), ("A", 1.0));
+        assert!(!document.id.is_nil());
+        let error = read[1].as_ref().unwrap_err();
+        assert!(format!("{:#}", error).contains("line 3"), "{:#}", error);
+    }
+}
@@ -0,0 +1,785 @@
+use crate::core::{
+    IndexSettings, InvertedIndex, Owner, Retrieved, SearchOptions, SearchResults, TfIdfRanker,
+};
+use crate::search::{Generation, IngestOptions, SearchEngine, SnapshotInfo, SnapshotStore};
+use crate::storage::{SledStorage, Storage};
+use anyhow::{Context, Result, anyhow};
+use serde::Serialize;
+use sled::{Db, Tree};
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+/// Document and term counts of a named index.
+#[derive(Debug, Clone, Serialize)]
+pub struct IndexSummary {
+    pub name: String,
+    pub documents: usize,
+    pub terms: usize,
+    pub shards: usize,
+    /// Aliases pointing at the index
+    pub aliases: Vec<String>,
+}
+
+/// Named indexes sharing one sled database, each stored in its own tree with its own
+/// settings. Indexes are loaded the first time they are used.
+///
+/// An index may be split into shards when it is created. The first shard uses the index's
+/// tree, the others trees named `shard-<n>:<tree>`.
+///
+/// An alias is a name standing for one or more indexes. Repointing it is atomic, so an index
+/// can be rebuilt under a new name and swapped in while searches keep using the old one.
+///
+/// Snapshots of indexes are kept in the `snapshots` directory next to the database.
+pub struct IndexCatalog {
+    db: Db,
+    /// Loaded indexes
+    engines: RwLock<HashMap<String, Arc<SearchEngine>>>,
+    /// Alias name to index names, mirrored in `alias_tree`. Locked before `engines` when
+    /// both are needed.
+    aliases: RwLock<BTreeMap<String, Vec<String>>>,
+    alias_tree: Tree,
+    ingest_options: IngestOptions,
+    snapshots: SnapshotStore,
+}
+
+impl IndexCatalog {
+    /// Index used when no name is given. It lives in the tree used before indexes had
+    /// names and cannot be dropped.
+    pub const DEFAULT_INDEX: &'static str = "default";
+    const DEFAULT_TREE: &'static str = "inverted_index";
+    const TREE_PREFIX: &'static str = "index:";
+    const ALIAS_TREE: &'static str = "aliases";
+    const SHARD_PREFIX: &'static str = "shard-";
+    const MAX_SHARDS: usize = 64;
+    const SNAPSHOT_DIRECTORY: &'static str = "snapshots";
+
+    pub fn open(db_path: &str) -> Result<Self> {
+        if let Some(parent) = Path::new(db_path).parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create directory: {:?}", parent))?;
+        }
+
+        let db = sled::open(db_path)
+            .with_context(|| format!("Failed to open sled database at: {}", db_path))?;
+
+        let alias_tree = db
+            .open_tree(Self::ALIAS_TREE)
+            .context("Failed to open aliases tree")?;
+        let mut aliases = BTreeMap::new();
+        for entry in alias_tree.iter() {
+            let (alias, indexes) = entry.context("Failed to read aliases")?;
+            aliases.insert(
+                String::from_utf8_lossy(&alias).into_owned(),
+                serde_json::from_slice(&indexes).context("Invalid alias")?,
+            );
+        }
+
+        let snapshots = Path::new(db_path)
+            .parent()
+            .unwrap_or(Path::new("."))
+            .join(Self::SNAPSHOT_DIRECTORY);
+
+        Ok(Self {
+            db,
+            engines: RwLock::new(HashMap::new()),
+            aliases: RwLock::new(aliases),
+            alias_tree,
+            ingest_options: IngestOptions::default(),
+            snapshots: SnapshotStore::new(snapshots),
+        })
+    }
+
+    /// Batching and progress reporting of every index loaded or created afterwards.
+    pub fn with_ingest_options(mut self, ingest_options: IngestOptions) -> Self {
+        self.ingest_options = ingest_options;
+        self
+    }
+
+    /// Names of all indexes, sorted, the default index included.
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .db
+            .tree_names()
+            .iter()
+            .filter_map(|tree| {
+                let tree = std::str::from_utf8(tree).ok()?;
+                tree.strip_prefix(Self::TREE_PREFIX).map(str::to_string)
+            })
+            .collect();
+        names.push(Self::DEFAULT_INDEX.to_string());
+        names.sort();
+        names
+    }
+
+    pub fn exists(&self, name: &str) -> bool {
+        name == Self::DEFAULT_INDEX
+            || self
+                .db
+                .tree_names()
+                .iter()
+                .any(|tree| tree.as_ref() == Self::tree_name(name).as_bytes())
+    }
+
+    /// The index called `name`, or the only index of the alias `name`.
+    pub async fn get(&self, name: &str) -> Result<Arc<SearchEngine>> {
+        match &self.resolve(name).await?[..] {
+            [index] => self.load(index).await,
+            _ => Err(anyhow!(
+                "Alias '{}' points at several indexes, name one of them",
+                name
+            )),
+        }
+    }
+
+    /// The indexes an alias points at, or just `name` for an index.
+    pub async fn resolve(&self, name: &str) -> Result<Vec<String>> {
+        if let Some(indexes) = self.aliases.read().await.get(name) {
+            return Ok(indexes.clone());
+        }
+        if self.engines.read().await.contains_key(name) || self.exists(name) {
+            Ok(vec![name.to_string()])
+        } else {
+            Err(anyhow!("No index or alias named '{}'", name))
+        }
+    }
+
+    /// Loads an index from storage on first use.
+    async fn load(&self, name: &str) -> Result<Arc<SearchEngine>> {
+        if let Some(engine) = self.engines.read().await.get(name) {
+            return Ok(Arc::clone(engine));
+        }
+        if !self.exists(name) {
+            return Err(anyhow!("No index named '{}'", name));
+        }
+
+        let mut engines = self.engines.write().await;
+        if let Some(engine) = engines.get(name) {
+            return Ok(Arc::clone(engine));
+        }
+        let shards = self.shard_trees(name).len();
+        let engine = self.engine(name, IndexSettings::default(), shards)?;
+        engine
+            .load_index()
+            .await
+            .with_context(|| format!("Failed to load index '{}'", name))?;
+
+        let engine = Arc::new(engine);
+        engines.insert(name.to_string(), Arc::clone(&engine));
+        Ok(engine)
+    }
+
+    /// Creates an empty index analyzed and scored with `settings` and split into `shards`
+    /// shards. The settings are saved right away, so they apply even before the first
+    /// document is added. The shard count cannot be changed afterwards.
+    pub async fn create(
+        &self,
+        name: &str,
+        settings: IndexSettings,
+        shards: usize,
+    ) -> Result<Arc<SearchEngine>> {
+        Self::validate_name(name)?;
+        if !(1..=Self::MAX_SHARDS).contains(&shards) {
+            return Err(anyhow!(
+                "Invalid shard count {}, use 1 to {}",
+                shards,
+                Self::MAX_SHARDS
+            ));
+        }
+        let aliases = self.aliases.read().await;
+        let mut engines = self.engines.write().await;
+        if self.exists(name) {
+            return Err(anyhow!("Index '{}' already exists", name));
+        }
+        if aliases.contains_key(name) {
+            return Err(anyhow!("'{}' is already an alias", name));
+        }
+
+        let engine = self.engine(name, settings, shards)?;
+        engine.save_index().await?;
+
+        let engine = Arc::new(engine);
+        engines.insert(name.to_string(), Arc::clone(&engine));
+        log::info!("Created index '{}' with {} shards", name, shards);
+        Ok(engine)
+    }
+
+    /// Deletes an index and everything stored in it. Indexes still used by an alias cannot
+    /// be dropped.
+    pub async fn drop_index(&self, name: &str) -> Result<()> {
+        if name == Self::DEFAULT_INDEX {
+            return Err(anyhow!(
+                "The default index cannot be dropped, clear it instead"
+            ));
+        }
+        let aliases = self.aliases.read().await;
+        if let Some(alias) = aliases
+            .iter()
+            .find_map(|(alias, indexes)| indexes.iter().any(|i| i == name).then_some(alias))
+        {
+            return Err(anyhow!(
+                "Index '{}' is used by alias '{}', repoint the alias first",
+                name,
+                alias
+            ));
+        }
+        let mut engines = self.engines.write().await;
+        if !self.exists(name) {
+            return Err(anyhow!("No index named '{}'", name));
+        }
+
+        engines.remove(name);
+        for tree in self.shard_trees(name) {
+            SledStorage::drop_trees(&self.db, &tree)
+                .with_context(|| format!("Failed to drop index '{}'", name))?;
+        }
+        log::info!("Dropped index '{}'", name);
+        Ok(())
+    }
+
+    /// Upgrades every shard of an index stored in an older format version and returns the
+    /// oldest version found, `None` when the index holds nothing yet. Indexes are upgraded
+    /// when they are loaded as well.
+    pub async fn migrate(&self, name: &str) -> Result<Option<u32>> {
+        let _engines = self.engines.write().await;
+        if !self.exists(name) {
+            return Err(anyhow!("No index named '{}'", name));
+        }
+
+        let mut versions = Vec::new();
+        for tree in self.shard_trees(name) {
+            let version = SledStorage::with_tree(self.db.clone(), &tree)?
+                .migrate()
+                .with_context(|| format!("Failed to migrate index '{}'", name))?;
+            versions.extend(version);
+        }
+        Ok(versions.into_iter().min())
+    }
+
+    pub fn snapshots(&self) -> &SnapshotStore {
+        &self.snapshots
+    }
+
+    /// Takes a snapshot of the index or single-index alias `name` as it was last committed,
+    /// while it keeps serving. The snapshot is named `<index>-<time>` unless `snapshot` is
+    /// given.
+    pub async fn create_snapshot(
+        &self,
+        name: &str,
+        snapshot: Option<&str>,
+    ) -> Result<SnapshotInfo> {
+        let index = match &self.resolve(name).await?[..] {
+            [index] => index.clone(),
+            _ => {
+                return Err(anyhow!(
+                    "Alias '{}' points at several indexes, name one of them",
+                    name
+                ));
+            }
+        };
+        let snapshot = match snapshot {
+            Some(snapshot) => snapshot.to_string(),
+            None => format!("{}-{}", index, chrono::Utc::now().format("%Y%m%d-%H%M%S")),
+        };
+
+        self.load(&index)
+            .await?
+            .read_committed(|shards| self.snapshots.create(&snapshot, &index, shards))
+            .await
+    }
+
+    /// Restores a snapshot into the index `name`, by default the one it was taken of. A
+    /// missing index is created with the snapshot's shard count; an existing one must have
+    /// the same count and keeps serving searches until the snapshot replaces it.
+    pub async fn restore_snapshot(
+        &self,
+        snapshot: &str,
+        name: Option<&str>,
+    ) -> Result<SnapshotInfo> {
+        let (info, shards) = self.snapshots.read(snapshot)?;
+        let name = name.unwrap_or(&info.index);
+        let engine = if self.exists(name) {
+            self.load(name).await?
+        } else {
+            self.create(name, shards[0].settings.clone(), shards.len())
+                .await?
+        };
+        engine.restore(shards).await.with_context(|| {
+            format!("Failed to restore snapshot '{}' into '{}'", snapshot, name)
+        })?;
+        log::info!("Restored snapshot '{}' into index '{}'", snapshot, name);
+        Ok(info)
+    }
+
+    /// Serves the memory-mapped index file at `path` as `name` until the catalog is dropped.
+    /// Nothing is stored, and the index is read-only.
+    pub async fn open_mapped(&self, name: &str, path: impl AsRef<Path>) -> Result<()> {
+        let storage = SledStorage::with_tree(self.db.clone(), &Self::tree_name(name))?;
+        let engine = SearchEngine::open_mapped(Arc::new(storage), path)?;
+        self.engines
+            .write()
+            .await
+            .insert(name.to_string(), Arc::new(engine));
+        Ok(())
+    }
+
+    pub async fn list(&self) -> Result<Vec<IndexSummary>> {
+        let aliases = self.aliases().await;
+        let mut summaries = Vec::new();
+        for name in self.names() {
+            let engine = self.load(&name).await?;
+            let (documents, terms) = engine.get_stats().await;
+            summaries.push(IndexSummary {
+                shards: engine.shard_count(),
+                aliases: aliases
+                    .iter()
+                    .filter(|(_, indexes)| indexes.contains(&name))
+                    .map(|(alias, _)| alias.clone())
+                    .collect(),
+                name,
+                documents,
+                terms,
+            });
+        }
+        Ok(summaries)
+    }
+
+    /// Every alias with the indexes it points at.
+    pub async fn aliases(&self) -> BTreeMap<String, Vec<String>> {
+        self.aliases.read().await.clone()
+    }
+
+    /// Points `alias` at `indexes`, creating it or atomically replacing what it pointed at.
+    /// Searches started before keep using the previous indexes, later ones see the new ones.
+    pub async fn set_alias(&self, alias: &str, indexes: Vec<String>) -> Result<()> {
+        Self::validate_name(alias)?;
+        if indexes.is_empty() {
+            return Err(anyhow!("Alias '{}' needs at least one index", alias));
+        }
+        let mut aliases = self.aliases.write().await;
+        if self.exists(alias) {
+            return Err(anyhow!(
+                "'{}' is an index, aliases need their own name",
+                alias
+            ));
+        }
+        if let Some(missing) = indexes.iter().find(|index| !self.exists(index)) {
+            return Err(anyhow!("No index named '{}'", missing));
+        }
+
+        let value = serde_json::to_vec(&indexes).context("Failed to serialize alias")?;
+        self.alias_tree
+            .insert(alias, value)
+            .context("Failed to store alias")?;
+        self.alias_tree.flush().context("Failed to flush aliases")?;
+        log::info!("Alias '{}' points at {}", alias, indexes.join(", "));
+        aliases.insert(alias.to_string(), indexes);
+        Ok(())
+    }
+
+    pub async fn remove_alias(&self, alias: &str) -> Result<()> {
+        let mut aliases = self.aliases.write().await;
+        if aliases.remove(alias).is_none() {
+            return Err(anyhow!("No alias named '{}'", alias));
+        }
+        self.alias_tree
+            .remove(alias)
+            .context("Failed to remove alias")?;
+        self.alias_tree.flush().context("Failed to flush aliases")?;
+        log::info!("Removed alias '{}'", alias);
+        Ok(())
+    }
+
+    /// Searches one or more indexes or aliases. Hits of several indexes are merged by score
+    /// and labeled with the index they came from; facet counts are summed.
+    /// Fails for options one of the indexes `names` cannot apply, see
+    /// `SearchEngine::check_options`, and for mapped indexes searched together with others.
+    /// Indexes that are not loaded yet are regular ones.
+    pub async fn check_options(&self, names: &[String], options: &SearchOptions) -> Result<()> {
+        let mut indexes = Vec::new();
+        for name in names {
+            indexes.extend(self.resolve(name).await?);
+        }
+        indexes.sort();
+        indexes.dedup();
+        for index in &indexes {
+            if let Some(engine) = self.engines.read().await.get(index) {
+                if engine.is_mapped() && indexes.len() > 1 {
+                    return Err(anyhow!(
+                        "Mapped index '{}' can only be searched on its own",
+                        index
+                    ));
+                }
+                engine
+                    .check_options(options)
+                    .with_context(|| format!("Cannot search index '{}'", index))?;
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn search(
+        &self,
+        names: &[String],
+        query: &str,
+        options: &SearchOptions,
+    ) -> Result<SearchResults> {
+        let mut indexes = Vec::new();
+        for name in names {
+            indexes.extend(self.resolve(name).await?);
+        }
+        let mut names: Vec<&String> = indexes.iter().collect();
+        names.sort();
+        names.dedup();
+        match names[..] {
+            [] => Err(anyhow!("No index to search")),
+            [name] => {
+                self.load(name)
+                    .await?
+                    .search_with_options(query, options)
+                    .await
+            }
+            _ => {
+                let mut snapshots = Vec::with_capacity(names.len());
+                for name in names {
+                    let engine = self.load(name).await?;
+                    if engine.is_mapped() {
+                        return Err(anyhow!(
+                            "Mapped index '{}' can only be searched on its own",
+                            name
+                        ));
+                    }
+                    snapshots.push((name, engine.snapshot().await));
+                }
+                Ok(Self::rank(&snapshots, query, options))
+            }
+        }
+    }
+
+    /// Ranks the hits of several indexes together. Each index retrieves with its own
+    /// statistics and its lexical scores are normalised to its best hit, so indexes of
+    /// different sizes and scoring models rank on one scale. Fusion, sorting, collapsing
+    /// and diversification then run once over all hits. Hits keep the index they came from,
+    /// so documents with the same id in several indexes stay apart.
+    fn rank(
+        snapshots: &[(&String, Generation)],
+        query: &str,
+        options: &SearchOptions,
+    ) -> SearchResults {
+        let mut shards: Vec<&InvertedIndex> = Vec::new();
+        let mut owners = Vec::new();
+        let mut retrieved = Vec::with_capacity(snapshots.len());
+        for (name, snapshot) in snapshots {
+            let index: Vec<&InvertedIndex> = snapshot.iter().map(Arc::as_ref).collect();
+            let index_options = TfIdfRanker::collection_options(&index, query, options);
+            let mut part =
+                TfIdfRanker::retrieve_shards(&index, query, &index_options).offset(shards.len());
+            let owner = Owner {
+                name: name.to_string(),
+                collection_stats: index_options.collection_stats.clone(),
+                scale: part.normalize(),
+            };
+            retrieved.push(part);
+            owners.extend(std::iter::repeat_n(owner, index.len()));
+            shards.extend(index);
+        }
+
+        let retrieved = Retrieved::merge(retrieved, options.num_candidates);
+        let (hits, hybrid) = TfIdfRanker::fuse(retrieved, options);
+        TfIdfRanker::rank_hits(&shards, &owners, query, hits, hybrid.as_ref(), options)
+    }
+
+    fn engine(&self, name: &str, settings: IndexSettings, shards: usize) -> Result<SearchEngine> {
+        let tree = Self::tree_name(name);
+        let storages = (0..shards)
+            .map(|shard| {
+                let storage = match shard {
+                    0 => SledStorage::with_tree(self.db.clone(), &tree)?,
+                    _ => SledStorage::with_tree(self.db.clone(), &Self::shard_tree(&tree, shard))?,
+                };
+                Ok(Arc::new(storage) as Arc<dyn Storage>)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(SearchEngine::sharded(storages, settings)
+            .with_ingest_options(self.ingest_options.clone()))
+    }
+
+    /// Trees of every shard of an existing index, in shard order.
+    fn shard_trees(&self, name: &str) -> Vec<String> {
+        let existing = self.db.tree_names();
+        let tree = Self::tree_name(name);
+        let mut trees = vec![tree.clone()];
+        loop {
+            let next = Self::shard_tree(&tree, trees.len());
+            if !existing.iter().any(|t| t.as_ref() == next.as_bytes()) {
+                return trees;
+            }
+            trees.push(next);
+        }
+    }
+
+    fn shard_tree(tree: &str, shard: usize) -> String {
+        format!("{}{}:{}", Self::SHARD_PREFIX, shard, tree)
+    }
+
+    fn tree_name(name: &str) -> String {
+        if name == Self::DEFAULT_INDEX {
+            Self::DEFAULT_TREE.to_string()
+        } else {
+            format!("{}{}", Self::TREE_PREFIX, name)
+        }
+    }
+
+    /// Names are used in URLs and file names: lowercase letters, digits, `-` and `_`.
+    fn validate_name(name: &str) -> Result<()> {
+        let valid = !name.is_empty()
+            && name.len() <= 64
+            && name
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_');
+        if valid {
+            Ok(())
+        } else {
+            Err(anyhow!(
+                "Invalid index name '{}', use up to 64 lowercase letters, digits, '-' and '_'",
+                name
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Document;
+    use crate::JsonStorage;
+    use crate::core::ranking::SearchResult;
+    use crate::core::{Collapse, ScoringModel};
+    use uuid::Uuid;
+
+    async fn index(catalog: &IndexCatalog, name: &str, scoring: ScoringModel, shards: usize) {
+        let settings = IndexSettings {
+            scoring,
+            ..IndexSettings::default()
+        };
+        let engine = catalog.create(name, settings, shards).await.unwrap();
+        for i in 0..12 {
+            let document = Document::new(
+                format!("{} {}", name, i),
+                format!("{} page {} about rust {}", name, i, "rust ".repeat(i % 4)),
+                format!("https://{}{}.example/page{}", name, i % 2, i),
+            );
+            engine.add_document(document).await.unwrap();
+        }
+        engine.commit().await;
+    }
+
+    #[tokio::test]
+    async fn searches_indexes_of_different_scoring_models_together() {
+        let dir = tempfile::tempdir().unwrap();
+        let catalog = IndexCatalog::open(&dir.path().to_string_lossy()).unwrap();
+        index(&catalog, "docs", ScoringModel::TfIdf, 1).await;
+        index(&catalog, "blog", ScoringModel::Bm25 { k1: 1.2, b: 0.75 }, 3).await;
+        let names = vec!["docs".to_string(), "blog".to_string()];
+
+        let options = SearchOptions::new()
+            .with_limit(20)
+            .with_collapse(Collapse::parse("host:2").unwrap());
+        let results = catalog.search(&names, "rust", &options).await.unwrap();
+
+        // Two hits of each of the four hosts, the best of each index scoring the same
+        assert_eq!(results.total_hits, 24);
+        assert_eq!(results.results.len(), 8);
+        let more_in_groups: usize = results
+            .results
+            .iter()
+            .map(|result| result.more_in_group)
+            .sum();
+        assert_eq!(more_in_groups, 16);
+        let best = |name: &str| {
+            results
+                .results
+                .iter()
+                .filter(|result| result.index.as_deref() == Some(name))
+                .map(|result| result.score)
+                .fold(0.0, f64::max)
+        };
+        assert!((best("docs") - best("blog")).abs() < 1e-9);
+    }
+
+    #[tokio::test]
+    async fn keeps_documents_with_the_same_id_in_several_indexes_apart() {
+        let dir = tempfile::tempdir().unwrap();
+        let catalog = IndexCatalog::open(&dir.path().to_string_lossy()).unwrap();
+        for (name, shards) in [("docs", 1), ("blog", 2)] {
+            let engine = catalog
+                .create(name, IndexSettings::default(), shards)
+                .await
+                .unwrap();
+            for i in 0..3 {
+                let mut document = Document::new(
+                    format!("{} {}", name, i),
+                    format!("{} about rust {}", name, "rust ".repeat(i)),
+                    format!("https://{}.example/{}", name, i),
+                );
+                document.id = Uuid::from_u128(i as u128);
+                engine.add_document(document).await.unwrap();
+            }
+            engine.commit().await;
+        }
+        let names = vec!["docs".to_string(), "blog".to_string()];
+
+        let options = SearchOptions::new().with_explain(true);
+        let results = catalog.search(&names, "rust", &options).await.unwrap();
+        assert_eq!(results.total_hits, 6);
+        for result in &results.results {
+            let index = result.index.as_deref().unwrap();
+            assert!(result.document.content.starts_with(index), "{:?}", result);
+            let explanation = result.explanation.as_ref().unwrap();
+            assert!((explanation.value - result.score).abs() < 1e-9);
+        }
+    }
+
+    #[tokio::test]
+    async fn repoints_aliases_to_rebuilt_indexes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_string_lossy().to_string();
+        let catalog = IndexCatalog::open(&path).unwrap();
+        let build = |name: &'static str, content: &'static str| {
+            let catalog = &catalog;
+            async move {
+                let engine = catalog
+                    .create(name, IndexSettings::default(), 1)
+                    .await
+                    .unwrap();
+                let document = Document::new(
+                    "Guide".to_string(),
+                    content.to_string(),
+                    format!("https://{}.example/guide", name),
+                );
+                engine.add_document(document).await.unwrap();
+                engine.commit().await;
+                engine.save_index().await.unwrap();
+            }
+        };
+
+        build("docs_v1", "old crawl of the guide").await;
+        catalog
+            .set_alias("docs", vec!["docs_v1".to_string()])
+            .await
+            .unwrap();
+        let live = catalog.get("docs").await.unwrap();
+        assert_eq!(live.search("old", None).await.unwrap().len(), 1);
+
+        // Rebuilding next to the live index leaves it untouched until the alias moves
+        build("docs_v2", "fresh crawl of the guide").await;
+        assert!(live.search("fresh", None).await.unwrap().is_empty());
+        catalog
+            .set_alias("docs", vec!["docs_v2".to_string()])
+            .await
+            .unwrap();
+        let names = vec!["docs".to_string()];
+        let results = catalog
+            .search(&names, "fresh", &SearchOptions::new())
+            .await
+            .unwrap();
+        assert_eq!(results.results.len(), 1);
+        assert!(
+            catalog
+                .search(&names, "old", &SearchOptions::new())
+                .await
+                .unwrap()
+                .results
+                .is_empty()
+        );
+        assert_eq!(live.search("old", None).await.unwrap().len(), 1);
+
+        assert!(catalog.drop_index("docs_v2").await.is_err());
+        catalog.drop_index("docs_v1").await.unwrap();
+        drop(live);
+        drop(catalog);
+
+        let catalog = IndexCatalog::open(&path).unwrap();
+        assert_eq!(
+            catalog.aliases().await.get("docs"),
+            Some(&vec!["docs_v2".to_string()])
+        );
+        let engine = catalog.get("docs").await.unwrap();
+        assert_eq!(engine.search("fresh", None).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn restores_snapshots_into_indexes_and_other_storages() {
+        let dir = tempfile::tempdir().unwrap();
+        let catalog = IndexCatalog::open(&dir.path().to_string_lossy()).unwrap();
+        let scoring = ScoringModel::Bm25 { k1: 1.5, b: 0.5 };
+        index(&catalog, "docs", scoring.clone(), 2).await;
+        let engine = catalog.get("docs").await.unwrap();
+        let ranking = |results: Vec<SearchResult>| -> Vec<(Uuid, f64)> {
+            results
+                .iter()
+                .map(|result| (result.document.id, result.score))
+                .collect()
+        };
+        let before = ranking(engine.search("rust", Some(20)).await.unwrap());
+
+        let info = catalog
+            .create_snapshot("docs", Some("before"))
+            .await
+            .unwrap();
+        assert_eq!((info.shards, info.documents), (2, 12));
+        engine
+            .add_document(Document::new(
+                "Later".to_string(),
+                "rust added after the snapshot".to_string(),
+                "later.md".to_string(),
+            ))
+            .await
+            .unwrap();
+        engine.commit().await;
+        assert_ne!(
+            ranking(engine.search("rust", Some(20)).await.unwrap()),
+            before
+        );
+
+        catalog.restore_snapshot("before", None).await.unwrap();
+        assert_eq!(
+            ranking(engine.search("rust", Some(20)).await.unwrap()),
+            before
+        );
+
+        // A new index takes the snapshot's settings and shard count
+        catalog
+            .restore_snapshot("before", Some("copy"))
+            .await
+            .unwrap();
+        let copy = catalog.get("copy").await.unwrap();
+        assert_eq!(copy.shard_count(), 2);
+        assert_eq!(copy.settings().await.scoring, scoring);
+        assert_eq!(
+            ranking(copy.search("rust", Some(20)).await.unwrap()),
+            before
+        );
+
+        let storages: Vec<Arc<dyn Storage>> = (0..2)
+            .map(|shard| {
+                let path = dir.path().join(format!("shard{}.json", shard));
+                Arc::new(JsonStorage::new(path.to_string_lossy().to_string())) as Arc<dyn Storage>
+            })
+            .collect();
+        catalog
+            .snapshots()
+            .restore_into("before", &storages)
+            .unwrap();
+        let documents: usize = storages
+            .iter()
+            .map(|storage| storage.load_index().unwrap().unwrap().total_documents)
+            .sum();
+        assert_eq!(documents, 12);
+
+        assert_eq!(catalog.snapshots().list().unwrap().len(), 1);
+        catalog.snapshots().delete("before").unwrap();
+        assert!(catalog.snapshots().list().unwrap().is_empty());
+    }
+}
@@ -1,94 +1,722 @@
 use crate::Document;
-use crate::core::{InvertedIndex, TfIdfRanker};
-use crate::crawler::{FileCrawler, WebCrawler};
-use crate::storage::Storage;
-use anyhow::{Context, Result};
-use std::sync::Arc;
-use tokio::sync::RwLock;
+use crate::core::ranking::SearchResult;
+use crate::core::{
+    CollectionStats, IndexSettings, InvertedIndex, LinkGraph, MappedIndex, QueryTerm,
+    SearchOptions, SearchResults, TfIdfRanker,
+};
+use crate::crawler::{CrawledPage, FileCrawler, WebCrawler};
+use crate::search::{IngestOptions, IngestProgress};
+use crate::storage::{IndexSources, Storage};
+use anyhow::{Context, Result, anyhow};
+use futures::stream::{self, Stream, StreamExt};
+use std::collections::{HashMap, HashSet};
+use std::ops::Deref;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::Instant;
+use tokio::sync::{Mutex, MutexGuard, OwnedRwLockReadGuard, RwLock};
+use uuid::Uuid;
 
+/// Searches read the last committed generation of the index and never wait for indexing.
+/// Changes go to the next generation, a copy of the committed one, and become visible (and
+/// saveable) together when it is published with `commit`.
+///
+/// An index may be split into shards, each a full `InvertedIndex` with its own storage.
+/// Documents are assigned to a shard by id, and a generation spans all shards.
 pub struct SearchEngine {
-    index: Arc<RwLock<InvertedIndex>>,
-    storage: Arc<dyn Storage>,
+    /// One index per shard. The lock is only held to take or swap the `Arc`.
+    committed: RwLock<Arc<Vec<Arc<InvertedIndex>>>>,
+    /// Next generation of each shard, `None` while it has no pending changes. Serializes
+    /// writers.
+    staging: Mutex<Vec<Option<InvertedIndex>>>,
+    generation: AtomicU64,
+    /// One storage per shard
+    storages: Vec<Arc<dyn Storage>>,
+    /// Change log revision of each shard its storage holds, `None` when the storage has to
+    /// be rewritten
+    saved: StdMutex<Vec<Option<u64>>>,
+    /// Serializes saves, loads and clears, which hold it for writing, and keeps them waiting
+    /// while a `Generation` holds it for reading
+    saving: Arc<RwLock<()>>,
+    ingest_options: IngestOptions,
+    /// Progress of the running ingestion, the latest started one when several run
+    progress: StdMutex<Option<IngestProgress>>,
+    /// Memory-mapped index searched instead of `committed`, which makes the engine read-only
+    mapped: Option<Arc<MappedIndex>>,
 }
 
 impl SearchEngine {
+    /// Number of terms taken from a document to find similar ones.
+    const SIMILAR_QUERY_TERMS: usize = 25;
+
     pub fn new(storage: Arc<dyn Storage>) -> Self {
+        Self::with_settings(storage, IndexSettings::default())
+    }
+
+    pub fn with_settings(storage: Arc<dyn Storage>, settings: IndexSettings) -> Self {
+        Self::sharded(vec![storage], settings)
+    }
+
+    /// An engine splitting its documents over one shard per storage. Searches run on all
+    /// shards in parallel and score with the statistics of the whole collection, so results
+    /// rank the same as in a single index.
+    pub fn sharded(storages: Vec<Arc<dyn Storage>>, settings: IndexSettings) -> Self {
+        let indexes = storages
+            .iter()
+            .map(|_| InvertedIndex::with_settings(settings.clone()))
+            .collect();
+        Self::with_shards(storages, indexes)
+    }
+
+    fn with_shards(storages: Vec<Arc<dyn Storage>>, indexes: Vec<InvertedIndex>) -> Self {
+        assert!(
+            !storages.is_empty() && storages.len() == indexes.len(),
+            "every shard needs an index and a storage"
+        );
         Self {
-            index: Arc::new(RwLock::new(InvertedIndex::new())),
-            storage,
+            staging: Mutex::new(indexes.iter().map(|_| None).collect()),
+            committed: RwLock::new(Arc::new(indexes.into_iter().map(Arc::new).collect())),
+            generation: AtomicU64::new(0),
+            saved: StdMutex::new(storages.iter().map(|_| None).collect()),
+            saving: Arc::new(RwLock::new(())),
+            storages,
+            ingest_options: IngestOptions::default(),
+            progress: StdMutex::new(None),
+            mapped: None,
+        }
+    }
+
+    /// A read-only engine serving searches from a memory-mapped index file written by
+    /// `write_mapped`. Opening it does not load documents or postings into memory.
+    pub fn open_mapped(storage: Arc<dyn Storage>, path: impl AsRef<Path>) -> Result<Self> {
+        let mapped = MappedIndex::open(path)?;
+        log::info!(
+            "Mapped index with {} documents, {} terms",
+            mapped.total_documents(),
+            mapped.term_count()
+        );
+
+        let mut engine = Self::with_settings(storage, mapped.settings().clone());
+        engine.mapped = Some(Arc::new(mapped));
+        Ok(engine)
+    }
+
+    /// Writes the last committed generation as a memory-mapped index file for `open_mapped`.
+    /// Sharded indexes cannot be written.
+    pub async fn write_mapped(&self, path: impl AsRef<Path>) -> Result<()> {
+        let shards = self.snapshot().await;
+        let [index] = &shards[..] else {
+            return Err(anyhow!(
+                "Sharded indexes cannot be compacted into a mapped index"
+            ));
+        };
+        MappedIndex::write(index, path).context("Failed to write mapped index")
+    }
+
+    /// Fails for engines serving a mapped index, which cannot be changed in place.
+    fn ensure_writable(&self) -> Result<()> {
+        match self.mapped {
+            Some(_) => Err(anyhow!(
+                "The mapped index is read-only; index into the regular index and compact it again"
+            )),
+            None => Ok(()),
         }
     }
 
+    /// Batching and progress reporting of `index_directory`, `index_web_pages` and
+    /// `index_website`.
+    pub fn with_ingest_options(mut self, ingest_options: IngestOptions) -> Self {
+        self.ingest_options = ingest_options;
+        self
+    }
+
+    /// The last committed generation of the index, one `InvertedIndex` per shard. Saves wait
+    /// until it is dropped, so it must not be held while saving.
+    pub async fn snapshot(&self) -> Generation {
+        let saving = Arc::clone(&self.saving).read_owned().await;
+        Generation {
+            shards: self.current().await,
+            _saving: saving,
+        }
+    }
+
+    /// The last committed generation, for writers that coordinate with saves themselves.
+    async fn current(&self) -> Arc<Vec<Arc<InvertedIndex>>> {
+        Arc::clone(&*self.committed.read().await)
+    }
+
+    /// Whether the engine serves a read-only memory-mapped index.
+    pub fn is_mapped(&self) -> bool {
+        self.mapped.is_some()
+    }
+
+    pub fn shard_count(&self) -> usize {
+        self.storages.len()
+    }
+
+    /// Shard a document belongs to. Ids are random, so their value spreads documents evenly.
+    fn shard_of(&self, doc_id: &Uuid) -> usize {
+        (doc_id.as_u128() % self.storages.len() as u128) as usize
+    }
+
+    /// Number of generations committed since the engine was created.
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::SeqCst)
+    }
+
+    /// Publishes all pending changes at once and returns the new generation number, or the
+    /// current one when nothing changed.
+    pub async fn commit(&self) -> u64 {
+        let mut staging = self.staging.lock().await;
+        if staging.iter().all(Option::is_none) {
+            return self.generation();
+        }
+
+        let current = self.current().await;
+        let next: Vec<Arc<InvertedIndex>> = staging
+            .iter_mut()
+            .zip(current.iter())
+            .map(|(next, current)| match next.take() {
+                Some(next) => Arc::new(next),
+                None => Arc::clone(current),
+            })
+            .collect();
+        let documents: usize = next.iter().map(|index| index.total_documents).sum();
+        *self.committed.write().await = Arc::new(next);
+        let generation = self.generation.fetch_add(1, Ordering::SeqCst) + 1;
+        log::info!(
+            "Committed generation {} with {} documents",
+            generation,
+            documents
+        );
+        generation
+    }
+
+    /// Locks the next generation for changes.
+    async fn stage(&self) -> Staging<'_> {
+        let shards = self.staging.lock().await;
+        // Commits hold the staging lock too, so the committed generation stays as it is
+        let committed = self.current().await;
+        let saved = self.saved.lock().expect("saved lock poisoned").clone();
+        let sources = self
+            .storages
+            .iter()
+            .map(|storage| storage.sources())
+            .collect();
+        Staging {
+            shards,
+            committed,
+            saved,
+            sources,
+        }
+    }
+
+    /// Builds an in-memory copy of this engine whose documents are re-analyzed and scored with
+    /// `settings`, e.g. to compare configurations before switching. The copy shares this
+    /// engine's storage, so it should not be saved. Fails if documents cannot be loaded.
+    pub async fn reindexed(&self, settings: IndexSettings) -> Result<SearchEngine> {
+        let current = self.snapshot().await;
+        let indexes = current
+            .iter()
+            .map(|shard| {
+                let documents = shard
+                    .documents
+                    .try_values()
+                    .map(|document| document.map(Arc::unwrap_or_clone))
+                    .collect::<Result<Vec<_>>>()?;
+                let mut index = InvertedIndex::with_settings(settings.clone());
+                index.add_documents(documents)?;
+                index.update_link_graph(shard.link_graph.clone());
+                Ok(index)
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(SearchEngine::with_shards(self.storages.clone(), indexes))
+    }
+
+    pub async fn settings(&self) -> IndexSettings {
+        self.snapshot().await[0].settings.clone()
+    }
+
     pub async fn load_index(&self) -> Result<()> {
-        let loaded_index = self
-            .storage
-            .load_index()
-            .context("Failed to load index from storage")?;
-
-        if let Some(index) = loaded_index {
-            let mut current_index = self.index.write().await;
-            *current_index = index;
+        self.ensure_writable()?;
+        let _saving = self.saving.write().await;
+        let mut loaded = Vec::new();
+        for (shard, storage) in self.storages.iter().enumerate() {
+            if let Some(index) = storage
+                .load_index()
+                .context("Failed to load index from storage")?
+            {
+                loaded.push((shard, index));
+            }
+        }
+
+        if !loaded.is_empty() {
             log::info!(
                 "Loaded existing index with {} documents",
-                current_index.total_documents
+                loaded
+                    .iter()
+                    .map(|(_, index)| index.total_documents)
+                    .sum::<usize>()
             );
+            let mut staging = self.stage().await;
+            for (shard, index) in loaded {
+                self.saved.lock().expect("saved lock poisoned")[shard] =
+                    Some(index.changes.revision());
+                staging.replace(shard, index);
+            }
+            drop(staging);
+            self.commit().await;
         }
 
         Ok(())
     }
 
+    /// Saves the last committed generation; pending changes are not included. Storages that
+    /// already hold an earlier generation only receive what changed since.
     pub async fn save_index(&self) -> Result<()> {
-        let index = self.index.read().await;
-        self.storage
-            .save_index(&index)
-            .context("Failed to save index to storage")
+        self.ensure_writable()?;
+        let _saving = self.saving.write().await;
+        let shards = self.current().await;
+        for (shard, (storage, index)) in self.storages.iter().zip(shards.iter()).enumerate() {
+            let revision = index.changes.revision();
+            let saved = self.saved.lock().expect("saved lock poisoned")[shard];
+            match saved {
+                Some(saved) if saved == revision => continue,
+                Some(saved) if saved < revision => storage.save_changes(index, saved),
+                _ => storage.save_index(index),
+            }
+            .context("Failed to save index to storage")?;
+            self.saved.lock().expect("saved lock poisoned")[shard] = Some(revision);
+        }
+        Ok(())
     }
 
-    pub async fn index_directory(&self, directory_path: &str) -> Result<usize> {
-        let crawler = FileCrawler::new(directory_path);
-        let documents = crawler.crawl().context("Failed to crawl directory")?;
+    /// Runs `read` on the last committed generation. Like any `Generation`, it keeps saves,
+    /// loads and clears from changing the storage meanwhile, so documents not loaded yet are
+    /// read as of that generation too. Searches and indexing go on; only saves wait.
+    pub async fn read_committed<T>(
+        &self,
+        read: impl FnOnce(&[Arc<InvertedIndex>]) -> Result<T>,
+    ) -> Result<T> {
+        if self.mapped.is_some() {
+            return Err(anyhow!("Mapped indexes cannot be read as a whole"));
+        }
+        read(&self.snapshot().await)
+    }
 
-        let document_count = documents.len();
-        let mut index = self.index.write().await;
+    /// Every document of the last committed generation, each loaded and cloned when the
+    /// iterator reaches it. Saves wait until the iterator is dropped, see `Generation`.
+    pub async fn committed_documents(&self) -> Result<impl Iterator<Item = Document> + use<>> {
+        if self.mapped.is_some() {
+            return Err(anyhow!("Mapped indexes cannot be read as a whole"));
+        }
+        let shards = self.snapshot().await;
+        let doc_ids: Vec<(usize, Uuid)> = shards
+            .iter()
+            .enumerate()
+            .flat_map(|(shard, index)| index.documents.ids().map(move |doc_id| (shard, *doc_id)))
+            .collect();
+        Ok(doc_ids
+            .into_iter()
+            .filter_map(move |(shard, doc_id)| shards[shard].get_document(&doc_id))
+            .map(Arc::unwrap_or_clone))
+    }
 
-        for document in documents {
-            log::debug!("Indexing document: {}", document.title);
-            index.add_document(document);
+    /// Replaces the whole index with `indexes`, one per shard, e.g. from a snapshot. Pending
+    /// changes are discarded. Searches see the previous index until the replacement is
+    /// committed, then it is saved in full.
+    pub async fn restore(&self, indexes: Vec<InvertedIndex>) -> Result<()> {
+        self.ensure_writable()?;
+        if indexes.len() != self.shard_count() {
+            return Err(anyhow!(
+                "Cannot restore {} shards into an index with {}",
+                indexes.len(),
+                self.shard_count()
+            ));
         }
 
+        let saving = self.saving.write().await;
+        let mut staging = self.stage().await;
+        for (shard, index) in indexes.into_iter().enumerate() {
+            self.saved.lock().expect("saved lock poisoned")[shard] = None;
+            staging.replace(shard, index);
+        }
+        drop(staging);
+        self.commit().await;
+        drop(saving);
+
+        self.save_index().await
+    }
+
+    /// Indexes a single document built by the caller, e.g. one carrying a precomputed
+    /// embedding. Embeddings must match the dimensionality of those already indexed.
+    /// Returns `None` when the document was skipped as a near-duplicate. The document is
+    /// searchable after the next `commit`.
+    pub async fn add_document(&self, document: Document) -> Result<Option<Uuid>> {
+        self.ensure_writable()?;
+        let mut staging = self.stage().await;
+        if let Some(embedding) = &document.embedding {
+            for shard in 0..self.shard_count() {
+                staging
+                    .peek(shard)
+                    .vectors
+                    .validate(embedding)
+                    .with_context(|| format!("Invalid embedding for {}", document.path))?;
+            }
+        }
+
+        let doc_id = document.id;
+        let shard = self.shard_of(&doc_id);
+        Ok(staging
+            .shard(shard)
+            .add_document(document)?
+            .then_some(doc_id))
+    }
+
+    pub async fn index_directory(&self, directory_path: &str) -> Result<usize> {
+        let crawler = FileCrawler::new(directory_path);
+        let documents = stream::iter(crawler.documents()).map(|document| {
+            document
+                .map(CrawledPage::from)
+                .context("Failed to crawl directory")
+        });
+        let progress = self.ingest(documents).await?;
+
         log::info!(
             "Indexed {} documents from {}",
-            document_count,
+            progress.indexed,
             directory_path
         );
-        Ok(document_count)
+        Ok(progress.indexed)
     }
 
-    pub async fn search(
+    /// Indexes documents read from an export, in batches like a crawl, e.g. from
+    /// `bulk::read_documents`. Documents with the id of an indexed one replace it.
+    pub async fn import<S>(&self, documents: S) -> Result<IngestProgress>
+    where
+        S: Stream<Item = Result<Document>>,
+    {
+        let progress = self
+            .ingest(documents.map(|document| document.map(CrawledPage::from)))
+            .await?;
+        log::info!(
+            "Imported {} of {} documents",
+            progress.indexed,
+            progress.crawled
+        );
+        Ok(progress)
+    }
+
+    /// Indexes pages while they are being crawled. Every `batch_size` pages the batch is
+    /// committed, making it searchable, and saved if the options say so. The link graph and
+    /// anchor text grow with each batch; authority is computed once all pages are indexed and
+    /// committed on its own. When the stream fails, the pages received before the error are
+    /// still committed.
+    ///
+    /// A page whose document id is already indexed, e.g. an imported document, replaces it.
+    ///
+    /// Near-duplicates are only detected within a shard. Every shard keeps the whole link
+    /// graph, so authority and anchor text are the same as without sharding.
+    pub async fn ingest<S>(&self, pages: S) -> Result<IngestProgress>
+    where
+        S: Stream<Item = Result<CrawledPage>>,
+    {
+        self.ensure_writable()?;
+        let options = &self.ingest_options;
+        let started = Instant::now();
+        let mut progress = IngestProgress::default();
+        self.report(&progress);
+        let mut reported = Instant::now();
+
+        futures::pin_mut!(pages);
+        let mut batch = Batch::new(self.shard_count());
+        let mut linked = false;
+        let mut failure = None;
+
+        loop {
+            let page = match pages.next().await {
+                Some(Ok(page)) => Some(page),
+                Some(Err(e)) => {
+                    failure = Some(e);
+                    None
+                }
+                None => None,
+            };
+            let Some(page) = page else {
+                self.commit_batch(batch, &mut progress, &mut failure).await;
+                break;
+            };
+
+            log::debug!("Indexing document: {}", page.document.title);
+            linked |= page.links.is_some();
+            progress.crawled += 1;
+            let shard = self.shard_of(&page.document.id);
+            if batch.add(page, shard) {
+                progress.replaced += 1;
+            }
+            progress.elapsed_secs = started.elapsed().as_secs_f64();
+            progress.docs_per_sec = progress.crawled as f64 / progress.elapsed_secs.max(1e-9);
+            if reported.elapsed() >= options.progress_interval {
+                self.report(&progress);
+                reported = Instant::now();
+            } else {
+                self.track(&progress);
+            }
+
+            if batch.len() >= options.batch_size.max(1) {
+                let full = std::mem::replace(&mut batch, Batch::new(self.shard_count()));
+                self.commit_batch(full, &mut progress, &mut failure).await;
+                reported = Instant::now();
+                if failure.is_some() {
+                    break;
+                }
+            }
+        }
+
+        // PageRank is global, so it is computed once for the whole crawl rather than per batch
+        if linked && progress.batches > 0 {
+            let mut staging = self.stage().await;
+            let ranks = staging.peek(0).link_graph.pagerank();
+            for index in staging.all() {
+                index.set_authority(&ranks);
+            }
+            drop(staging);
+            progress.generation = self.commit().await;
+            if options.save
+                && let Err(e) = self.save_index().await
+            {
+                failure.get_or_insert(e);
+            }
+            self.report(&progress);
+        }
+
+        self.progress.lock().expect("progress lock poisoned").take();
+        match failure {
+            Some(e) => Err(e),
+            None => Ok(progress),
+        }
+    }
+
+    /// Indexes and commits a batch of pages, and saves it if the options say so. When a shard
+    /// fails to index its pages, none of the batch is committed. Errors are kept in
+    /// `failure`, the first one winning.
+    async fn commit_batch(
+        &self,
+        batch: Batch,
+        progress: &mut IngestProgress,
+        failure: &mut Option<anyhow::Error>,
+    ) {
+        if batch.is_empty() {
+            return;
+        }
+
+        // Every shard is indexed on a copy first, so a failing shard leaves the others untouched
+        let mut staging = self.stage().await;
+        let mut indexed = Vec::new();
+        for (shard, documents) in batch.documents.into_iter().enumerate() {
+            if documents.is_empty() {
+                continue;
+            }
+            let mut index = staging.shard(shard).clone();
+            let count = documents.len();
+            let existing = documents
+                .iter()
+                .filter(|document| index.documents.contains(&document.id))
+                .count();
+            match index.add_documents(documents) {
+                Ok(added) => indexed.push((shard, index, count, added, existing)),
+                Err(e) => {
+                    failure.get_or_insert(e.context("Failed to index a batch of documents"));
+                    return;
+                }
+            }
+        }
+        for (shard, index, count, added, existing) in indexed {
+            staging.replace(shard, index);
+            progress.indexed += added;
+            progress.skipped += count - added;
+            progress.replaced += existing;
+        }
+        if batch.links.page_count() > 0 {
+            for index in staging.all() {
+                index.add_links(batch.links.clone());
+            }
+        }
+        drop(staging);
+
+        progress.generation = self.commit().await;
+        progress.batches += 1;
+        if self.ingest_options.save
+            && let Err(e) = self.save_index().await
+        {
+            failure.get_or_insert(e);
+        }
+        log::info!(
+            "Ingested {} documents, {} indexed, in {} batches ({:.0} docs/sec)",
+            progress.crawled,
+            progress.indexed,
+            progress.batches,
+            progress.docs_per_sec
+        );
+        self.report(progress);
+    }
+
+    /// Progress of the running ingestion, if any.
+    pub fn progress(&self) -> Option<IngestProgress> {
+        self.progress
+            .lock()
+            .expect("progress lock poisoned")
+            .clone()
+    }
+
+    /// Publishes `progress` and passes it to the progress callback.
+    fn report(&self, progress: &IngestProgress) {
+        self.track(progress);
+        if let Some(on_progress) = &self.ingest_options.on_progress {
+            on_progress(progress);
+        }
+    }
+
+    /// Publishes `progress` for `SearchEngine::progress` without calling back.
+    fn track(&self, progress: &IngestProgress) {
+        *self.progress.lock().expect("progress lock poisoned") = Some(progress.clone());
+    }
+
+    pub async fn search(&self, query: &str, limit: Option<usize>) -> Result<Vec<SearchResult>> {
+        let options = SearchOptions::new().with_limit(limit.unwrap_or(10));
+        Ok(self.search_with_options(query, &options).await?.results)
+    }
+
+    /// Fails for options this engine cannot apply, e.g. facets on a memory-mapped index.
+    pub fn check_options(&self, options: &SearchOptions) -> Result<()> {
+        match self.mapped {
+            Some(_) => TfIdfRanker::check_mapped(options),
+            None => Ok(()),
+        }
+    }
+
+    pub async fn search_with_options(
         &self,
         query: &str,
-        limit: Option<usize>,
-    ) -> Result<Vec<crate::core::ranking::SearchResult>> {
-        let index = self.index.read().await;
-        let results = TfIdfRanker::rank_documents(&index, query, limit);
+        options: &SearchOptions,
+    ) -> Result<SearchResults> {
+        let results = match &self.mapped {
+            Some(mapped) => TfIdfRanker::rank_mapped(mapped, query, options)?,
+            None => Self::rank(&self.snapshot().await, query, options),
+        };
+
+        log::info!(
+            "Search for '{}' returned {} of {} results",
+            query,
+            results.results.len(),
+            results.total_hits
+        );
+        Ok(results)
+    }
+
+    /// Ranks all shards together, see `TfIdfRanker::rank_shards`.
+    fn rank(shards: &[Arc<InvertedIndex>], query: &str, options: &SearchOptions) -> SearchResults {
+        let shards: Vec<&InvertedIndex> = shards.iter().map(Arc::as_ref).collect();
+        TfIdfRanker::rank_shards(&shards, query, options)
+    }
 
-        log::info!("Search for '{}' returned {} results", query, results.len());
+    /// Finds documents similar to `doc_id` by querying its most distinctive terms, weighted by
+    /// TF-IDF. The document itself is never part of the results.
+    pub async fn similar(&self, doc_id: &Uuid, limit: usize) -> Result<Vec<SearchResult>> {
+        if self.mapped.is_some() {
+            return Err(anyhow!(
+                "Similar documents are not supported by mapped indexes"
+            ));
+        }
+        let shards = self.snapshot().await;
+        let Some(index) = shards
+            .iter()
+            .find(|index| index.get_document(doc_id).is_some())
+        else {
+            return Err(anyhow!("No document with id {}", doc_id));
+        };
+
+        // Terms are weighted by how rare they are in all shards, not just the document's
+        let collection = match shards.len() {
+            1 => None,
+            _ => {
+                let terms = index
+                    .term_frequencies(doc_id)
+                    .into_keys()
+                    .collect::<Vec<_>>();
+                let mut collection = CollectionStats::default();
+                for shard in shards.iter() {
+                    collection.merge(shard.term_stats(terms.iter().map(String::as_str)));
+                }
+                Some(collection)
+            }
+        };
+        let query = index
+            .distinctive_terms(doc_id, Self::SIMILAR_QUERY_TERMS, collection.as_ref())
+            .iter()
+            .map(QueryTerm::to_string)
+            .collect::<Vec<_>>()
+            .join(" ");
+        log::debug!("Similar documents query for {}: {}", doc_id, query);
+
+        // Ask for one extra hit since the source document usually ranks first
+        let options = SearchOptions::new().with_limit(limit + 1);
+        let mut results = Self::rank(&shards, &query, &options).results;
+        results.retain(|result| result.document.id != *doc_id);
+        results.truncate(limit);
         Ok(results)
     }
 
+    pub async fn document_id(&self, path: &str) -> Option<Uuid> {
+        if let Some(mapped) = &self.mapped {
+            return mapped.doc_id_by_path(path).unwrap_or_else(|e| {
+                log::error!("Failed to look up {} in the mapped index: {:#}", path, e);
+                None
+            });
+        }
+        self.snapshot()
+            .await
+            .iter()
+            .find_map(|index| index.doc_id_by_path(path))
+    }
+
     pub async fn clear_index(&self) -> Result<()> {
-        let mut index = self.index.write().await;
-        *index = InvertedIndex::new();
+        self.ensure_writable()?;
+        let _saving = self.saving.write().await;
+        let mut staging = self.stage().await;
+        let settings = staging.peek(0).settings.clone();
+        for shard in 0..self.shard_count() {
+            staging.replace(shard, InvertedIndex::with_settings(settings.clone()));
+        }
+        drop(staging);
+        self.commit().await;
 
-        self.storage.clear().context("Failed to clear storage")?;
+        for (shard, storage) in self.storages.iter().enumerate() {
+            storage.clear().context("Failed to clear storage")?;
+            self.saved.lock().expect("saved lock poisoned")[shard] = None;
+        }
 
         log::info!("Index cleared");
         Ok(())
     }
 
     pub async fn get_stats(&self) -> (usize, usize) {
-        let index = self.index.read().await;
-        (index.total_documents, index.index.len())
+        if let Some(mapped) = &self.mapped {
+            return (mapped.total_documents(), mapped.term_count());
+        }
+        let shards = self.snapshot().await;
+        let documents = shards.iter().map(|index| index.total_documents).sum();
+        let terms = match &shards[..] {
+            [index] => index.index.len(),
+            _ => shards
+                .iter()
+                .flat_map(|index| index.index.keys())
+                .collect::<HashSet<_>>()
+                .len(),
+        };
+        (documents, terms)
     }
 
     pub async fn index_web_pages(
@@ -102,21 +730,10 @@ impl SearchEngine {
             crawler = crawler.with_max_pages(max);
         }
 
-        let documents = crawler
-            .crawl(start_urls)
-            .await
-            .context("Failed to crawl web pages")?;
-
-        let document_count = documents.len();
-        let mut index = self.index.write().await;
-
-        for document in documents {
-            log::debug!("Indexing web document: {}", document.title);
-            index.add_document(document);
-        }
+        let progress = self.ingest(crawler.pages(start_urls).map(Ok)).await?;
 
-        log::info!("Indexed {} web documents", document_count);
-        Ok(document_count)
+        log::info!("Indexed {} web documents", progress.indexed);
+        Ok(progress.indexed)
     }
 
     pub async fn index_website(&self, base_url: String, max_pages: Option<usize>) -> Result<usize> {
@@ -129,25 +746,895 @@ impl SearchEngine {
             .with_allowed_domains(vec![domain])
             .with_delay(2000); // 2 second delay for single domain crawling
 
-        let documents = crawler
-            .crawl(vec![base_url])
+        let progress = self.ingest(crawler.pages(vec![base_url]).map(Ok)).await?;
+
+        log::info!("Indexed {} documents from website", progress.indexed);
+        Ok(progress.indexed)
+    }
+
+    /// The canonical document of the near-duplicate group `doc_id` belongs to, followed by
+    /// its duplicates. `None` if the document is unknown.
+    pub async fn duplicates(&self, doc_id: &Uuid) -> Option<(Document, Vec<Document>)> {
+        let shards = self.snapshot().await;
+        let index = shards
+            .iter()
+            .find(|index| index.get_document(doc_id).is_some())?;
+
+        let canonical = index.duplicates.canonical_of(doc_id);
+        let duplicates = index
+            .duplicates
+            .duplicates_of(&canonical)
+            .iter()
+            .filter_map(|id| index.get_document(id))
+            .map(Arc::unwrap_or_clone)
+            .collect();
+        Some((
+            Arc::unwrap_or_clone(index.get_document(&canonical)?),
+            duplicates,
+        ))
+    }
+
+    /// Sets the static boost of the document at `path`. Returns `false` if no such document
+    /// is indexed, an error for boosts that are not finite and above zero. Takes effect with
+    /// the next `commit`.
+    pub async fn set_boost(&self, path: &str, boost: f64) -> Result<bool> {
+        if !Document::is_valid_boost(boost) {
+            return Err(anyhow!(
+                "Boost must be a finite number above 0, got {}",
+                boost
+            ));
+        }
+        if self.mapped.is_some() {
+            return Ok(false);
+        }
+        let mut staging = self.stage().await;
+        let found = (0..self.shard_count())
+            .find_map(|shard| Some((shard, staging.peek(shard).doc_id_by_path(path)?)));
+        Ok(match found {
+            Some((shard, doc_id)) => staging.shard(shard).set_boost(&doc_id, boost)?,
+            None => false,
+        })
+    }
+
+    pub async fn list_all_documents(&self) -> Vec<Document> {
+        if let Some(mapped) = &self.mapped {
+            return mapped
+                .documents()
+                .filter_map(|document| {
+                    document
+                        .map_err(|e| log::error!("Skipping mapped document: {:#}", e))
+                        .ok()
+                })
+                .collect();
+        }
+        let shards = self.snapshot().await;
+        shards
+            .iter()
+            .flat_map(|index| index.get_all_documents())
+            .map(Arc::unwrap_or_clone)
+            .collect()
+    }
+}
+
+/// Pages of an ingestion received since its last commit.
+struct Batch {
+    /// Documents by shard
+    documents: Vec<Vec<Document>>,
+    /// Position of each document in its shard's list, so a later page with the same id
+    /// replaces it instead of being indexed twice
+    positions: HashMap<Uuid, usize>,
+    links: LinkGraph,
+}
+
+impl Batch {
+    fn new(shards: usize) -> Self {
+        Self {
+            documents: vec![Vec::new(); shards],
+            positions: HashMap::new(),
+            links: LinkGraph::new(),
+        }
+    }
+
+    /// Adds a page for `shard` and returns whether it replaced one with the same id.
+    fn add(&mut self, page: CrawledPage, shard: usize) -> bool {
+        if let Some(links) = page.links {
+            self.links.set_outlinks(page.document.path.clone(), links);
+        }
+        let documents = &mut self.documents[shard];
+        match self.positions.get(&page.document.id) {
+            Some(&position) => {
+                documents[position] = page.document;
+                true
+            }
+            None => {
+                self.positions.insert(page.document.id, documents.len());
+                documents.push(page.document);
+                false
+            }
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+}
+
+/// The next generation, locked for changes.
+struct Staging<'a> {
+    shards: MutexGuard<'a, Vec<Option<InvertedIndex>>>,
+    committed: Arc<Vec<Arc<InvertedIndex>>>,
+    saved: Vec<Option<u64>>,
+    sources: Vec<Option<IndexSources>>,
+}
+
+impl Staging<'_> {
+    /// A shard of the next generation, copied from the committed one on its first change.
+    fn shard(&mut self, shard: usize) -> &mut InvertedIndex {
+        let (committed, saved) = (&self.committed[shard], self.saved[shard]);
+        let sources = self.sources[shard].as_ref();
+        self.shards[shard].get_or_insert_with(|| Self::copy(committed, saved, sources))
+    }
+
+    /// Every shard of the next generation.
+    fn all(&mut self) -> impl Iterator<Item = &mut InvertedIndex> {
+        self.shards
+            .iter_mut()
+            .zip(self.committed.iter().zip(&self.saved).zip(&self.sources))
+            .map(|(shard, ((committed, saved), sources))| {
+                shard.get_or_insert_with(|| Self::copy(committed, *saved, sources.as_ref()))
+            })
+    }
+
+    /// Copies a committed shard, sharing all its data with it, forgetting changes its storage
+    /// already holds and, when the storage can read them back, dropping the postings and
+    /// documents they touched from memory.
+    fn copy(
+        committed: &InvertedIndex,
+        saved: Option<u64>,
+        sources: Option<&IndexSources>,
+    ) -> InvertedIndex {
+        let mut index = committed.clone();
+        if let Some(saved) = saved {
+            let saved = index.changes.forget(saved);
+            if let Some(sources) = sources {
+                index.index.release(saved.terms, &sources.postings);
+                index.documents.release(saved.documents, &sources.documents);
+            }
+        }
+        index
+    }
+
+    /// A shard as it would be committed now, without copying it.
+    fn peek(&self, shard: usize) -> &InvertedIndex {
+        self.shards[shard]
+            .as_ref()
+            .unwrap_or(&self.committed[shard])
+    }
+
+    fn replace(&mut self, shard: usize, index: InvertedIndex) {
+        self.shards[shard] = Some(index);
+    }
+}
+
+/// A committed generation of an index, one `InvertedIndex` per shard. Documents and postings
+/// it has not loaded are read from storage, which saves, loads and clears leave alone while
+/// it is held, so they are read as of this generation.
+pub struct Generation {
+    shards: Arc<Vec<Arc<InvertedIndex>>>,
+    _saving: OwnedRwLockReadGuard<()>,
+}
+
+impl Deref for Generation {
+    type Target = [Arc<InvertedIndex>];
+
+    fn deref(&self) -> &Self::Target {
+        &self.shards
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{Collapse, DuplicateMode, Fusion, Link, Section};
+    use crate::storage::{JsonStorage, SledStorage};
+    use std::time::Duration;
+
+    const TOPICS: [&str; 4] = [
+        "memory safety",
+        "garbage collection",
+        "async runtime",
+        "web server",
+    ];
+
+    async fn engine(shards: usize, dir: &Path) -> SearchEngine {
+        let storages = (0..shards)
+            .map(|shard| {
+                let path = dir.join(format!("shard{}.json", shard));
+                Arc::new(JsonStorage::new(path.to_string_lossy().to_string())) as Arc<dyn Storage>
+            })
+            .collect();
+        let engine = SearchEngine::sharded(storages, IndexSettings::default());
+        for i in 0..24 {
+            let topic = TOPICS[i % TOPICS.len()];
+            let mut document = Document::new(
+                format!("Page {}", i),
+                format!(
+                    "Page {} is about {} and {}",
+                    i,
+                    topic,
+                    "memory ".repeat(i % 5)
+                ),
+                format!("https://site{}.example/page{}", i % 3, i),
+            )
+            .with_embedding(vec![(i % 4) as f32 + 0.5, (i % 7) as f32, 1.0]);
+            document.id = Uuid::from_u128(i as u128 + 1);
+            engine.add_document(document).await.unwrap();
+        }
+        engine.commit().await;
+        engine
+    }
+
+    async fn ranked(engine: &SearchEngine, options: &SearchOptions) -> Vec<(Uuid, f64)> {
+        engine
+            .search_with_options("memory", options)
             .await
-            .context("Failed to crawl website")?;
+            .unwrap()
+            .results
+            .iter()
+            .map(|result| (result.document.id, result.score))
+            .collect()
+    }
+
+    fn assert_same_ranking(a: &[(Uuid, f64)], b: &[(Uuid, f64)]) {
+        assert_eq!(a.len(), b.len());
+        for ((a_id, a_score), (b_id, b_score)) in a.iter().zip(b) {
+            assert_eq!(a_id, b_id);
+            assert!(
+                (a_score - b_score).abs() < 1e-9,
+                "{} != {}",
+                a_score,
+                b_score
+            );
+        }
+    }
 
-        let document_count = documents.len();
-        let mut index = self.index.write().await;
+    #[tokio::test]
+    async fn shards_fuse_like_a_single_index() {
+        let dir = tempfile::tempdir().unwrap();
+        let single = engine(1, dir.path()).await;
+        let sharded = engine(3, dir.path()).await;
 
-        for document in documents {
-            log::debug!("Indexing web document: {}", document.title);
-            index.add_document(document);
+        for fusion in [Fusion::default(), Fusion::Weighted { alpha: 0.7 }] {
+            let options = SearchOptions::new()
+                .with_limit(20)
+                .with_vector(vec![1.5, 3.0, 1.0])
+                .with_fusion(fusion);
+            assert_same_ranking(
+                &ranked(&single, &options).await,
+                &ranked(&sharded, &options).await,
+            );
         }
+    }
+
+    #[tokio::test]
+    async fn shards_collapse_and_diversify_like_a_single_index() {
+        let dir = tempfile::tempdir().unwrap();
+        let single = engine(1, dir.path()).await;
+        let sharded = engine(3, dir.path()).await;
+
+        let options = SearchOptions::new()
+            .with_limit(10)
+            .with_collapse(Collapse::parse("host:2").unwrap())
+            .with_diversify(0.5);
+        let single = single
+            .search_with_options("memory", &options)
+            .await
+            .unwrap();
+        let sharded = sharded
+            .search_with_options("memory", &options)
+            .await
+            .unwrap();
 
-        log::info!("Indexed {} documents from website", document_count);
-        Ok(document_count)
+        // Three hosts, at most two hits each, whichever shards hold them
+        assert_eq!(sharded.results.len(), 6);
+        assert_eq!(single.total_hits, sharded.total_hits);
+        for (a, b) in single.results.iter().zip(&sharded.results) {
+            assert_eq!(a.document.id, b.document.id);
+            assert_eq!(a.more_in_group, b.more_in_group);
+        }
     }
 
-    pub async fn list_all_documents(&self) -> Vec<Document> {
-        let index = self.index.read().await;
-        index.get_all_documents().into_iter().cloned().collect()
+    #[tokio::test]
+    async fn import_replaces_repeated_ids() {
+        let dir = tempfile::tempdir().unwrap();
+        let engine = engine(2, dir.path()).await;
+        let (documents, tokens) = {
+            let shards = engine.snapshot().await;
+            let documents: usize = shards.iter().map(|index| index.total_documents).sum();
+            let tokens: usize = shards.iter().map(|index| index.total_tokens).sum();
+            (documents, tokens)
+        };
+
+        let document = |content: &str| {
+            let mut document = Document::new(
+                "Imported".to_string(),
+                content.to_string(),
+                "https://import.example/".to_string(),
+            );
+            document.id = Uuid::from_u128(1000);
+            Ok(document)
+        };
+        let progress = engine
+            .import(stream::iter([
+                document("first copy"),
+                document("second copy now"),
+            ]))
+            .await
+            .unwrap();
+        assert_eq!(
+            (progress.crawled, progress.indexed, progress.replaced),
+            (2, 1, 1)
+        );
+
+        let shards = engine.snapshot().await;
+        let total_documents: usize = shards.iter().map(|index| index.total_documents).sum();
+        let total_tokens: usize = shards.iter().map(|index| index.total_tokens).sum();
+        assert_eq!(total_documents, documents + 1);
+        let index = shards
+            .iter()
+            .find(|index| index.documents.contains(&Uuid::from_u128(1000)))
+            .unwrap();
+        assert_eq!(
+            total_tokens,
+            tokens + index.doc_lengths[&Uuid::from_u128(1000)]
+        );
+        let imported = index.get_document(&Uuid::from_u128(1000)).unwrap();
+        assert_eq!(imported.content, "second copy now");
+        drop(shards);
+
+        // A later import replaces the indexed copy as well
+        let progress = engine
+            .import(stream::iter([document("third copy")]))
+            .await
+            .unwrap();
+        assert_eq!(
+            (
+                progress.crawled,
+                progress.indexed,
+                progress.skipped,
+                progress.replaced
+            ),
+            (1, 1, 0, 1)
+        );
+    }
+
+    #[tokio::test]
+    async fn releases_saved_documents_and_reads_them_back() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let storage = Arc::new(SledStorage::with_tree(db, "index").unwrap());
+        let engine = SearchEngine::new(storage);
+        let rust = Document::new(
+            "Rust".to_string(),
+            "Ownership and borrowing".to_string(),
+            "rust.md".to_string(),
+        );
+        let rust_id = rust.id;
+        engine.add_document(rust).await.unwrap();
+        engine.commit().await;
+        engine.save_index().await.unwrap();
+
+        engine
+            .add_document(Document::new(
+                "Go".to_string(),
+                "Goroutines and borrowing channels".to_string(),
+                "go.md".to_string(),
+            ))
+            .await
+            .unwrap();
+        engine.commit().await;
+        let shards = engine.snapshot().await;
+        assert!(format!("{:?}", shards[0].documents).contains("held: 1"));
+        assert_eq!(engine.search("borrowing", None).await.unwrap().len(), 2);
+        let rust = shards[0].get_document(&rust_id).unwrap();
+        assert_eq!(rust.content, "Ownership and borrowing");
+    }
+
+    #[tokio::test]
+    async fn saves_wait_for_generations_reading_the_storage() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let storage = || Arc::new(SledStorage::with_tree(db.clone(), "index").unwrap());
+        let document = |content: &str| {
+            let mut document = Document::new(
+                "Rust".to_string(),
+                content.to_string(),
+                "rust.md".to_string(),
+            );
+            document.id = Uuid::from_u128(1);
+            document
+        };
+        let engine = SearchEngine::new(storage());
+        engine
+            .add_document(document("first version"))
+            .await
+            .unwrap();
+        engine.commit().await;
+        engine.save_index().await.unwrap();
+
+        // Loaded documents are read from the storage when first needed
+        let engine = Arc::new(SearchEngine::new(storage()));
+        engine.load_index().await.unwrap();
+        let loaded = engine.snapshot().await;
+        engine
+            .add_document(document("second version"))
+            .await
+            .unwrap();
+        engine.commit().await;
+        let mut saving = tokio::spawn({
+            let engine = Arc::clone(&engine);
+            async move { engine.save_index().await }
+        });
+        let waited = tokio::time::timeout(Duration::from_millis(50), &mut saving).await;
+        assert!(waited.is_err());
+
+        let read = loaded[0].get_document(&Uuid::from_u128(1)).unwrap();
+        assert_eq!(read.content, "first version");
+        drop(loaded);
+        saving.await.unwrap().unwrap();
+        let shards = engine.snapshot().await;
+        let read = shards[0].get_document(&Uuid::from_u128(1)).unwrap();
+        assert_eq!(read.content, "second version");
+    }
+
+    #[tokio::test]
+    async fn failing_shard_discards_the_whole_batch() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let storages = || {
+            (0..2)
+                .map(|shard| {
+                    let tree = format!("shard{}", shard);
+                    Arc::new(SledStorage::with_tree(db.clone(), &tree).unwrap()) as Arc<dyn Storage>
+                })
+                .collect::<Vec<_>>()
+        };
+        let document = |id: u128, content: &str| {
+            let mut document = Document::new(
+                format!("Page {}", id),
+                content.to_string(),
+                format!("page{}.md", id),
+            );
+            document.id = Uuid::from_u128(id);
+            document
+        };
+
+        // Ids are spread over shards by their remainder, so 3 lives in shard 1
+        let engine = SearchEngine::sharded(storages(), IndexSettings::default());
+        engine
+            .add_document(document(3, "stored page"))
+            .await
+            .unwrap();
+        engine.commit().await;
+        engine.save_index().await.unwrap();
+        db.open_tree("documents:shard1")
+            .unwrap()
+            .insert(Uuid::from_u128(3).as_bytes(), b"corrupt".to_vec())
+            .unwrap();
+
+        let engine = SearchEngine::sharded(storages(), IndexSettings::default());
+        engine.load_index().await.unwrap();
+        let imported = engine
+            .import(stream::iter([
+                Ok(document(2, "fresh page")),
+                Ok(document(3, "replacement page")),
+            ]))
+            .await;
+        assert!(imported.is_err());
+
+        let shards = engine.snapshot().await;
+        assert!(!shards[0].documents.contains(&Uuid::from_u128(2)));
+        assert_eq!(shards[0].total_documents, 0);
+        assert!(engine.search("fresh", None).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn staged_changes_leave_the_committed_generation_untouched() {
+        let dir = tempfile::tempdir().unwrap();
+        let engine = engine(1, dir.path()).await;
+        let committed = engine.snapshot().await;
+        let before = committed[0].search("memory", None);
+
+        let mut document = Document::new(
+            "Memory".to_string(),
+            "memory memory memory safety".to_string(),
+            "https://site0.example/memory".to_string(),
+        );
+        document.id = Uuid::from_u128(1);
+        engine.add_document(document).await.unwrap();
+        engine
+            .set_boost("https://site1.example/page1", 3.0)
+            .await
+            .unwrap();
+        engine.commit().await;
+
+        assert_eq!(committed[0].search("memory", None), before);
+        assert_eq!(committed[0].total_documents, 24);
+        assert_eq!(committed[0].boosts.len(), 0);
+        let current = engine.snapshot().await;
+        assert_ne!(current[0].search("memory", None), before);
+        assert_eq!(current[0].boosts.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn ingest_builds_links_per_batch_and_authority_once() {
+        let page = |n: u128, links: &[(u128, &str)]| {
+            let mut document = Document::new(
+                format!("Page {}", n),
+                format!("Page {} about crawling", n),
+                format!("https://crawl.example/{}", n),
+            );
+            document.id = Uuid::from_u128(n);
+            let links = links
+                .iter()
+                .map(|(target, text)| Link {
+                    target: format!("https://crawl.example/{}", target),
+                    anchor_text: text.to_string(),
+                })
+                .collect();
+            CrawledPage {
+                document,
+                links: Some(links),
+            }
+        };
+        // Page 1 is crawled again in the last batch with other links
+        let pages = vec![
+            page(1, &[(2, "rust guide"), (3, "async book")]),
+            page(2, &[(3, "async runtime")]),
+            page(3, &[(1, "home")]),
+            page(4, &[(2, "rust guide"), (1, "home")]),
+            page(1, &[(4, "web server")]),
+            page(5, &[(4, "web server"), (3, "async")]),
+        ];
+
+        let mut expected = InvertedIndex::new();
+        let mut graph = LinkGraph::new();
+        for page in pages.iter().skip(1) {
+            expected.add_document(page.document.clone()).unwrap();
+            let links = page.links.clone().unwrap();
+            graph.set_outlinks(page.document.path.clone(), links);
+        }
+        expected.update_link_graph(graph);
+
+        let dir = tempfile::tempdir().unwrap();
+        let storages = (0..2)
+            .map(|shard| {
+                let path = dir.path().join(format!("shard{}.json", shard));
+                Arc::new(JsonStorage::new(path.to_string_lossy().to_string())) as Arc<dyn Storage>
+            })
+            .collect();
+        let reports = Arc::new(StdMutex::new(Vec::new()));
+        let recorded = Arc::clone(&reports);
+        let options = IngestOptions::new()
+            .with_batch_size(2)
+            .with_save(false)
+            .with_progress_interval(std::time::Duration::ZERO)
+            .with_progress(move |progress| recorded.lock().unwrap().push(progress.crawled));
+        let engine =
+            SearchEngine::sharded(storages, IndexSettings::default()).with_ingest_options(options);
+        let progress = engine
+            .ingest(stream::iter(pages.into_iter().map(Ok)))
+            .await
+            .unwrap();
+
+        assert_eq!((progress.crawled, progress.batches), (6, 3));
+        let reports = reports.lock().unwrap().clone();
+        assert!((1..=6).all(|crawled| reports.contains(&crawled)));
+        assert_eq!(
+            expected
+                .anchors
+                .postings("rust")
+                .map(|targets| targets.len()),
+            Some(1)
+        );
+        for index in engine.snapshot().await.iter() {
+            for term in ["rust", "guid", "async", "home", "web"] {
+                assert_eq!(
+                    index.anchors.postings(term),
+                    expected.anchors.postings(term),
+                    "anchors of '{}'",
+                    term
+                );
+            }
+            assert_eq!(
+                index.anchors.stats(1.0).total_tokens,
+                expected.anchors.stats(1.0).total_tokens
+            );
+            for doc_id in index.documents.ids() {
+                let authority = index.authority.get(doc_id).copied().unwrap();
+                assert!((authority - expected.authority[doc_id]).abs() < 1e-12);
+            }
+        }
+    }
+
+    fn crawled(n: u128, content: &str, links: &[(u128, &str)]) -> CrawledPage {
+        let mut document = Document::new(
+            format!("Page {}", n),
+            content.to_string(),
+            format!("https://crawl.example/{}", n),
+        );
+        document.id = Uuid::from_u128(n);
+        let links = links
+            .iter()
+            .map(|(target, text)| Link {
+                target: format!("https://crawl.example/{}", target),
+                anchor_text: text.to_string(),
+            })
+            .collect();
+        CrawledPage {
+            document,
+            links: Some(links),
+        }
+    }
+
+    #[tokio::test]
+    async fn linked_hub_pages_outrank_orphans_with_the_same_text() {
+        let dir = tempfile::tempdir().unwrap();
+        let crawl = |authority_weight: f64| {
+            let path = dir.path().join(format!("{}.json", authority_weight));
+            let settings = IndexSettings {
+                authority_weight,
+                ..IndexSettings::default()
+            };
+            async move {
+                let storage = Arc::new(JsonStorage::new(path.to_string_lossy().to_string()));
+                let engine = SearchEngine::with_settings(storage, settings);
+                // Page 1 is linked from every other page, page 2 from none
+                let pages = vec![
+                    crawled(1, "Borrow checker guide", &[]),
+                    crawled(2, "Borrow checker guide", &[]),
+                    crawled(3, "Links to the hub", &[(1, "")]),
+                    crawled(4, "More links", &[(1, ""), (3, "")]),
+                    crawled(5, "Even more links", &[(1, ""), (4, "")]),
+                ];
+                engine
+                    .ingest(stream::iter(pages.into_iter().map(Ok)))
+                    .await
+                    .unwrap();
+                engine.search("borrow checker", None).await.unwrap()
+            }
+        };
+
+        let blended = crawl(0.5).await;
+        assert_eq!(blended.len(), 2);
+        assert_eq!(blended[0].document.id, Uuid::from_u128(1));
+        assert_eq!(blended[1].document.id, Uuid::from_u128(2));
+        assert!(blended[0].score > blended[1].score);
+
+        // Without the authority weight only the text counts
+        let text_only = crawl(0.0).await;
+        assert_eq!(text_only[0].score, text_only[1].score);
+    }
+
+    #[tokio::test]
+    async fn finds_pages_by_the_anchor_text_of_links_to_them() {
+        let dir = tempfile::tempdir().unwrap();
+        let crawl = |anchor_boost: f64| {
+            let path = dir.path().join(format!("{}.json", anchor_boost));
+            let settings = IndexSettings {
+                anchor_boost,
+                ..IndexSettings::default()
+            };
+            async move {
+                let storage = Arc::new(JsonStorage::new(path.to_string_lossy().to_string()));
+                let engine = SearchEngine::with_settings(storage, settings);
+                // Page 9 is linked to before it is crawled
+                let pages = [
+                    crawled(1, "Ownership rules", &[]),
+                    crawled(
+                        2,
+                        "Reading list",
+                        &[(1, "borrow checker"), (9, "async runtime")],
+                    ),
+                ];
+                engine
+                    .ingest(stream::iter(pages.into_iter().map(Ok)))
+                    .await
+                    .unwrap();
+                engine
+            }
+        };
+
+        let engine = crawl(1.5).await;
+        let results = engine.search("borrow checker", None).await.unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].document.id, Uuid::from_u128(1));
+        let score = results[0].score;
+        assert!(
+            engine
+                .search("async runtime", None)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+
+        engine
+            .ingest(stream::iter([Ok(crawled(9, "Tokio notes", &[]))]))
+            .await
+            .unwrap();
+        let results = engine.search("async runtime", None).await.unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].document.id, Uuid::from_u128(9));
+
+        // The anchor field has a boost of its own
+        let boosted = crawl(3.0).await;
+        let boosted = boosted.search("borrow checker", None).await.unwrap();
+        assert!(boosted[0].score > score);
+    }
+
+    #[tokio::test]
+    async fn similar_documents_share_distinctive_terms() {
+        let dir = tempfile::tempdir().unwrap();
+        let storages = (0..2)
+            .map(|shard| {
+                let path = dir.path().join(format!("shard{}.json", shard));
+                Arc::new(JsonStorage::new(path.to_string_lossy().to_string())) as Arc<dyn Storage>
+            })
+            .collect();
+        let engine = SearchEngine::sharded(storages, IndexSettings::default());
+        let contents = [
+            "Rust ownership and borrowing keep memory safe",
+            "Borrowing rules of Rust ownership explained",
+            "Python garbage collection internals",
+            "Ownership in Rust: moves and borrowing",
+            "Cooking pasta at home",
+        ];
+        for (n, content) in contents.iter().enumerate() {
+            let mut document = Document::new(
+                format!("Note {}", n + 1),
+                content.to_string(),
+                format!("notes/{}.md", n + 1),
+            );
+            document.id = Uuid::from_u128(n as u128 + 1);
+            engine.add_document(document).await.unwrap();
+        }
+        engine.commit().await;
+
+        let similar = engine.similar(&Uuid::from_u128(1), 3).await.unwrap();
+        let ids: Vec<Uuid> = similar.iter().map(|result| result.document.id).collect();
+        assert!(!ids.contains(&Uuid::from_u128(1)));
+        assert!(!ids.contains(&Uuid::from_u128(5)));
+        let mut best: Vec<Uuid> = ids.iter().take(2).copied().collect();
+        best.sort();
+        assert_eq!(best, [Uuid::from_u128(2), Uuid::from_u128(4)]);
+
+        assert!(engine.similar(&Uuid::from_u128(99), 3).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn skips_or_groups_near_duplicates() {
+        let dir = tempfile::tempdir().unwrap();
+        let page = |n: u128, content: &str| {
+            let mut document = Document::new(
+                "Ownership".to_string(),
+                content.to_string(),
+                format!("https://docs.example/ownership?view={}", n),
+            );
+            document.id = Uuid::from_u128(n);
+            document
+        };
+        let original = "Ownership is a set of rules that govern how a Rust program manages \
+                        memory while it runs without a garbage collector";
+        let print_view = format!("{} print", original);
+        let engine = |mode: DuplicateMode| {
+            let path = dir.path().join(format!("{:?}.json", mode));
+            let mut settings = IndexSettings::default();
+            settings.duplicates.mode = mode;
+            SearchEngine::with_settings(
+                Arc::new(JsonStorage::new(path.to_string_lossy().to_string())),
+                settings,
+            )
+        };
+
+        let skipping = engine(DuplicateMode::Skip);
+        assert!(
+            skipping
+                .add_document(page(1, original))
+                .await
+                .unwrap()
+                .is_some()
+        );
+        assert!(
+            skipping
+                .add_document(page(2, &print_view))
+                .await
+                .unwrap()
+                .is_none()
+        );
+        skipping.commit().await;
+        assert_eq!(skipping.get_stats().await.0, 1);
+
+        let grouping = engine(DuplicateMode::Group);
+        grouping.add_document(page(1, original)).await.unwrap();
+        grouping.add_document(page(2, &print_view)).await.unwrap();
+        grouping
+            .add_document(page(3, "Async runtimes schedule futures on worker threads"))
+            .await
+            .unwrap();
+        grouping.commit().await;
+
+        let (canonical, duplicates) = grouping.duplicates(&Uuid::from_u128(2)).await.unwrap();
+        assert_eq!(canonical.id, Uuid::from_u128(1));
+        assert_eq!(duplicates.len(), 1);
+        assert_eq!(duplicates[0].id, Uuid::from_u128(2));
+
+        // Both copies are found unless duplicates are collapsed
+        let results = grouping.search("rust memory", None).await.unwrap();
+        assert_eq!(results.len(), 2);
+        assert!(results.iter().all(|result| result.duplicates == 1));
+        let options = SearchOptions::new().with_collapse_duplicates(true);
+        let collapsed = grouping
+            .search_with_options("rust memory", &options)
+            .await
+            .unwrap();
+        assert_eq!(collapsed.results.len(), 1);
+        assert_eq!(collapsed.results[0].document.id, Uuid::from_u128(1));
+    }
+
+    #[tokio::test]
+    async fn ranks_long_documents_by_their_best_passage() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("index.json");
+        let mut settings = IndexSettings::default();
+        settings.passages.min_words = 30;
+        settings.passages.window = 12;
+        settings.passages.overlap = 2;
+        let engine = SearchEngine::with_settings(
+            Arc::new(JsonStorage::new(path.to_string_lossy().to_string())),
+            settings,
+        );
+
+        let filler = |word: &str, count: usize| vec![word; count].join(" ");
+        let content = format!(
+            "{} {} {} the linker fails with a segfault {}",
+            filler("intro", 12),
+            filler("install", 12),
+            filler("steps", 4),
+            filler("debug", 4),
+        );
+        let section = |heading: &str, anchor: &str, start: usize| Section {
+            heading: heading.to_string(),
+            anchor: Some(anchor.to_string()),
+            start,
+        };
+        let mut guide = Document::new("Guide".to_string(), content, "guide.md".to_string())
+            .with_sections(vec![
+                section("Installation", "installation", 12),
+                section("Troubleshooting", "troubleshooting", 24),
+            ]);
+        guide.id = Uuid::from_u128(1);
+        let mut notes = Document::new(
+            "Notes".to_string(),
+            format!("{} the linker {}", filler("notes", 10), filler("notes", 10)),
+            "notes.md".to_string(),
+        );
+        notes.id = Uuid::from_u128(2);
+        engine.add_document(guide).await.unwrap();
+        engine.add_document(notes).await.unwrap();
+        engine.commit().await;
+
+        let results = engine.search("linker segfault", None).await.unwrap();
+        assert_eq!(results[0].document.id, Uuid::from_u128(1));
+        let passage = results[0].passage.as_ref().unwrap();
+        assert_eq!(passage.heading.as_deref(), Some("Troubleshooting"));
+        assert_eq!(results[0].url(), "guide.md#troubleshooting");
+        assert!(results[0].snippet.contains("segfault"));
+
+        // Only the passage is weighed against the short document, not the whole guide
+        let results = engine.search("linker", None).await.unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].document.id, Uuid::from_u128(1));
+        assert!(results[1].passage.is_none());
     }
 }
@@ -0,0 +1,276 @@
+use crate::search::SearchEngine;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/// A query together with graded relevance judgments keyed by document path or URL.
+///
+/// Grades are non-negative integers, `0` meaning not relevant.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Judgment {
+    pub query: String,
+    pub relevant: HashMap<String, u32>,
+}
+
+impl Judgment {
+    /// Reads a JSON array of judgments such as
+    /// `[{"query": "memory safety", "relevant": {"docs/ownership.md": 3, "docs/borrowing.md": 1}}]`.
+    pub fn load(path: impl AsRef<Path>) -> Result<Vec<Judgment>> {
+        let path = path.as_ref();
+        let json = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read judgments file: {:?}", path))?;
+        serde_json::from_str(&json).with_context(|| format!("Invalid judgments file: {:?}", path))
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct Metrics {
+    pub precision: f64,
+    pub recall: f64,
+    pub reciprocal_rank: f64,
+    pub average_precision: f64,
+    pub ndcg: f64,
+}
+
+impl Metrics {
+    /// Computes all metrics at cut-off `k` for a ranked list of paths.
+    pub fn compute(ranked: &[String], judgment: &Judgment, k: usize) -> Self {
+        let ranked = &ranked[..ranked.len().min(k)];
+        let grade = |path: &String| judgment.relevant.get(path).copied().unwrap_or(0);
+        let total_relevant = judgment.relevant.values().filter(|g| **g > 0).count();
+
+        let mut hits = 0;
+        let mut precision_sum = 0.0;
+        let mut reciprocal_rank = 0.0;
+        let mut dcg = 0.0;
+
+        for (i, path) in ranked.iter().enumerate() {
+            let g = grade(path);
+            if g == 0 {
+                continue;
+            }
+
+            hits += 1;
+            precision_sum += hits as f64 / (i + 1) as f64;
+            if reciprocal_rank == 0.0 {
+                reciprocal_rank = 1.0 / (i + 1) as f64;
+            }
+            dcg += Self::gain(g, i);
+        }
+
+        let mut ideal: Vec<u32> = judgment.relevant.values().copied().collect();
+        ideal.sort_unstable_by(|a, b| b.cmp(a));
+        let ideal_dcg: f64 = ideal
+            .into_iter()
+            .take(k)
+            .enumerate()
+            .map(|(i, g)| Self::gain(g, i))
+            .sum();
+
+        Self {
+            precision: if k > 0 { hits as f64 / k as f64 } else { 0.0 },
+            recall: if total_relevant > 0 {
+                hits as f64 / total_relevant as f64
+            } else {
+                0.0
+            },
+            reciprocal_rank,
+            average_precision: if total_relevant.min(k) > 0 {
+                precision_sum / total_relevant.min(k) as f64
+            } else {
+                0.0
+            },
+            ndcg: if ideal_dcg > 0.0 {
+                dcg / ideal_dcg
+            } else {
+                0.0
+            },
+        }
+    }
+
+    fn mean(metrics: &[Metrics]) -> Self {
+        if metrics.is_empty() {
+            return Self::default();
+        }
+
+        let n = metrics.len() as f64;
+        let sum = |f: fn(&Metrics) -> f64| metrics.iter().map(f).sum::<f64>() / n;
+
+        Self {
+            precision: sum(|m| m.precision),
+            recall: sum(|m| m.recall),
+            reciprocal_rank: sum(|m| m.reciprocal_rank),
+            average_precision: sum(|m| m.average_precision),
+            ndcg: sum(|m| m.ndcg),
+        }
+    }
+
+    /// Difference `self - baseline` for every metric.
+    pub fn delta(&self, baseline: &Metrics) -> Metrics {
+        Metrics {
+            precision: self.precision - baseline.precision,
+            recall: self.recall - baseline.recall,
+            reciprocal_rank: self.reciprocal_rank - baseline.reciprocal_rank,
+            average_precision: self.average_precision - baseline.average_precision,
+            ndcg: self.ndcg - baseline.ndcg,
+        }
+    }
+
+    /// Discounted gain `(2^grade - 1) / log2(position + 2)` for a zero-based position.
+    fn gain(grade: u32, position: usize) -> f64 {
+        (2f64.powi(grade as i32) - 1.0) / ((position + 2) as f64).log2()
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct QueryEvaluation {
+    pub query: String,
+    pub metrics: Metrics,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EvaluationReport {
+    pub k: usize,
+    pub queries: Vec<QueryEvaluation>,
+    pub mean: Metrics,
+}
+
+pub struct Evaluator;
+
+impl Evaluator {
+    /// Runs every judged query through `SearchEngine::search` and scores the top `k` results.
+    pub async fn evaluate(
+        engine: &SearchEngine,
+        judgments: &[Judgment],
+        k: usize,
+    ) -> Result<EvaluationReport> {
+        let mut queries = Vec::with_capacity(judgments.len());
+
+        for judgment in judgments {
+            let ranked: Vec<String> = engine
+                .search(&judgment.query, Some(k))
+                .await?
+                .into_iter()
+                .map(|result| result.document.path)
+                .collect();
+
+            queries.push(QueryEvaluation {
+                query: judgment.query.clone(),
+                metrics: Metrics::compute(&ranked, judgment, k),
+            });
+        }
+
+        let per_query: Vec<Metrics> = queries.iter().map(|q| q.metrics).collect();
+
+        Ok(EvaluationReport {
+            k,
+            mean: Metrics::mean(&per_query),
+            queries,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn judgment() -> Judgment {
+        Judgment {
+            query: "memory safety".to_string(),
+            relevant: HashMap::from([
+                ("a.md".to_string(), 3),
+                ("b.md".to_string(), 1),
+                ("c.md".to_string(), 2),
+                ("d.md".to_string(), 0),
+            ]),
+        }
+    }
+
+    fn ranked(paths: &[&str]) -> Vec<String> {
+        paths.iter().map(|path| path.to_string()).collect()
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-12,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn computes_metrics_at_k() {
+        // Relevant documents at ranks 2 and 4, the one at rank 3 is judged not relevant
+        let metrics = Metrics::compute(&ranked(&["x.md", "a.md", "d.md", "b.md"]), &judgment(), 4);
+        assert_close(metrics.precision, 2.0 / 4.0);
+        assert_close(metrics.recall, 2.0 / 3.0);
+        assert_close(metrics.reciprocal_rank, 1.0 / 2.0);
+        assert_close(metrics.average_precision, (1.0 / 2.0 + 2.0 / 4.0) / 3.0);
+        let dcg = 7.0 / 3f64.log2() + 1.0 / 5f64.log2();
+        let ideal_dcg = 7.0 + 3.0 / 3f64.log2() + 1.0 / 4f64.log2();
+        assert_close(metrics.ndcg, dcg / ideal_dcg);
+
+        // Only the first two ranks count at k = 2
+        let metrics = Metrics::compute(&ranked(&["x.md", "a.md", "d.md", "b.md"]), &judgment(), 2);
+        assert_close(metrics.precision, 1.0 / 2.0);
+        assert_close(metrics.recall, 1.0 / 3.0);
+        assert_close(metrics.average_precision, (1.0 / 2.0) / 2.0);
+        assert_close(
+            metrics.ndcg,
+            (7.0 / 3f64.log2()) / (7.0 + 3.0 / 3f64.log2()),
+        );
+    }
+
+    #[test]
+    fn ideal_ranking_scores_one() {
+        let metrics = Metrics::compute(&ranked(&["a.md", "c.md", "b.md"]), &judgment(), 3);
+        for value in [
+            metrics.precision,
+            metrics.recall,
+            metrics.reciprocal_rank,
+            metrics.average_precision,
+            metrics.ndcg,
+        ] {
+            assert_close(value, 1.0);
+        }
+    }
+
+    #[test]
+    fn misses_score_zero() {
+        let judgment = judgment();
+        for (ranked, k) in [(ranked(&["x.md", "d.md"]), 10), (ranked(&["a.md"]), 0)] {
+            let metrics = Metrics::compute(&ranked, &judgment, k);
+            assert_eq!(metrics.precision, 0.0);
+            assert_eq!(metrics.recall, 0.0);
+            assert_eq!(metrics.reciprocal_rank, 0.0);
+            assert_eq!(metrics.average_precision, 0.0);
+            assert_eq!(metrics.ndcg, 0.0);
+        }
+
+        let unjudged = Judgment {
+            query: "nothing".to_string(),
+            relevant: HashMap::new(),
+        };
+        let metrics = Metrics::compute(&ranked(&["a.md"]), &unjudged, 10);
+        assert_eq!((metrics.recall, metrics.ndcg), (0.0, 0.0));
+    }
+
+    #[test]
+    fn averages_and_compares_metrics() {
+        let judgment = judgment();
+        let good = Metrics::compute(&ranked(&["a.md", "c.md", "b.md"]), &judgment, 3);
+        let bad = Metrics::compute(&ranked(&["x.md", "y.md", "b.md"]), &judgment, 3);
+
+        let mean = Metrics::mean(&[good, bad]);
+        assert_close(mean.precision, (1.0 + 1.0 / 3.0) / 2.0);
+        assert_close(mean.reciprocal_rank, (1.0 + 1.0 / 3.0) / 2.0);
+        assert_eq!(Metrics::mean(&[]).ndcg, 0.0);
+
+        let delta = bad.delta(&good);
+        assert_close(delta.recall, 1.0 / 3.0 - 1.0);
+        assert_close(delta.ndcg, bad.ndcg - 1.0);
+    }
+}
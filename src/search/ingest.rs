@@ -0,0 +1,79 @@
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Callback receiving the progress of an ingestion.
+pub type ProgressCallback = Arc<dyn Fn(&IngestProgress) + Send + Sync>;
+
+/// How crawled documents are fed into the index while a crawl is running.
+#[derive(Clone)]
+pub struct IngestOptions {
+    /// Documents indexed before the next generation is committed
+    pub batch_size: usize,
+    /// Save the index after every commit, so an interrupted crawl keeps what it indexed
+    pub save: bool,
+    /// Called when ingestion starts, while pages arrive at most once per `progress_interval`,
+    /// and after every commit
+    pub on_progress: Option<ProgressCallback>,
+    pub progress_interval: Duration,
+}
+
+impl IngestOptions {
+    pub fn new() -> Self {
+        Self {
+            batch_size: 1000,
+            save: true,
+            on_progress: None,
+            progress_interval: Duration::from_secs(1),
+        }
+    }
+
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    pub fn with_save(mut self, save: bool) -> Self {
+        self.save = save;
+        self
+    }
+
+    pub fn with_progress(
+        mut self,
+        on_progress: impl Fn(&IngestProgress) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_progress = Some(Arc::new(on_progress));
+        self
+    }
+
+    pub fn with_progress_interval(mut self, progress_interval: Duration) -> Self {
+        self.progress_interval = progress_interval;
+        self
+    }
+}
+
+impl Default for IngestOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Counters of a running ingestion, updated with every page received.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct IngestProgress {
+    /// Documents received from the crawler
+    pub crawled: usize,
+    /// Documents added to the index as of the last commit
+    pub indexed: usize,
+    /// Documents skipped as near-duplicates as of the last commit
+    pub skipped: usize,
+    /// Documents with the id of one received earlier or already indexed, which they replace
+    pub replaced: usize,
+    /// Batches committed so far
+    pub batches: usize,
+    /// Generation of the last commit
+    pub generation: u64,
+    pub elapsed_secs: f64,
+    /// Documents crawled per second since the ingestion started
+    pub docs_per_sec: f64,
+}
@@ -1,3 +1,13 @@
+pub mod bulk;
+pub mod catalog;
 pub mod engine;
+pub mod evaluation;
+pub mod ingest;
+pub mod snapshot;
 
-pub use engine::SearchEngine;
+pub use bulk::DocumentRecord;
+pub use catalog::{IndexCatalog, IndexSummary};
+pub use engine::{Generation, SearchEngine};
+pub use evaluation::{EvaluationReport, Evaluator, Judgment, Metrics};
+pub use ingest::{IngestOptions, IngestProgress, ProgressCallback};
+pub use snapshot::{SnapshotInfo, SnapshotStore};
@@ -0,0 +1,220 @@
+use crate::core::InvertedIndex;
+use crate::storage::{FormatHeader, Storage};
+use anyhow::{Context, Result, anyhow};
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Describes a snapshot, stored as the first line of its archive.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnapshotInfo {
+    pub name: String,
+    /// Index the snapshot was taken of
+    pub index: String,
+    /// Unix seconds
+    pub created: i64,
+    pub shards: usize,
+    pub documents: usize,
+    pub format: FormatHeader,
+    /// Size of the archive in bytes, only known once it is written
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+}
+
+/// Snapshot archives of indexes, kept as `<name>.snapshot` in one directory.
+///
+/// An archive is gzip-compressed JSON lines: the `SnapshotInfo`, then every shard as a
+/// whole `InvertedIndex` with its postings, documents, settings and link graph. It does not
+/// depend on the storage it was taken from, so it can be restored into any `Storage`.
+pub struct SnapshotStore {
+    directory: PathBuf,
+}
+
+impl SnapshotStore {
+    const EXTENSION: &'static str = "snapshot";
+
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        Self {
+            directory: directory.into(),
+        }
+    }
+
+    /// Writes the shards of `index` as the snapshot `name`. The archive only appears once it
+    /// is complete, so a failed snapshot never looks like a valid one.
+    pub fn create(
+        &self,
+        name: &str,
+        index: &str,
+        shards: &[Arc<InvertedIndex>],
+    ) -> Result<SnapshotInfo> {
+        let path = self.path(name)?;
+        if path.exists() {
+            return Err(anyhow!("Snapshot '{}' already exists", name));
+        }
+        fs::create_dir_all(&self.directory)
+            .with_context(|| format!("Failed to create directory: {:?}", self.directory))?;
+
+        let mut info = SnapshotInfo {
+            name: name.to_string(),
+            index: index.to_string(),
+            created: chrono::Utc::now().timestamp(),
+            shards: shards.len(),
+            documents: shards.iter().map(|shard| shard.total_documents).sum(),
+            format: FormatHeader::current(),
+            size: None,
+        };
+
+        let temp_path = path.with_extension("tmp");
+        let written = Self::write(&temp_path, &info, shards);
+        if let Err(e) = written {
+            let _ = fs::remove_file(&temp_path);
+            return Err(e.context(format!("Failed to write snapshot '{}'", name)));
+        }
+        fs::rename(&temp_path, &path)
+            .with_context(|| format!("Failed to store snapshot '{}'", name))?;
+
+        info.size = fs::metadata(&path).ok().map(|metadata| metadata.len());
+        log::info!(
+            "Created snapshot '{}' of index '{}' with {} documents",
+            name,
+            index,
+            info.documents
+        );
+        Ok(info)
+    }
+
+    fn write(path: &Path, info: &SnapshotInfo, shards: &[Arc<InvertedIndex>]) -> Result<()> {
+        let file = File::create(path).with_context(|| format!("Failed to create {:?}", path))?;
+        let mut archive = GzEncoder::new(BufWriter::new(file), Compression::default());
+        serde_json::to_writer(&mut archive, info).context("Failed to serialize snapshot")?;
+        archive.write_all(b"\n")?;
+        for shard in shards {
+            serde_json::to_writer(&mut archive, &**shard).context("Failed to serialize index")?;
+            archive.write_all(b"\n")?;
+        }
+
+        let file = archive.finish()?.into_inner().map_err(|e| e.into_error())?;
+        file.sync_all().context("Failed to sync snapshot")?;
+        Ok(())
+    }
+
+    /// Every readable snapshot, oldest first.
+    pub fn list(&self) -> Result<Vec<SnapshotInfo>> {
+        if !self.directory.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut snapshots = Vec::new();
+        let entries = fs::read_dir(&self.directory)
+            .with_context(|| format!("Failed to read directory: {:?}", self.directory))?;
+        for entry in entries {
+            let path = entry.context("Failed to read snapshot directory")?.path();
+            if path.extension().is_none_or(|ext| ext != Self::EXTENSION) {
+                continue;
+            }
+            match Self::read_info(&path) {
+                Ok((info, _)) => snapshots.push(info),
+                Err(e) => log::warn!("Skipping unreadable snapshot {:?}: {:#}", path, e),
+            }
+        }
+        snapshots.sort_by(|a, b| a.created.cmp(&b.created).then(a.name.cmp(&b.name)));
+        Ok(snapshots)
+    }
+
+    /// The snapshot's description and its shards.
+    pub fn read(&self, name: &str) -> Result<(SnapshotInfo, Vec<InvertedIndex>)> {
+        let (info, archive) = Self::read_info(&self.existing(name)?)?;
+        if info.shards == 0 {
+            return Err(anyhow!("Snapshot '{}' holds no shards", name));
+        }
+        let shards = serde_json::Deserializer::from_reader(archive)
+            .into_iter::<InvertedIndex>()
+            .take(info.shards)
+            .collect::<Result<Vec<_>, _>>()
+            .with_context(|| format!("Snapshot '{}' is corrupt", name))?;
+        if shards.len() != info.shards {
+            return Err(anyhow!(
+                "Snapshot '{}' is truncated: expected {} shards, found {}",
+                name,
+                info.shards,
+                shards.len()
+            ));
+        }
+        Ok((info, shards))
+    }
+
+    /// Saves the snapshot's shards into `storages`, one per shard, replacing what they held.
+    /// Used to restore into a storage no engine is serving, e.g. a JSON file.
+    pub fn restore_into(&self, name: &str, storages: &[Arc<dyn Storage>]) -> Result<SnapshotInfo> {
+        let (info, shards) = self.read(name)?;
+        if shards.len() != storages.len() {
+            return Err(anyhow!(
+                "Snapshot '{}' has {} shards, but there are {} storages to restore into",
+                name,
+                shards.len(),
+                storages.len()
+            ));
+        }
+        for (storage, index) in storages.iter().zip(&shards) {
+            storage
+                .save_index(index)
+                .with_context(|| format!("Failed to restore snapshot '{}'", name))?;
+        }
+        Ok(info)
+    }
+
+    pub fn delete(&self, name: &str) -> Result<()> {
+        fs::remove_file(self.existing(name)?)
+            .with_context(|| format!("Failed to delete snapshot '{}'", name))?;
+        log::info!("Deleted snapshot '{}'", name);
+        Ok(())
+    }
+
+    /// Reads the description of an archive and returns the rest of it for the shards.
+    fn read_info(path: &Path) -> Result<(SnapshotInfo, impl BufRead + use<>)> {
+        let file = File::open(path).with_context(|| format!("Failed to open {:?}", path))?;
+        let size = file.metadata().ok().map(|metadata| metadata.len());
+        let mut archive = BufReader::new(GzDecoder::new(BufReader::new(file)));
+        let mut line = String::new();
+        archive
+            .read_line(&mut line)
+            .with_context(|| format!("Failed to read snapshot {:?}", path))?;
+        let mut info: SnapshotInfo =
+            serde_json::from_str(&line).with_context(|| format!("Not a snapshot: {:?}", path))?;
+        info.format
+            .version()
+            .with_context(|| format!("Cannot read snapshot '{}'", info.name))?;
+        info.size = size;
+        Ok((info, archive))
+    }
+
+    fn existing(&self, name: &str) -> Result<PathBuf> {
+        let path = self.path(name)?;
+        if path.exists() {
+            Ok(path)
+        } else {
+            Err(anyhow!("No snapshot named '{}'", name))
+        }
+    }
+
+    /// Names are used as file names: lowercase letters, digits, `-` and `_`.
+    fn path(&self, name: &str) -> Result<PathBuf> {
+        let valid = !name.is_empty()
+            && name.len() <= 128
+            && name
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_');
+        if !valid {
+            return Err(anyhow!(
+                "Invalid snapshot name '{}', use up to 128 lowercase letters, digits, '-' and '_'",
+                name
+            ));
+        }
+        Ok(self.directory.join(format!("{}.{}", name, Self::EXTENSION)))
+    }
+}
@@ -0,0 +1,94 @@
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+
+/// Marks data as an index stored by this crate.
+pub const MAGIC: &str = "mini-search-engine/index";
+
+/// Version of the stored index format, raised with every change to how `InvertedIndex`,
+/// `PostingList` or `Document` are stored, together with a migration from the previous one.
+///
+/// - 0: one serialized index without header
+/// - 1: JSON files with a checksum header; sled with terms, postings and documents under keys
+///   of their own
+/// - 2: a format header with magic, version and build in every stored index
+/// - 3: sled with the per-document and per-page data under keys of their own, only counters
+///   and settings under the meta key
+pub const FORMAT_VERSION: u32 = 3;
+
+/// Identifies the format of a stored index and the build that wrote it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FormatHeader {
+    pub magic: String,
+    pub version: u32,
+    /// Package name and version of the writer
+    pub build: String,
+}
+
+impl FormatHeader {
+    pub fn current() -> Self {
+        Self {
+            magic: MAGIC.to_string(),
+            version: FORMAT_VERSION,
+            build: Self::build(),
+        }
+    }
+
+    /// The format version, checked to be readable by this build.
+    pub fn version(&self) -> Result<u32> {
+        if self.magic != MAGIC {
+            return Err(anyhow!(
+                "Not a stored index: expected magic '{}', found '{}'",
+                MAGIC,
+                self.magic
+            ));
+        }
+        if self.version > FORMAT_VERSION {
+            return Err(anyhow!(
+                "Index is stored in format version {} by {}, but {} only reads versions up to {}; \
+                 upgrade the search engine to open it",
+                self.version,
+                self.build,
+                Self::build(),
+                FORMAT_VERSION
+            ));
+        }
+        Ok(self.version)
+    }
+
+    fn build() -> String {
+        format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"))
+    }
+}
+
+/// Upgrades stored data of format version `from` to version `to`.
+pub struct Migration<T: ?Sized> {
+    pub from: u32,
+    pub to: u32,
+    pub description: &'static str,
+    pub apply: fn(&T) -> Result<()>,
+}
+
+/// Applies `migrations` one after another to bring data stored in format `version` up to
+/// `FORMAT_VERSION`.
+pub fn migrate<T: ?Sized>(target: &T, mut version: u32, migrations: &[Migration<T>]) -> Result<()> {
+    while version < FORMAT_VERSION {
+        let migration = migrations
+            .iter()
+            .find(|migration| migration.from == version)
+            .ok_or_else(|| anyhow!("No migration from index format version {}", version))?;
+        log::info!(
+            "Migrating index from format version {} to {}: {}",
+            migration.from,
+            migration.to,
+            migration.description
+        );
+        (migration.apply)(target).map_err(|e| {
+            e.context(format!(
+                "Failed to migrate index from format version {} to {}",
+                migration.from, migration.to
+            ))
+        })?;
+        version = migration.to;
+    }
+    Ok(())
+}
@@ -1,16 +1,86 @@
 use crate::core::InvertedIndex;
 use crate::storage::Storage;
-use anyhow::{Context, Result};
-use std::fs;
+use crate::storage::format::{self, FORMAT_VERSION, FormatHeader, Migration};
+use crate::storage::legacy::{IndexV0, IndexV1};
+use anyhow::{Context, Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Write};
 use std::path::Path;
 
+/// First line of an index file, followed by the index as JSON.
+#[derive(Debug, Serialize, Deserialize)]
+struct FileHeader {
+    /// CRC-32 of the index JSON
+    checksum: u32,
+    /// Length of the index JSON in bytes
+    length: usize,
+    /// Missing in files of format version 1
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    format: Option<FormatHeader>,
+}
+
+impl FileHeader {
+    fn version(&self) -> Result<u32> {
+        match &self.format {
+            Some(format) => format.version(),
+            None => Ok(1),
+        }
+    }
+}
+
+/// Stores the index as a JSON file. Saves write a temporary file, sync it and rename it over
+/// the previous one, so a crash leaves either the old or the new index but never a partial
+/// one. A checksum catches files damaged anyway.
 pub struct JsonStorage {
     file_path: String,
+    backup: bool,
 }
 
 impl JsonStorage {
+    /// Files of older versions are converted while reading, so upgrading only rewrites the
+    /// file in the current format.
+    const MIGRATIONS: &'static [Migration<JsonStorage>] = &[
+        Migration {
+            from: 0,
+            to: 3,
+            description: "add a header with checksum and format version",
+            apply: Self::rewrite,
+        },
+        Migration {
+            from: 1,
+            to: 3,
+            description: "add the format version to the header",
+            apply: Self::rewrite,
+        },
+        Migration {
+            from: 2,
+            to: 3,
+            description: "record format version 3, which only changed sled storage",
+            apply: Self::rewrite,
+        },
+    ];
+
     pub fn new(file_path: String) -> Self {
-        Self { file_path }
+        Self {
+            file_path,
+            backup: false,
+        }
+    }
+
+    /// Keeps the previously saved index next to the file as `<file>.bak`. Loading falls back
+    /// to it when the current file is missing or corrupt.
+    pub fn with_backup(mut self, backup: bool) -> Self {
+        self.backup = backup;
+        self
+    }
+
+    fn backup_path(&self) -> String {
+        format!("{}.bak", self.file_path)
+    }
+
+    fn temp_path(&self) -> String {
+        format!("{}.tmp", self.file_path)
     }
 
     fn ensure_directory_exists(&self) -> Result<()> {
@@ -20,37 +90,305 @@ impl JsonStorage {
         }
         Ok(())
     }
+
+    /// Writes `parts` to a temporary file, syncs it and renames it to the index file,
+    /// rotating the previous file to the backup if enabled.
+    fn write_atomically(&self, parts: &[&[u8]]) -> Result<()> {
+        let temp_path = self.temp_path();
+        let mut file = File::create(&temp_path)
+            .with_context(|| format!("Failed to create temporary file: {}", temp_path))?;
+        for part in parts {
+            file.write_all(part)
+                .with_context(|| format!("Failed to write temporary file: {}", temp_path))?;
+        }
+        file.sync_all()
+            .with_context(|| format!("Failed to sync temporary file: {}", temp_path))?;
+        drop(file);
+
+        if self.backup && Path::new(&self.file_path).exists() {
+            fs::rename(&self.file_path, self.backup_path())
+                .with_context(|| format!("Failed to keep previous copy of {}", self.file_path))?;
+        }
+        fs::rename(&temp_path, &self.file_path)
+            .with_context(|| format!("Failed to replace index file: {}", self.file_path))?;
+
+        // Make the renames durable too; directories cannot be opened on every platform
+        let directory = match Path::new(&self.file_path).parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        if let Ok(directory) = File::open(directory) {
+            let _ = directory.sync_all();
+        }
+        Ok(())
+    }
+
+    /// The index of a file and the format version it is stored in.
+    fn read(path: &str) -> Result<(u32, InvertedIndex)> {
+        let data =
+            fs::read(path).with_context(|| format!("Failed to read index from file: {}", path))?;
+        let (header, json) = Self::split(&data);
+        let version = match &header {
+            Some(header) => header
+                .version()
+                .with_context(|| format!("Cannot read index file {}", path))?,
+            None => 0,
+        };
+        if let Some(header) = &header {
+            Self::verify(header, json)
+                .with_context(|| format!("Index file {} is corrupt", path))?;
+        }
+        let index = match version {
+            0 => serde_json::from_slice::<IndexV0>(json).map(InvertedIndex::from),
+            1 => serde_json::from_slice::<IndexV1>(json).map(InvertedIndex::from),
+            _ => serde_json::from_slice(json),
+        }
+        .with_context(|| format!("Failed to deserialize index from JSON: {}", path))?;
+        Ok((version, index))
+    }
+
+    /// The format version of a file, read from its header line alone.
+    fn read_version(path: &str) -> Result<u32> {
+        let file = File::open(path)
+            .with_context(|| format!("Failed to read index from file: {}", path))?;
+        let mut line = Vec::new();
+        BufReader::new(file)
+            .read_until(b'\n', &mut line)
+            .with_context(|| format!("Failed to read index from file: {}", path))?;
+        match serde_json::from_slice::<FileHeader>(&line) {
+            Ok(header) => header
+                .version()
+                .with_context(|| format!("Cannot read index file {}", path)),
+            Err(_) => Ok(0),
+        }
+    }
+
+    /// Splits a file into its header and the index JSON. Files written before headers were
+    /// added have none.
+    fn split(data: &[u8]) -> (Option<FileHeader>, &[u8]) {
+        if let Some(end) = data.iter().position(|byte| *byte == b'\n')
+            && let Ok(header) = serde_json::from_slice(&data[..end])
+        {
+            return (Some(header), &data[end + 1..]);
+        }
+        (None, data)
+    }
+
+    /// Checks the index JSON against the length and checksum in its header.
+    fn verify(header: &FileHeader, json: &[u8]) -> Result<()> {
+        if json.len() != header.length {
+            return Err(anyhow!(
+                "expected {} bytes of index data, found {}; the file was truncated",
+                header.length,
+                json.len()
+            ));
+        }
+        let checksum = crc32fast::hash(json);
+        if checksum != header.checksum {
+            return Err(anyhow!(
+                "checksum mismatch, expected {:08x} but the data has {:08x}",
+                header.checksum,
+                checksum
+            ));
+        }
+        Ok(())
+    }
+
+    /// The stored index with its format version, falling back to the backup when the file
+    /// is missing or corrupt.
+    fn load_stored(&self) -> Result<Option<(u32, InvertedIndex)>> {
+        let backup_path = self.backup_path();
+        let has_backup = Path::new(&backup_path).exists();
+
+        if !Path::new(&self.file_path).exists() {
+            if !has_backup {
+                return Ok(None);
+            }
+            log::warn!(
+                "Index file {} is missing, loading the previous copy {}",
+                self.file_path,
+                backup_path
+            );
+            return Self::read(&backup_path).map(Some);
+        }
+
+        // A newer format is not damage, so it must not fall back to an older copy
+        Self::read_version(&self.file_path)?;
+        match Self::read(&self.file_path) {
+            Ok(stored) => Ok(Some(stored)),
+            Err(e) if has_backup => {
+                log::warn!("{:#}; loading the previous copy {}", e, backup_path);
+                Self::read(&backup_path).map(Some).with_context(|| {
+                    format!(
+                        "Index file {} is unreadable ({:#}) and so is its previous copy",
+                        self.file_path, e
+                    )
+                })
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    fn rewrite(&self) -> Result<()> {
+        match self.load_stored()? {
+            Some((_, index)) => self.save_index(&index),
+            None => Ok(()),
+        }
+    }
 }
 
 impl Storage for JsonStorage {
     fn save_index(&self, index: &InvertedIndex) -> Result<()> {
         self.ensure_directory_exists()?;
 
-        let json =
-            serde_json::to_string_pretty(index).context("Failed to serialize index to JSON")?;
+        let json = serde_json::to_vec_pretty(index).context("Failed to serialize index to JSON")?;
+        let mut header = serde_json::to_vec(&FileHeader {
+            checksum: crc32fast::hash(&json),
+            length: json.len(),
+            format: Some(FormatHeader::current()),
+        })
+        .context("Failed to serialize index header")?;
+        header.push(b'\n');
 
-        fs::write(&self.file_path, json)
+        self.write_atomically(&[&header, &json])
             .with_context(|| format!("Failed to write index to file: {}", self.file_path))
     }
 
     fn load_index(&self) -> Result<Option<InvertedIndex>> {
-        if !Path::new(&self.file_path).exists() {
+        let Some((version, index)) = self.load_stored()? else {
             return Ok(None);
+        };
+        if version < FORMAT_VERSION {
+            format::migrate(self, version, Self::MIGRATIONS)
+                .with_context(|| format!("Failed to upgrade index file {}", self.file_path))?;
         }
+        Ok(Some(index))
+    }
 
-        let json = fs::read_to_string(&self.file_path)
-            .with_context(|| format!("Failed to read index from file: {}", self.file_path))?;
-
-        let index = serde_json::from_str(&json).context("Failed to deserialize index from JSON")?;
+    fn format_version(&self) -> Result<Option<u32>> {
+        for path in [self.file_path.clone(), self.backup_path()] {
+            if Path::new(&path).exists() {
+                return Self::read_version(&path).map(Some);
+            }
+        }
+        Ok(None)
+    }
 
-        Ok(Some(index))
+    fn migrate(&self) -> Result<Option<u32>> {
+        let Some(version) = self.format_version()? else {
+            return Ok(None);
+        };
+        format::migrate(self, version, Self::MIGRATIONS)
+            .with_context(|| format!("Failed to upgrade index file {}", self.file_path))?;
+        Ok(Some(version))
     }
 
     fn clear(&self) -> Result<()> {
-        if Path::new(&self.file_path).exists() {
-            fs::remove_file(&self.file_path)
-                .with_context(|| format!("Failed to remove index file: {}", self.file_path))?;
+        for path in [self.file_path.clone(), self.backup_path(), self.temp_path()] {
+            if Path::new(&path).exists() {
+                fs::remove_file(&path)
+                    .with_context(|| format!("Failed to remove index file: {}", path))?;
+            }
         }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::Document;
+    use crate::storage::legacy::IndexV0;
+
+    const BASELINE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/examples/search_index.json");
+
+    #[test]
+    fn migrates_baseline_index_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("search_index.json");
+        fs::copy(BASELINE, &path).unwrap();
+        let storage = JsonStorage::new(path.to_string_lossy().to_string());
+        assert_eq!(storage.format_version().unwrap(), Some(0));
+
+        let index = storage.load_index().unwrap().unwrap();
+        let legacy: IndexV0 = serde_json::from_slice(&fs::read(BASELINE).unwrap()).unwrap();
+        assert_eq!(index.total_documents, legacy.total_documents);
+        assert_eq!(index.doc_lengths.len(), legacy.documents.len());
+        assert!(index.total_tokens > 0);
+        for (term, posting_list) in &legacy.index {
+            assert_eq!(
+                index.index.get(term).map(|p| p.term_frequency.clone()),
+                Some(posting_list.term_frequency.clone().into()),
+                "postings of '{}'",
+                term
+            );
+        }
+        for doc_id in legacy.documents.keys() {
+            assert!(index.doc_values.get("path", doc_id).is_some());
+        }
+
+        assert_eq!(storage.format_version().unwrap(), Some(FORMAT_VERSION));
+        let reloaded = storage.load_index().unwrap().unwrap();
+        assert_eq!(reloaded.total_documents, index.total_documents);
+        assert_eq!(reloaded.total_tokens, index.total_tokens);
+    }
+
+    #[test]
+    fn migrates_version_1_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("search_index.json");
+        fs::copy(BASELINE, &path).unwrap();
+        let storage = JsonStorage::new(path.to_string_lossy().to_string());
+        let index = storage.load_index().unwrap().unwrap();
+
+        // Version 1 headers had no format
+        let data = fs::read(&path).unwrap();
+        let (_, json) = JsonStorage::split(&data);
+        let header = serde_json::json!({ "checksum": crc32fast::hash(json), "length": json.len() });
+        fs::write(&path, [header.to_string().as_bytes(), b"\n", json].concat()).unwrap();
+        assert_eq!(storage.format_version().unwrap(), Some(1));
+
+        assert_eq!(storage.migrate().unwrap(), Some(1));
+        assert_eq!(storage.format_version().unwrap(), Some(FORMAT_VERSION));
+        let migrated = storage.load_index().unwrap().unwrap();
+        assert_eq!(migrated.total_documents, index.total_documents);
+        assert_eq!(migrated.doc_lengths, index.doc_lengths);
+    }
+
+    #[test]
+    fn falls_back_to_the_previous_copy_when_the_checksum_fails() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("index.json").to_string_lossy().to_string();
+        let storage = JsonStorage::new(path.clone()).with_backup(true);
+        let mut index = InvertedIndex::new();
+        for (title, content) in [("First", "first save"), ("Second", "second save")] {
+            index
+                .add_document(Document::new(
+                    title.to_string(),
+                    content.to_string(),
+                    format!("{}.md", title),
+                ))
+                .unwrap();
+            storage.save_index(&index).unwrap();
+        }
+        assert!(!Path::new(&storage.temp_path()).exists());
+
+        // Damage one byte of the index JSON without changing its length
+        let mut data = fs::read(&path).unwrap();
+        let position = data.len() - 10;
+        data[position] ^= 0x01;
+        fs::write(&path, &data).unwrap();
+
+        let loaded = storage.load_index().unwrap().unwrap();
+        assert_eq!(loaded.total_documents, 1);
+        assert_eq!(loaded.search("first", None).len(), 1);
+
+        fs::remove_file(storage.backup_path()).unwrap();
+        let error = storage.load_index().err().unwrap();
+        assert!(format!("{:#}", error).contains("checksum mismatch"));
+
+        fs::write(&path, &data[..data.len() / 2]).unwrap();
+        let error = storage.load_index().err().unwrap();
+        assert!(format!("{:#}", error).contains("truncated"));
+    }
+}
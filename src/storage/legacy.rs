@@ -0,0 +1,193 @@
+//! Index layouts of earlier format versions. They are frozen copies rather than the current
+//! types, so later changes to `InvertedIndex` or `Document` cannot break reading old data.
+
+use crate::core::{
+    AnchorIndex, DocValues, Document, DocumentStore, DuplicateIndex, IndexParts, IndexSettings,
+    InvertedIndex, LinkGraph, PassageIndex, PostingList, PostingStore, Section, VectorIndex,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// Format version 0: the whole index as one value, postings and documents only.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct IndexV0 {
+    pub index: HashMap<String, PostingListV0>,
+    pub documents: HashMap<Uuid, DocumentV0>,
+    pub total_documents: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PostingListV0 {
+    pub term_frequency: HashMap<Uuid, usize>,
+    pub document_frequency: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DocumentV0 {
+    pub id: Uuid,
+    pub title: String,
+    pub content: String,
+    pub path: String,
+    pub word_count: usize,
+}
+
+impl From<IndexV0> for InvertedIndex {
+    /// Everything but the documents is derived from them, so the index is rebuilt by adding
+    /// them again. That also fills in doc values, lengths and the other per-document data
+    /// version 0 did not have.
+    fn from(legacy: IndexV0) -> Self {
+        let mut documents: Vec<Document> = legacy
+            .documents
+            .into_values()
+            .map(|legacy| {
+                let mut document = Document::new(legacy.title, legacy.content, legacy.path);
+                document.id = legacy.id;
+                document
+            })
+            .collect();
+        // Which documents group as near-duplicates depends on the order they are added in
+        documents.sort_by(|a, b| a.path.cmp(&b.path).then(a.id.cmp(&b.id)));
+
+        let mut index = InvertedIndex::new();
+        index
+            .add_documents(documents)
+            .expect("an index held in memory loads nothing");
+        if index.total_documents != legacy.total_documents {
+            log::warn!(
+                "Index of format version 0 counted {} documents, {} were converted",
+                legacy.total_documents,
+                index.total_documents
+            );
+        }
+        index
+    }
+}
+
+/// Format version 1: the JSON index body, unchanged in version 2 which only added the format
+/// header and in version 3 which only changed sled storage.
+#[derive(Debug, Deserialize)]
+pub struct IndexV1 {
+    pub index: HashMap<String, PostingList>,
+    pub documents: HashMap<Uuid, DocumentV1>,
+    pub total_documents: usize,
+    pub doc_values: DocValues,
+    pub doc_lengths: HashMap<Uuid, usize>,
+    pub total_tokens: usize,
+    pub settings: IndexSettings,
+    pub link_graph: LinkGraph,
+    pub authority: HashMap<Uuid, f64>,
+    pub anchors: AnchorIndex,
+    pub boosts: HashMap<Uuid, f64>,
+    pub vectors: VectorIndex,
+    pub duplicates: DuplicateIndex,
+    pub passages: PassageIndex,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DocumentV1 {
+    pub id: Uuid,
+    pub title: String,
+    pub content: String,
+    pub path: String,
+    pub word_count: usize,
+    #[serde(default)]
+    pub metadata: HashMap<String, String>,
+    #[serde(default)]
+    pub timestamp: Option<i64>,
+    #[serde(default = "DocumentV1::default_boost")]
+    pub boost: f64,
+    #[serde(default)]
+    pub embedding: Option<Vec<f32>>,
+    #[serde(default)]
+    pub sections: Vec<Section>,
+}
+
+impl DocumentV1 {
+    fn default_boost() -> f64 {
+        1.0
+    }
+}
+
+impl From<DocumentV1> for Document {
+    fn from(legacy: DocumentV1) -> Self {
+        Document {
+            id: legacy.id,
+            title: legacy.title,
+            content: legacy.content,
+            path: legacy.path,
+            word_count: legacy.word_count,
+            metadata: legacy.metadata,
+            timestamp: legacy.timestamp,
+            boost: legacy.boost,
+            embedding: legacy.embedding,
+            sections: legacy.sections,
+        }
+    }
+}
+
+impl From<IndexV1> for InvertedIndex {
+    fn from(legacy: IndexV1) -> Self {
+        let documents: HashMap<Uuid, Document> = legacy
+            .documents
+            .into_iter()
+            .map(|(doc_id, document)| (doc_id, document.into()))
+            .collect();
+        let parts = IndexParts {
+            total_documents: legacy.total_documents,
+            doc_values: legacy.doc_values,
+            doc_lengths: legacy.doc_lengths.into(),
+            total_tokens: legacy.total_tokens,
+            settings: legacy.settings,
+            link_graph: legacy.link_graph,
+            authority: legacy.authority.into(),
+            anchors: legacy.anchors,
+            boosts: legacy.boosts.into(),
+            vectors: legacy.vectors,
+            duplicates: legacy.duplicates,
+            passages: legacy.passages,
+        };
+        InvertedIndex::from_parts(
+            parts,
+            PostingStore::from(legacy.index),
+            DocumentStore::from(documents),
+        )
+    }
+}
+
+/// Format versions 1 and 2 of sled storage: everything but the terms, postings and documents
+/// under the index's meta key.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct IndexMetaV2 {
+    pub total_documents: usize,
+    pub doc_values: DocValues,
+    pub doc_lengths: HashMap<Uuid, usize>,
+    pub total_tokens: usize,
+    pub settings: IndexSettings,
+    pub link_graph: LinkGraph,
+    pub authority: HashMap<Uuid, f64>,
+    pub anchors: AnchorIndex,
+    pub boosts: HashMap<Uuid, f64>,
+    pub vectors: VectorIndex,
+    pub duplicates: DuplicateIndex,
+    pub passages: PassageIndex,
+}
+
+impl From<IndexMetaV2> for IndexParts {
+    fn from(legacy: IndexMetaV2) -> Self {
+        IndexParts {
+            total_documents: legacy.total_documents,
+            doc_values: legacy.doc_values,
+            doc_lengths: legacy.doc_lengths.into(),
+            total_tokens: legacy.total_tokens,
+            settings: legacy.settings,
+            link_graph: legacy.link_graph,
+            authority: legacy.authority.into(),
+            anchors: legacy.anchors,
+            boosts: legacy.boosts.into(),
+            vectors: legacy.vectors,
+            duplicates: legacy.duplicates,
+            passages: legacy.passages,
+        }
+    }
+}
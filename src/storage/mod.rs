@@ -1,14 +1,48 @@
+pub mod format;
 pub mod json_storage;
+mod legacy;
 pub mod sled_storage;
 
-use crate::core::InvertedIndex;
+use crate::core::{DocumentSource, InvertedIndex, PostingSource};
 use anyhow::Result;
+use std::sync::Arc;
 
 pub trait Storage: Send + Sync {
     fn save_index(&self, index: &InvertedIndex) -> Result<()>;
     fn load_index(&self) -> Result<Option<InvertedIndex>>;
     fn clear(&self) -> Result<()>;
+
+    /// Format version of the stored index, `None` when nothing is stored. Fails for versions
+    /// newer than this build can read.
+    fn format_version(&self) -> Result<Option<u32>>;
+
+    /// Upgrades the stored index to the current format version and returns the version it
+    /// had, `None` when nothing is stored. Loading an index upgrades it as well.
+    fn migrate(&self) -> Result<Option<u32>>;
+
+    /// Saves `index` when the storage already holds it as of change log `revision`. Storages
+    /// that can write only what changed since then override this; the default saves
+    /// everything.
+    fn save_changes(&self, index: &InvertedIndex, revision: u64) -> Result<()> {
+        let _ = revision;
+        self.save_index(index)
+    }
+
+    /// Where an index loaded from this storage reads the postings and documents it does not
+    /// hold in memory, so saved ones can be released. `None` for storages whose indexes are
+    /// held in memory in full.
+    fn sources(&self) -> Option<IndexSources> {
+        None
+    }
+}
+
+/// Reads the postings and documents a storage holds for its index.
+#[derive(Clone)]
+pub struct IndexSources {
+    pub postings: Arc<dyn PostingSource>,
+    pub documents: Arc<dyn DocumentSource>,
 }
 
+pub use format::{FORMAT_VERSION, FormatHeader};
 pub use json_storage::JsonStorage;
 pub use sled_storage::SledStorage;
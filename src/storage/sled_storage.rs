@@ -1,16 +1,108 @@
-use crate::core::InvertedIndex;
-use crate::storage::Storage;
-use anyhow::{Context, Result};
-use sled::{Db, Tree};
+use crate::core::{
+    Document, DocumentPassages, DocumentSource, DocumentStore, DuplicateEntry, IndexMeta,
+    IndexParts, InvertedIndex, Link, PostingList, PostingSource, PostingStore, VectorIndex,
+    VectorNode,
+};
+use crate::storage::format::{self, FormatHeader, Migration};
+use crate::storage::legacy::{IndexMetaV2, IndexV0};
+use crate::storage::{IndexSources, Storage};
+use anyhow::{Context, Result, anyhow};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use sled::transaction::{ConflictableTransactionResult, Transactional};
+use sled::{Batch, Db, IVec, Tree};
 use std::path::Path;
+use std::sync::Arc;
+use uuid::Uuid;
 
+/// Stores an index under many small keys: its terms with their document frequencies, the
+/// postings of each term, each document and each kind of per-document data live in trees of
+/// their own, keyed by term or document id, the outlinks and anchor text of pages in trees
+/// keyed by URL, and the counters and settings under one key of the index's tree. Saves
+/// after the first only write the keys of changed terms, documents and pages, and documents
+/// are read from the database when they are first needed.
 pub struct SledStorage {
-    #[allow(dead_code)]
     db: Db,
     tree: Tree,
+    terms: Tree,
+    postings: Tree,
+    documents: Tree,
+    doc_values: Tree,
+    lengths: Tree,
+    boosts: Tree,
+    authority: Tree,
+    /// Vector node of each document with an embedding
+    vectors: Tree,
+    /// `DuplicateEntry` of each fingerprinted document
+    signatures: Tree,
+    /// `DocumentPassages` of each split document
+    passages: Tree,
+    /// Links found on each crawled page, by page URL
+    outlinks: Tree,
+    /// Anchor text terms of the links to each page, by target URL
+    anchors: Tree,
+}
+
+/// Changes to the trees of an index, applied together by `SledStorage::write`.
+#[derive(Default)]
+struct Writes {
+    terms: Batch,
+    postings: Batch,
+    documents: Batch,
+    doc_values: Batch,
+    lengths: Batch,
+    boosts: Batch,
+    authority: Batch,
+    vectors: Batch,
+    signatures: Batch,
+    passages: Batch,
+    outlinks: Batch,
+    anchors: Batch,
 }
 
 impl SledStorage {
+    const META_KEY: &'static str = "meta";
+    /// Key of the `FormatHeader`, as JSON so any later version can read it
+    const FORMAT_KEY: &'static str = "format";
+    /// Key of the single serialized index of format version 0
+    const LEGACY_KEY: &'static str = "index";
+    /// Prefixes of the names of the trees next to the index's tree, in the order of `trees`
+    const TREE_PREFIXES: [&'static str; 12] = [
+        "terms",
+        "postings",
+        "documents",
+        "doc_values",
+        "lengths",
+        "boosts",
+        "authority",
+        "vectors",
+        "signatures",
+        "passages",
+        "outlinks",
+        "anchors",
+    ];
+
+    const MIGRATIONS: &'static [Migration<SledStorage>] = &[
+        Migration {
+            from: 0,
+            to: 3,
+            description: "split the index into terms, postings, documents and their data",
+            apply: Self::split_legacy,
+        },
+        Migration {
+            from: 1,
+            to: 3,
+            description: "move per-document and per-page data out of the meta key",
+            apply: Self::split_meta,
+        },
+        Migration {
+            from: 2,
+            to: 3,
+            description: "move per-document and per-page data out of the meta key",
+            apply: Self::split_meta,
+        },
+    ];
+
     pub fn new(db_path: &str) -> Result<Self> {
         if let Some(parent) = Path::new(db_path).parent() {
             std::fs::create_dir_all(parent)
@@ -20,48 +112,764 @@ impl SledStorage {
         let db = sled::open(db_path)
             .with_context(|| format!("Failed to open sled database at: {}", db_path))?;
 
-        let tree = db
-            .open_tree("inverted_index")
-            .context("Failed to open inverted_index tree")?;
+        Self::with_tree(db, "inverted_index")
+    }
+
+    /// Stores the index in the tree `tree_name` of an open database and the trees named
+    /// after it, so several indexes can share one database.
+    pub fn with_tree(db: Db, tree_name: &str) -> Result<Self> {
+        let [
+            tree,
+            terms,
+            postings,
+            documents,
+            doc_values,
+            lengths,
+            boosts,
+            authority,
+            vectors,
+            signatures,
+            passages,
+            outlinks,
+            anchors,
+        ] = Self::tree_names(tree_name).map(|name| {
+            db.open_tree(&name)
+                .with_context(|| format!("Failed to open {} tree", name))
+        });
 
-        Ok(Self { db, tree })
+        Ok(Self {
+            tree: tree?,
+            terms: terms?,
+            postings: postings?,
+            documents: documents?,
+            doc_values: doc_values?,
+            lengths: lengths?,
+            boosts: boosts?,
+            authority: authority?,
+            vectors: vectors?,
+            signatures: signatures?,
+            passages: passages?,
+            outlinks: outlinks?,
+            anchors: anchors?,
+            db,
+        })
     }
-}
 
-impl Storage for SledStorage {
-    fn save_index(&self, index: &InvertedIndex) -> Result<()> {
-        let serialized = bincode::serialize(index).context("Failed to serialize index")?;
+    /// Deletes every tree `with_tree` stores an index in.
+    pub fn drop_trees(db: &Db, tree_name: &str) -> Result<()> {
+        for name in Self::tree_names(tree_name) {
+            db.drop_tree(&name)
+                .with_context(|| format!("Failed to drop {} tree", name))?;
+        }
+        Ok(())
+    }
 
-        self.tree
-            .insert("index", serialized)
-            .context("Failed to insert index into database")?;
+    fn tree_names(tree_name: &str) -> [String; 13] {
+        let mut names = [const { String::new() }; 13];
+        names[0] = tree_name.to_string();
+        for (name, prefix) in names[1..].iter_mut().zip(Self::TREE_PREFIXES) {
+            *name = format!("{}:{}", prefix, tree_name);
+        }
+        names
+    }
 
-        self.tree.flush().context("Failed to flush database")?;
+    /// The index's tree followed by the others in the order of `TREE_PREFIXES`.
+    fn trees(&self) -> [&Tree; 13] {
+        [
+            &self.tree,
+            &self.terms,
+            &self.postings,
+            &self.documents,
+            &self.doc_values,
+            &self.lengths,
+            &self.boosts,
+            &self.authority,
+            &self.vectors,
+            &self.signatures,
+            &self.passages,
+            &self.outlinks,
+            &self.anchors,
+        ]
+    }
 
+    /// Queues writing `value` under `key`, or removing the key when there is none.
+    fn put<T: Serialize + ?Sized>(batch: &mut Batch, key: &[u8], value: Option<&T>) -> Result<()> {
+        match value {
+            Some(value) => batch.insert(
+                key,
+                bincode::serialize(value).context("Failed to serialize index data")?,
+            ),
+            None => batch.remove(key),
+        }
         Ok(())
     }
 
-    fn load_index(&self) -> Result<Option<InvertedIndex>> {
-        match self
+    fn put_term(writes: &mut Writes, term: &str, posting_list: &PostingList) -> Result<()> {
+        let df = posting_list.document_frequency as u64;
+        writes.terms.insert(term.as_bytes(), &df.to_be_bytes()[..]);
+        writes.postings.insert(
+            term.as_bytes(),
+            bincode::serialize(&posting_list.term_frequency)
+                .context("Failed to serialize postings")?,
+        );
+        Ok(())
+    }
+
+    fn put_document(writes: &mut Writes, document: &Document) -> Result<()> {
+        writes.documents.insert(
+            document.id.as_bytes(),
+            bincode::serialize(document).context("Failed to serialize document")?,
+        );
+        Ok(())
+    }
+
+    /// Queues the data kept about a document next to it, or its removal for documents no
+    /// longer indexed.
+    fn put_document_data(writes: &mut Writes, index: &InvertedIndex, doc_id: &Uuid) -> Result<()> {
+        let key = doc_id.as_bytes();
+        let doc_values = index.doc_values.document(doc_id);
+        let doc_values = (!doc_values.is_empty()).then_some(&doc_values);
+        Self::put(&mut writes.doc_values, key, doc_values)?;
+        Self::put(&mut writes.lengths, key, index.doc_lengths.get(doc_id))?;
+        Self::put(&mut writes.boosts, key, index.boosts.get(doc_id))?;
+        Self::put(
+            &mut writes.passages,
+            key,
+            index.passage_entry(doc_id)?.as_ref(),
+        )?;
+        Self::put_links(writes, index, doc_id)
+    }
+
+    /// Queues the data of a document that changes with other documents and the link graph:
+    /// its vector node, duplicate entry and authority.
+    fn put_links(writes: &mut Writes, index: &InvertedIndex, doc_id: &Uuid) -> Result<()> {
+        let key = doc_id.as_bytes();
+        Self::put(&mut writes.vectors, key, index.vectors.node(doc_id))?;
+        Self::put(
+            &mut writes.signatures,
+            key,
+            index.duplicates.entry(doc_id).as_ref(),
+        )?;
+        Self::put(&mut writes.authority, key, index.authority.get(doc_id))
+    }
+
+    /// Queues the outlinks and anchor text of a page.
+    fn put_page(writes: &mut Writes, index: &InvertedIndex, page: &str) -> Result<()> {
+        let key = page.as_bytes();
+        let outlinks = index
+            .link_graph
+            .contains(page)
+            .then(|| index.link_graph.outlinks(page));
+        Self::put(&mut writes.outlinks, key, outlinks)?;
+        Self::put(&mut writes.anchors, key, index.anchors.target(page))
+    }
+
+    /// Queues removing every key of the per-document and per-page trees and writing those of
+    /// `index` instead.
+    fn rewrite_data(&self, writes: &mut Writes, index: &InvertedIndex) -> Result<()> {
+        let data = [
+            (&self.doc_values, &mut writes.doc_values),
+            (&self.lengths, &mut writes.lengths),
+            (&self.boosts, &mut writes.boosts),
+            (&self.authority, &mut writes.authority),
+            (&self.vectors, &mut writes.vectors),
+            (&self.signatures, &mut writes.signatures),
+            (&self.passages, &mut writes.passages),
+            (&self.outlinks, &mut writes.outlinks),
+            (&self.anchors, &mut writes.anchors),
+        ];
+        for (tree, batch) in data {
+            for key in tree.iter().keys() {
+                batch.remove(key.context("Failed to read index data from database")?);
+            }
+        }
+
+        for doc_id in index.documents.ids() {
+            Self::put_document_data(writes, index, doc_id)?;
+        }
+        for (page, _) in index.link_graph.iter() {
+            Self::put_page(writes, index, page)?;
+        }
+        for target in index.anchors.targets() {
+            Self::put_page(writes, index, target)?;
+        }
+        Ok(())
+    }
+
+    /// Applies the writes together with the counters and settings in one transaction.
+    fn write(&self, index: &InvertedIndex, writes: Writes) -> Result<()> {
+        let mut meta = Batch::default();
+        meta.insert(
+            Self::META_KEY,
+            bincode::serialize(&index.meta()).context("Failed to serialize index")?,
+        );
+        meta.insert(Self::FORMAT_KEY, Self::format_header()?);
+        meta.remove(Self::LEGACY_KEY);
+
+        let batches = [
+            meta,
+            writes.terms,
+            writes.postings,
+            writes.documents,
+            writes.doc_values,
+            writes.lengths,
+            writes.boosts,
+            writes.authority,
+            writes.vectors,
+            writes.signatures,
+            writes.passages,
+            writes.outlinks,
+            writes.anchors,
+        ];
+        self.trees()[..]
+            .transaction(|trees| -> ConflictableTransactionResult<()> {
+                for (tree, batch) in trees.iter().zip(&batches) {
+                    tree.apply_batch(batch)?;
+                }
+                Ok(())
+            })
+            .map_err(|e| anyhow!("Failed to write index to database: {:?}", e))?;
+
+        self.db.flush().context("Failed to flush database")?;
+        Ok(())
+    }
+
+    /// Every term with its document frequency; postings are loaded when they are needed.
+    fn load_terms(&self) -> Result<Vec<(String, usize)>> {
+        let mut terms = Vec::new();
+        for entry in self.terms.iter() {
+            let (term, df) = entry.context("Failed to read terms from database")?;
+            let term = String::from_utf8_lossy(&term).into_owned();
+            let Ok(df) = <[u8; 8]>::try_from(&df[..]) else {
+                log::warn!("Skipping term '{}' with a corrupt document frequency", term);
+                continue;
+            };
+            terms.push((term, u64::from_be_bytes(df) as usize));
+        }
+        Ok(terms)
+    }
+
+    /// Every key of a per-document or per-page tree with its value.
+    fn load_entries<T: DeserializeOwned>(tree: &Tree) -> Result<Vec<(IVec, T)>> {
+        tree.iter()
+            .map(|entry| {
+                let (key, value) = entry.context("Failed to read index data from database")?;
+                let value = bincode::deserialize(&value).with_context(|| {
+                    format!(
+                        "Failed to deserialize {}",
+                        String::from_utf8_lossy(&tree.name())
+                    )
+                })?;
+                Ok((key, value))
+            })
+            .collect()
+    }
+
+    fn load_documents_data<T: DeserializeOwned>(tree: &Tree) -> Result<Vec<(Uuid, T)>> {
+        Self::load_entries(tree)?
+            .into_iter()
+            .map(|(key, value)| {
+                Ok((
+                    Uuid::from_slice(&key).context("Invalid document key")?,
+                    value,
+                ))
+            })
+            .collect()
+    }
+
+    fn load_pages_data<T: DeserializeOwned>(tree: &Tree) -> Result<Vec<(String, T)>> {
+        Ok(Self::load_entries(tree)?
+            .into_iter()
+            .map(|(key, value)| (String::from_utf8_lossy(&key).into_owned(), value))
+            .collect())
+    }
+
+    /// Reads the per-document and per-page trees into the parts of an index with the
+    /// counters and settings of `meta`.
+    fn load_parts(&self, meta: IndexMeta<'_>) -> Result<IndexParts> {
+        let mut parts = IndexParts {
+            total_documents: meta.total_documents,
+            total_tokens: meta.total_tokens,
+            settings: meta.settings.into_owned(),
+            doc_lengths: Self::load_documents_data(&self.lengths)?
+                .into_iter()
+                .collect(),
+            boosts: Self::load_documents_data(&self.boosts)?
+                .into_iter()
+                .collect(),
+            authority: Self::load_documents_data(&self.authority)?
+                .into_iter()
+                .collect(),
+            vectors: VectorIndex::from_nodes(
+                meta.vector_dimensions,
+                meta.vector_entry_point,
+                Self::load_documents_data::<VectorNode>(&self.vectors)?
+                    .into_iter()
+                    .collect(),
+            ),
+            ..IndexParts::default()
+        };
+        for (doc_id, fields) in Self::load_documents_data(&self.doc_values)? {
+            parts.doc_values.insert_values(doc_id, fields);
+        }
+        for (doc_id, entry) in Self::load_documents_data::<DuplicateEntry>(&self.signatures)? {
+            parts.duplicates.insert_entry(doc_id, entry);
+        }
+        for (doc_id, entry) in Self::load_documents_data::<DocumentPassages>(&self.passages)? {
+            parts.passages.insert_entry(doc_id, entry);
+        }
+        for (page, links) in Self::load_pages_data::<Vec<Link>>(&self.outlinks)? {
+            parts.link_graph.set_outlinks(page, links);
+        }
+        for (target, terms) in Self::load_pages_data(&self.anchors)? {
+            parts.anchors.insert_target(target, terms);
+        }
+        Ok(parts)
+    }
+
+    /// An index of `parts` reading its postings and documents from the database.
+    fn assemble(&self, parts: IndexParts) -> Result<InvertedIndex> {
+        let terms = self.load_terms()?;
+        let doc_ids = self
+            .documents
+            .iter()
+            .keys()
+            .map(|key| {
+                let key = key.context("Failed to read documents from database")?;
+                Uuid::from_slice(&key).context("Invalid document key")
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let sources = self
+            .sources()
+            .expect("sled storage reads indexes on demand");
+        Ok(InvertedIndex::from_parts(
+            parts,
+            PostingStore::with_source(terms, sources.postings),
+            DocumentStore::with_source(doc_ids, sources.documents),
+        ))
+    }
+
+    fn meta(&self) -> Result<IVec> {
+        self.tree
+            .get(Self::META_KEY)
+            .context("Failed to get index from database")?
+            .ok_or_else(|| anyhow!("Index metadata is missing"))
+    }
+
+    fn format_header() -> Result<Vec<u8>> {
+        serde_json::to_vec(&FormatHeader::current()).context("Failed to serialize format header")
+    }
+
+    /// Stores an index saved as a single value in the per-key layout.
+    fn split_legacy(&self) -> Result<()> {
+        let data = self
             .tree
-            .get("index")
+            .get(Self::LEGACY_KEY)
             .context("Failed to get index from database")?
-        {
-            Some(data) => {
-                let index = bincode::deserialize(&data).context("Failed to deserialize index")?;
-                Ok(Some(index))
+            .ok_or_else(|| anyhow!("Index of format version 0 is missing"))?;
+        let legacy: IndexV0 = bincode::deserialize(&data).context("Failed to deserialize index")?;
+        self.save_index(&InvertedIndex::from(legacy))
+    }
+
+    /// Moves the per-document and per-page data of an index stored with all of it under the
+    /// meta key into trees of their own. Terms, postings and documents stay as they are.
+    fn split_meta(&self) -> Result<()> {
+        let legacy: IndexMetaV2 =
+            bincode::deserialize(&self.meta()?).context("Failed to deserialize index")?;
+        let index = self.assemble(legacy.into())?;
+        let mut writes = Writes::default();
+        self.rewrite_data(&mut writes, &index)?;
+        self.write(&index, writes)
+    }
+}
+
+impl Storage for SledStorage {
+    fn save_index(&self, index: &InvertedIndex) -> Result<()> {
+        let mut writes = Writes::default();
+
+        // Remove whatever the previous save left behind, then write everything
+        for term in self.terms.iter().keys() {
+            let term = term.context("Failed to read terms from database")?;
+            writes.terms.remove(term.clone());
+            writes.postings.remove(term);
+        }
+        for doc_id in self.documents.iter().keys() {
+            writes
+                .documents
+                .remove(doc_id.context("Failed to read documents from database")?);
+        }
+
+        for entry in index.index.iter() {
+            let (term, posting_list) = entry?;
+            Self::put_term(&mut writes, term, &posting_list)?;
+        }
+        for document in index.documents.try_values() {
+            Self::put_document(&mut writes, &*document?)?;
+        }
+        self.rewrite_data(&mut writes, index)?;
+
+        self.write(index, writes)
+    }
+
+    fn save_changes(&self, index: &InvertedIndex, revision: u64) -> Result<()> {
+        let mut writes = Writes::default();
+
+        for term in index.changes.terms_since(revision) {
+            match index.index.try_get(term)? {
+                Some(posting_list) => Self::put_term(&mut writes, term, &posting_list)?,
+                None => {
+                    writes.terms.remove(term.as_bytes());
+                    writes.postings.remove(term.as_bytes());
+                }
             }
-            None => Ok(None),
         }
+        for doc_id in index.changes.documents_since(revision) {
+            match index.documents.try_get(doc_id)? {
+                Some(document) => Self::put_document(&mut writes, &document)?,
+                None => writes.documents.remove(doc_id.as_bytes()),
+            }
+            Self::put_document_data(&mut writes, index, doc_id)?;
+        }
+        for doc_id in index.changes.links_since(revision) {
+            Self::put_links(&mut writes, index, doc_id)?;
+        }
+        for page in index.changes.pages_since(revision) {
+            Self::put_page(&mut writes, index, page)?;
+        }
+
+        self.write(index, writes)
+    }
+
+    fn sources(&self) -> Option<IndexSources> {
+        Some(IndexSources {
+            postings: Arc::new(SledPostings(self.postings.clone())),
+            documents: Arc::new(SledDocuments(self.documents.clone())),
+        })
+    }
+
+    fn load_index(&self) -> Result<Option<InvertedIndex>> {
+        if self.migrate()?.is_none() {
+            return Ok(None);
+        }
+        let meta = self.meta()?;
+        let meta: IndexMeta = bincode::deserialize(&meta).context("Failed to deserialize index")?;
+        let parts = self.load_parts(meta)?;
+        self.assemble(parts).map(Some)
+    }
+
+    fn format_version(&self) -> Result<Option<u32>> {
+        let get = |key| {
+            self.tree
+                .get(key)
+                .context("Failed to get index from database")
+        };
+        if let Some(header) = get(Self::FORMAT_KEY)? {
+            let header: FormatHeader =
+                serde_json::from_slice(&header).context("Invalid format header")?;
+            return header.version().map(Some);
+        }
+        if get(Self::META_KEY)?.is_some() {
+            Ok(Some(1))
+        } else if get(Self::LEGACY_KEY)?.is_some() {
+            Ok(Some(0))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn migrate(&self) -> Result<Option<u32>> {
+        let Some(version) = self.format_version()? else {
+            return Ok(None);
+        };
+        format::migrate(self, version, Self::MIGRATIONS)?;
+        Ok(Some(version))
     }
 
     fn clear(&self) -> Result<()> {
-        self.tree.clear().context("Failed to clear database")?;
+        for tree in self.trees() {
+            tree.clear().context("Failed to clear database")?;
+        }
 
-        self.tree
+        self.db
             .flush()
             .context("Failed to flush database after clear")?;
 
         Ok(())
     }
 }
+
+/// Reads documents of a loaded index from its documents tree.
+struct SledDocuments(Tree);
+
+impl DocumentSource for SledDocuments {
+    fn load_document(&self, doc_id: &Uuid) -> Result<Option<Document>> {
+        match self
+            .0
+            .get(doc_id.as_bytes())
+            .context("Failed to read document from database")?
+        {
+            Some(data) => Ok(Some(
+                bincode::deserialize(&data).context("Failed to deserialize document")?,
+            )),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Reads postings of a loaded index from its postings tree.
+struct SledPostings(Tree);
+
+impl PostingSource for SledPostings {
+    fn load_postings(&self, term: &str) -> Result<Option<imbl::HashMap<Uuid, usize>>> {
+        match self
+            .0
+            .get(term.as_bytes())
+            .context("Failed to read postings from database")?
+        {
+            Some(data) => Ok(Some(
+                bincode::deserialize(&data).context("Failed to deserialize postings")?,
+            )),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{IndexSettings, LinkGraph};
+    use crate::storage::FORMAT_VERSION;
+    use crate::storage::legacy::IndexMetaV2;
+
+    const BASELINE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/examples/search_index.json");
+
+    #[test]
+    fn migrates_single_value_index() {
+        let legacy: IndexV0 = serde_json::from_slice(&std::fs::read(BASELINE).unwrap()).unwrap();
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let storage = SledStorage::with_tree(db, "legacy").unwrap();
+        storage
+            .tree
+            .insert(
+                SledStorage::LEGACY_KEY,
+                bincode::serialize(&legacy).unwrap(),
+            )
+            .unwrap();
+        assert_eq!(storage.format_version().unwrap(), Some(0));
+
+        let index = storage.load_index().unwrap().unwrap();
+        assert_eq!(index.total_documents, legacy.total_documents);
+        assert_eq!(index.index.len(), legacy.index.len());
+        for doc_id in legacy.documents.keys() {
+            assert_eq!(
+                index.get_document(doc_id).map(|doc| doc.title.clone()),
+                legacy.documents.get(doc_id).map(|doc| doc.title.clone())
+            );
+        }
+        assert_eq!(storage.format_version().unwrap(), Some(FORMAT_VERSION));
+        assert!(storage.tree.get(SledStorage::LEGACY_KEY).unwrap().is_none());
+    }
+
+    #[test]
+    fn saves_and_loads_index() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let storage = SledStorage::with_tree(db, "index").unwrap();
+        let mut index = InvertedIndex::new();
+        index
+            .add_document(Document::new(
+                "Rust".to_string(),
+                "Ownership and borrowing".to_string(),
+                "rust.md".to_string(),
+            ))
+            .unwrap();
+        storage.save_index(&index).unwrap();
+
+        let loaded = storage.load_index().unwrap().unwrap();
+        assert_eq!(loaded.total_documents, 1);
+        assert_eq!(loaded.total_tokens, index.total_tokens);
+        assert_eq!(loaded.search("borrowing", None).len(), 1);
+    }
+
+    #[test]
+    fn keeps_document_and_page_data_under_keys_of_their_own() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let storage = SledStorage::with_tree(db, "index").unwrap();
+        let mut settings = IndexSettings::default();
+        settings.passages.min_words = 10;
+        settings.passages.window = 6;
+        settings.passages.overlap = 2;
+        let mut index = InvertedIndex::with_settings(settings);
+
+        let mut first = Document::new(
+            "Rust".to_string(),
+            "Ownership and borrowing let the Rust language keep memory safe without a \
+             garbage collector or manual frees"
+                .to_string(),
+            "https://example.com/rust".to_string(),
+        );
+        first.boost = 2.0;
+        first.embedding = Some(vec![1.0, 0.0]);
+        let mut copy = first.clone();
+        copy.id = Uuid::new_v4();
+        copy.path = "https://example.com/copy".to_string();
+        copy.embedding = Some(vec![0.9, 0.1]);
+        index
+            .add_documents(vec![first.clone(), copy.clone()])
+            .unwrap();
+        assert_eq!(index.duplicates.duplicates_of(&first.id), [copy.id]);
+        storage.save_index(&index).unwrap();
+        let saved = index.changes.revision();
+
+        let meta = storage.tree.get(SledStorage::META_KEY).unwrap().unwrap();
+        let meta: IndexMeta = bincode::deserialize(&meta).unwrap();
+        assert_eq!(meta.total_documents, 2);
+        assert_eq!(meta.vector_dimensions, 2);
+        for tree in [
+            &storage.doc_values,
+            &storage.lengths,
+            &storage.boosts,
+            &storage.vectors,
+            &storage.signatures,
+            &storage.passages,
+        ] {
+            assert_eq!(tree.len(), 2);
+        }
+
+        // Only what changed is written: the links, the copy and what the copy was linked to
+        let mut graph = LinkGraph::new();
+        graph.set_outlinks(
+            copy.path.clone(),
+            vec![Link {
+                target: first.path.clone(),
+                anchor_text: "memory safety".to_string(),
+            }],
+        );
+        index.update_link_graph(graph);
+        index.remove_document(&copy.id).unwrap();
+        storage.save_changes(&index, saved).unwrap();
+
+        let loaded = storage.load_index().unwrap().unwrap();
+        assert_eq!(storage.lengths.len(), 1);
+        assert_eq!(loaded.doc_lengths, index.doc_lengths);
+        assert_eq!(loaded.boosts, index.boosts);
+        assert_eq!(loaded.authority, index.authority);
+        assert_eq!(
+            loaded.doc_values.document(&first.id),
+            index.doc_values.document(&first.id)
+        );
+        assert_eq!(
+            loaded.vectors.node(&first.id),
+            index.vectors.node(&first.id)
+        );
+        assert_eq!(loaded.vectors.entry_point(), Some(first.id));
+        assert_eq!(
+            loaded.duplicates.entry(&first.id),
+            index.duplicates.entry(&first.id)
+        );
+        assert!(loaded.duplicates.duplicates_of(&first.id).is_empty());
+        assert!(loaded.passage_entry(&first.id).unwrap().is_some());
+        assert_eq!(
+            loaded.passage_entry(&first.id).unwrap(),
+            index.passage_entry(&first.id).unwrap()
+        );
+        assert_eq!(
+            loaded.link_graph.outlinks(&copy.path),
+            index.link_graph.outlinks(&copy.path)
+        );
+        assert_eq!(
+            loaded.anchors.target(&first.path),
+            index.anchors.target(&first.path)
+        );
+        assert_eq!(
+            loaded.search("memory safety", None),
+            index.search("memory safety", None)
+        );
+    }
+
+    #[test]
+    fn migrates_meta_of_format_version_2() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let storage = SledStorage::with_tree(db, "index").unwrap();
+        let mut index = InvertedIndex::new();
+        let mut document = Document::new(
+            "Rust".to_string(),
+            "Ownership and borrowing".to_string(),
+            "https://example.com/rust".to_string(),
+        );
+        document.embedding = Some(vec![1.0, 0.0]);
+        let doc_id = document.id;
+        index.add_document(document).unwrap();
+        storage.save_index(&index).unwrap();
+
+        let legacy = IndexMetaV2 {
+            total_documents: index.total_documents,
+            doc_values: index.doc_values.clone(),
+            doc_lengths: index.doc_lengths.clone().into_iter().collect(),
+            total_tokens: index.total_tokens,
+            settings: index.settings.clone(),
+            link_graph: index.link_graph.clone(),
+            authority: index.authority.clone().into_iter().collect(),
+            anchors: index.anchors.clone(),
+            boosts: index.boosts.clone().into_iter().collect(),
+            vectors: index.vectors.clone(),
+            duplicates: index.duplicates.clone(),
+            passages: index.passages.clone(),
+        };
+        for tree in &storage.trees()[4..] {
+            tree.clear().unwrap();
+        }
+        let mut header = FormatHeader::current();
+        header.version = 2;
+        storage
+            .tree
+            .insert(
+                SledStorage::FORMAT_KEY,
+                serde_json::to_vec(&header).unwrap(),
+            )
+            .unwrap();
+        storage
+            .tree
+            .insert(SledStorage::META_KEY, bincode::serialize(&legacy).unwrap())
+            .unwrap();
+        assert_eq!(storage.format_version().unwrap(), Some(2));
+
+        let loaded = storage.load_index().unwrap().unwrap();
+        assert_eq!(storage.format_version().unwrap(), Some(FORMAT_VERSION));
+        assert_eq!(storage.lengths.len(), 1);
+        assert_eq!(loaded.doc_lengths, index.doc_lengths);
+        assert_eq!(
+            loaded.doc_values.document(&doc_id),
+            index.doc_values.document(&doc_id)
+        );
+        assert_eq!(loaded.vectors.node(&doc_id), index.vectors.node(&doc_id));
+        assert_eq!(
+            loaded.search("borrowing", None),
+            index.search("borrowing", None)
+        );
+    }
+
+    #[test]
+    fn keeps_index_when_a_document_cannot_be_loaded() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let storage = SledStorage::with_tree(db, "index").unwrap();
+        let mut index = InvertedIndex::new();
+        let document = Document::new(
+            "Rust".to_string(),
+            "Ownership and borrowing".to_string(),
+            "rust.md".to_string(),
+        );
+        let doc_id = document.id;
+        index.add_document(document).unwrap();
+        storage.save_index(&index).unwrap();
+        storage
+            .documents
+            .insert(doc_id.as_bytes(), b"corrupt".to_vec())
+            .unwrap();
+
+        let mut loaded = storage.load_index().unwrap().unwrap();
+        assert!(loaded.remove_document(&doc_id).is_err());
+        assert_eq!(loaded.total_documents, 1);
+        assert!(loaded.documents.contains(&doc_id));
+        assert_eq!(loaded.search("borrowing", None).len(), 1);
+    }
+}
@@ -165,6 +165,71 @@ pub const SEARCH_PAGE_HTML: &str = r#"<!DOCTYPE html>
             font-weight: 600;
         }
 
+        .related {
+            margin-top: 10px;
+            font-size: 0.9rem;
+            color: #666;
+        }
+
+        .related a {
+            color: #667eea;
+            text-decoration: none;
+            margin-right: 12px;
+            cursor: pointer;
+        }
+
+        .related a:hover {
+            text-decoration: underline;
+        }
+
+        .facets {
+            display: flex;
+            flex-wrap: wrap;
+            gap: 15px;
+            margin-bottom: 20px;
+        }
+
+        .facet-group {
+            background: rgba(255, 255, 255, 0.15);
+            border-radius: 12px;
+            padding: 10px 15px;
+            color: white;
+            font-size: 0.85rem;
+        }
+
+        .facet-group h5 {
+            text-transform: uppercase;
+            letter-spacing: 0.05em;
+            margin-bottom: 6px;
+            color: rgba(255, 255, 255, 0.8);
+        }
+
+        .facet-value {
+            display: block;
+            cursor: pointer;
+            padding: 2px 0;
+        }
+
+        .facet-value:hover {
+            text-decoration: underline;
+        }
+
+        .active-filters {
+            text-align: center;
+            margin-bottom: 15px;
+        }
+
+        .filter-chip {
+            display: inline-block;
+            margin: 3px 5px;
+            padding: 4px 12px;
+            background: white;
+            color: #667eea;
+            border-radius: 20px;
+            cursor: pointer;
+            font-size: 0.85rem;
+        }
+
         .loading {
             text-align: center;
             padding: 40px;
@@ -249,6 +314,8 @@ pub const SEARCH_PAGE_HTML: &str = r#"<!DOCTYPE html>
         </div>
 
         <div id="stats" class="stats" style="display: none;"></div>
+        <div id="activeFilters" class="active-filters"></div>
+        <div id="facets" class="facets" style="display: none;"></div>
         <div id="results" class="results" style="display: none;"></div>
         <div id="loading" class="loading" style="display: none;">
             <p>🔄 Searching...</p>
@@ -269,6 +336,10 @@ pub const SEARCH_PAGE_HTML: &str = r#"<!DOCTYPE html>
         const resultsDiv = document.getElementById('results');
         const statsDiv = document.getElementById('stats');
         const loadingDiv = document.getElementById('loading');
+        const facetsDiv = document.getElementById('facets');
+        const activeFiltersDiv = document.getElementById('activeFilters');
+        const FACET_FIELDS = ['tag', 'domain', 'extension', 'year'];
+        let activeFilters = [];
 
         // Search on Enter key
         searchInput.addEventListener('keypress', function(e) {
@@ -285,7 +356,19 @@ pub const SEARCH_PAGE_HTML: &str = r#"<!DOCTYPE html>
             showLoading();
 
             try {
-                const response = await fetch(`/search?q=${encodeURIComponent(query)}&limit=10`);
+                let url = `/search?q=${encodeURIComponent(query)}&limit=10&facets=${FACET_FIELDS.join(',')}&collapse_duplicates=true`;
+                if (activeFilters.length > 0) {
+                    url += `&filter=${encodeURIComponent(activeFilters.join(','))}`;
+                }
+                // At most two hits per site, unless the search is already narrowed to one
+                if (!activeFilters.some(filter => filter.startsWith('domain:'))) {
+                    url += '&collapse=host:2';
+                }
+                let response = await fetch(url);
+                // Memory-mapped indexes only rank text; search them without facets and grouping
+                if (response.status === 400) {
+                    response = await fetch(`/search?q=${encodeURIComponent(query)}&limit=10`);
+                }
                 if (!response.ok) {
                     throw new Error(`HTTP error! status: ${response.status}`);
                 }
@@ -301,15 +384,18 @@ pub const SEARCH_PAGE_HTML: &str = r#"<!DOCTYPE html>
         function showLoading() {
             resultsDiv.style.display = 'none';
             statsDiv.style.display = 'none';
+            facetsDiv.style.display = 'none';
             loadingDiv.style.display = 'block';
         }
 
         function displayResults(data) {
             loadingDiv.style.display = 'none';
+            displayActiveFilters();
+            displayFacets(data.facets || []);
 
             if (data.results && data.results.length > 0) {
                 // Show stats
-                statsDiv.innerHTML = `Found ${data.total} result${data.total !== 1 ? 's' : ''} for "${data.query}"`;
+                statsDiv.innerHTML = `Found ${data.total_hits} result${data.total_hits !== 1 ? 's' : ''} for "${escapeHtml(data.query)}"`;
                 statsDiv.style.display = 'block';
 
                 // Show results
@@ -317,7 +403,7 @@ pub const SEARCH_PAGE_HTML: &str = r#"<!DOCTYPE html>
                 data.results.forEach((result, index) => {
                     const isUrl = result.path.startsWith('http://') || result.path.startsWith('https://');
                     const linkTarget = isUrl ? '_blank' : '_self';
-                    const linkHref = isUrl ? result.path : '#';
+                    const linkHref = isUrl ? result.url : '#';
 
                     resultsHTML += `
                         <div class="result-item">
@@ -325,14 +411,17 @@ pub const SEARCH_PAGE_HTML: &str = r#"<!DOCTYPE html>
                             <a href="${linkHref}" target="${linkTarget}" class="result-title">
                                 ${escapeHtml(result.title)}
                             </a>
-                            <div class="result-url">${escapeHtml(result.path)}</div>
+                            <div class="result-url">${escapeHtml(result.path)}${result.section ? ` › ${escapeHtml(result.section)}` : ''}${result.duplicates > 0 ? ` · ${result.duplicates} near-duplicate${result.duplicates !== 1 ? 's' : ''} hidden` : ''}</div>
                             <div class="result-snippet">${escapeHtml(result.snippet)}</div>
+                            ${result.more_in_group > 0 ? `<div class="related"><a data-filter="${escapeHtml(`domain:${result.group}`)}" onclick="addFilter(this.dataset.filter)">${result.more_in_group} more from ${escapeHtml(result.group)}</a></div>` : ''}
+                            <div class="related" id="related-${index}" data-id="${result.id}"></div>
                         </div>
                     `;
                 });
 
                 resultsDiv.innerHTML = resultsHTML;
                 resultsDiv.style.display = 'block';
+                data.results.forEach((result, index) => displayRelated(result.id, index));
             } else {
                 // No results
                 statsDiv.style.display = 'none';
@@ -346,6 +435,69 @@ pub const SEARCH_PAGE_HTML: &str = r#"<!DOCTYPE html>
             }
         }
 
+        async function displayRelated(id, index) {
+            try {
+                const response = await fetch(`/documents/${id}/similar?limit=3`);
+                if (!response.ok) return;
+                const data = await response.json();
+                if (data.results.length === 0) return;
+
+                const links = data.results.map(related => {
+                    const isUrl = related.path.startsWith('http://') || related.path.startsWith('https://');
+                    const href = isUrl ? related.path : '#';
+                    return `<a href="${escapeHtml(href)}" target="${isUrl ? '_blank' : '_self'}">${escapeHtml(related.title)}</a>`;
+                }).join('');
+
+                const relatedDiv = document.getElementById(`related-${index}`);
+                // A newer search may have replaced the results in the meantime
+                if (relatedDiv && relatedDiv.dataset.id === id) {
+                    relatedDiv.innerHTML = `Related pages: ${links}`;
+                }
+            } catch (error) {
+                console.error('Failed to load related pages:', error);
+            }
+        }
+
+        function displayFacets(facets) {
+            let facetsHTML = '';
+            facets.forEach(facet => {
+                if (facet.values.length === 0) return;
+                facetsHTML += `<div class="facet-group"><h5>${escapeHtml(facet.field)}</h5>`;
+                facet.values.forEach(value => {
+                    const filter = `${facet.field}:${value.value}`;
+                    facetsHTML += `
+                        <span class="facet-value" data-filter="${escapeHtml(filter)}" onclick="addFilter(this.dataset.filter)">
+                            ${escapeHtml(value.value)} (${value.count})
+                        </span>
+                    `;
+                });
+                facetsHTML += '</div>';
+            });
+
+            facetsDiv.innerHTML = facetsHTML;
+            facetsDiv.style.display = facetsHTML ? 'flex' : 'none';
+        }
+
+        function displayActiveFilters() {
+            activeFiltersDiv.innerHTML = activeFilters.map(filter => `
+                <span class="filter-chip" data-filter="${escapeHtml(filter)}" onclick="removeFilter(this.dataset.filter)">
+                    ${escapeHtml(filter)} ✕
+                </span>
+            `).join('');
+        }
+
+        function addFilter(filter) {
+            if (!activeFilters.includes(filter)) {
+                activeFilters.push(filter);
+            }
+            performSearch();
+        }
+
+        function removeFilter(filter) {
+            activeFilters = activeFilters.filter(f => f !== filter);
+            performSearch();
+        }
+
         function showError(message) {
             loadingDiv.style.display = 'none';
             statsDiv.style.display = 'none';
@@ -360,6 +512,7 @@ pub const SEARCH_PAGE_HTML: &str = r#"<!DOCTYPE html>
 
         function searchExample(query) {
             searchInput.value = query;
+            activeFilters = [];
             performSearch();
         }
 